@@ -0,0 +1,1403 @@
+use log::{debug, warn};
+use serde_json::Value;
+use std::any::Any;
+use std::collections::HashSet;
+use std::future::Future;
+use std::io::IsTerminal;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+
+use crate::commands::{self, CommandContext, CommandOutcome};
+use crate::config::Config;
+use crate::diff::{self, FileDiff};
+use crate::file_tracker::FileTracker;
+use crate::ignore_rules::IgnoreRules;
+use crate::markdown;
+use crate::mentions;
+use crate::path_patterns::{self, PathPatterns};
+use crate::permissions::{
+    self, ApprovalDecision, ApprovalRequest, PermissionCheck, PermissionMode,
+};
+use crate::read_file::{self, BatchFile, ReadOptions};
+use crate::scan_directory::{self, ScanOptions};
+use crate::session::{self, Session, SessionRecord};
+use crate::tools::text_editor::{self, EditHistory};
+use crate::usage::TokenUsage;
+use crate::workspace::Workspace;
+use crate::workspace_index::WorkspaceIndex;
+use crate::{AnthropicClient, Message, MessageContent, ToolType};
+
+const EDIT_TOOL_NAME: &str = "str_replace_based_edit_tool";
+/// Recorded in the conversation when the user cancels a turn, so the model knows it was cut short
+const INTERRUPTED_MARKER: &str = "[Interrupted by user]";
+
+/// Something that happened during a turn, for the frontend to display.
+#[derive(Debug, Clone)]
+pub enum AgentEvent {
+    /// Final text reply from the model
+    AssistantText(String),
+    /// The model asked for a tool; `detail` is a short description of the input
+    ToolCall {
+        id: String,
+        name: String,
+        detail: String,
+    },
+    /// A tool finished; `content` is what gets sent back to the model
+    ToolResult {
+        id: String,
+        name: String,
+        success: bool,
+        content: String,
+    },
+    /// A file was modified, as a unified diff against its previous content
+    FileDiff { path: String, diff: String },
+    /// Output of a slash command or other informational text
+    Notice(String),
+    /// Model, token totals and permission mode, sent whenever any may have changed
+    Status {
+        model: String,
+        usage: TokenUsage,
+        permission_mode: PermissionMode,
+    },
+}
+
+/// Receives agent events. Implemented by the plain terminal output and the TUI.
+pub trait Frontend {
+    fn emit(&mut self, event: AgentEvent);
+
+    /// Shows a side-effecting tool call to the user and waits for their decision.
+    fn request_approval<'a>(&'a mut self, request: &'a ApprovalRequest) -> ApprovalFuture<'a>;
+}
+
+/// The user's answer to an approval request. Awaited, so an interrupt can cancel the turn while waiting.
+pub type ApprovalFuture<'a> = Pin<Box<dyn Future<Output = ApprovalDecision> + 'a>>;
+
+/// Owns the conversation and runs turns against the model.
+pub struct Agent {
+    pub client: AnthropicClient,
+    pub messages: Vec<Message>,
+    pub usage: TokenUsage,
+    pub working_dir: PathBuf,
+    pub permission_mode: PermissionMode,
+    pub config: Config,
+    /// Transcript the conversation is saved to, if a data directory is available
+    pub session: Option<Session>,
+    /// Cached project listing behind `scan_directory`, built on the first scan
+    index: Option<Arc<WorkspaceIndex>>,
+    /// Files the model has seen, checked for outside changes before each request
+    files: FileTracker,
+    /// Backups behind undo and the originals behind `/diff`
+    edits: EditHistory,
+}
+
+impl Agent {
+    pub fn new(client: AnthropicClient, working_dir: PathBuf, config: Config) -> Self {
+        Self {
+            client,
+            messages: Vec::new(),
+            usage: TokenUsage::default(),
+            permission_mode: config.permissions.mode,
+            config,
+            session: Session::new(&working_dir),
+            files: FileTracker::new(&working_dir),
+            working_dir,
+            index: None,
+            edits: EditHistory::new(),
+        }
+    }
+
+    /// The index for the current `ignore_patterns`, replaced when a profile switch changes them.
+    fn workspace_index(&mut self) -> Arc<WorkspaceIndex> {
+        let patterns = &self.config.ignore_patterns;
+        match &self.index {
+            Some(index) if index.patterns().patterns() == patterns.patterns() => Arc::clone(index),
+            _ => Arc::clone(
+                self.index
+                    .insert(Arc::new(WorkspaceIndex::new(&self.working_dir, patterns))),
+            ),
+        }
+    }
+
+    pub fn status(&self) -> AgentEvent {
+        AgentEvent::Status {
+            model: self.client.config.model_name.clone(),
+            usage: self.usage.clone(),
+            permission_mode: self.permission_mode,
+        }
+    }
+
+    /// Handles one line of user input: slash commands run locally, anything else starts a model turn.
+    pub async fn handle_input(
+        &mut self,
+        line: &str,
+        frontend: &mut dyn Frontend,
+    ) -> Result<CommandOutcome, Box<dyn std::error::Error>> {
+        if commands::is_command(line) {
+            let mut context = CommandContext {
+                messages: &mut self.messages,
+                client: &mut self.client,
+                usage: &self.usage,
+                permission_mode: &mut self.permission_mode,
+                session: &mut self.session,
+                config: &mut self.config,
+                working_dir: &self.working_dir,
+                edits: &mut self.edits,
+                reverted_files: Vec::new(),
+                diffs: Vec::new(),
+                output: String::new(),
+            };
+            let outcome = commands::dispatch(line, &mut context);
+            let (output, reverted_files, diffs) =
+                (context.output, context.reverted_files, context.diffs);
+            if !output.is_empty() {
+                frontend.emit(AgentEvent::Notice(output.trim_end().to_string()));
+            }
+            for diff in diffs {
+                frontend.emit(AgentEvent::FileDiff {
+                    path: diff.path,
+                    diff: diff.unified,
+                });
+            }
+            for diff in reverted_files {
+                self.record_undo(&diff, frontend);
+            }
+            self.save_session();
+            frontend.emit(self.status());
+            return outcome;
+        }
+
+        let expanded = mentions::expand_mentions(
+            line,
+            &self.working_dir,
+            self.config.max_read_bytes,
+            |path| self.resolve_path(path),
+        );
+        for error in expanded.errors {
+            frontend.emit(AgentEvent::Notice(error));
+        }
+        if !expanded.attached.is_empty() {
+            frontend.emit(AgentEvent::Notice(format!(
+                "Attached {}",
+                expanded.attached.join(", ")
+            )));
+        }
+        self.messages.push(Message {
+            role: "user".to_string(),
+            content: expanded.text.into(),
+        });
+        self.save_session();
+
+        // A panic anywhere in the turn is reported like any other error; the conversation so far is kept
+        let mut turn = Box::pin(self.run_turn(frontend));
+        let guarded = std::future::poll_fn(|cx| {
+            match std::panic::catch_unwind(AssertUnwindSafe(|| turn.as_mut().poll(cx))) {
+                Ok(Poll::Pending) => Poll::Pending,
+                Ok(Poll::Ready(result)) => Poll::Ready(Ok(result)),
+                Err(panic) => Poll::Ready(Err(panic)),
+            }
+        })
+        .await;
+        drop(turn);
+        match guarded {
+            Ok(result) => result?,
+            Err(panic) => {
+                self.save_session();
+                return Err(format!("internal error: {}", panic_message(panic.as_ref())).into());
+            }
+        }
+        Ok(CommandOutcome::Continue)
+    }
+
+    /// Brings the index, the file tracker and the transcript up to date with a file `/undo`
+    /// restored, and tells the model so it doesn't mistake the revert for an outside edit.
+    fn record_undo(&mut self, diff: &FileDiff, frontend: &mut dyn Frontend) {
+        if let Some(index) = &self.index {
+            index.invalidate(&self.working_dir.join(&diff.path));
+        }
+        self.files
+            .record(Path::new(&diff.path), None, self.config.max_read_bytes);
+        frontend.emit(AgentEvent::FileDiff {
+            path: diff.path.clone(),
+            diff: diff.unified.clone(),
+        });
+        self.record_in_session(SessionRecord::FileDiff {
+            path: diff.path.clone(),
+            diff: diff.unified.clone(),
+        });
+        self.messages.push(Message {
+            role: "user".to_string(),
+            content: format!(
+                "The user reverted your last edit to {} with /undo.\n\n{}",
+                diff.path,
+                diff.summary_for_model()
+            )
+            .into(),
+        });
+    }
+
+    /// Continues a saved session of this project (the latest when `id` is `None`), restoring its messages and model settings.
+    pub fn resume(&mut self, id: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
+        let description = session::resume_into(
+            &self.working_dir,
+            id,
+            &mut self.messages,
+            &mut self.client.config,
+            &mut self.session,
+        )?;
+        self.config
+            .set_model(&self.client.config.model_name, "--resume");
+        self.edits.clear();
+        Ok(description)
+    }
+
+    /// Records that the in-flight turn was cancelled. Call after dropping the `handle_input` future.
+    ///
+    /// Whatever the turn already added to the conversation (the user's message, finished tool results)
+    /// is kept, so the next turn carries on from there.
+    pub fn interrupt(&mut self, frontend: &mut dyn Frontend) {
+        self.messages.push(Message {
+            role: "user".to_string(),
+            content: INTERRUPTED_MARKER.to_string().into(),
+        });
+        frontend.emit(AgentEvent::Notice("Interrupted.".to_string()));
+        self.save_session();
+    }
+
+    /// Appends new messages and settings to the session transcript. Failures are logged, not fatal.
+    pub fn save_session(&mut self) {
+        if let Some(session) = &mut self.session {
+            if let Err(e) = session.sync(&self.messages, &self.client.config) {
+                warn!("Could not save the session transcript: {}", e);
+            }
+        }
+    }
+
+    /// Sends the conversation to the model, running tools until it replies with text.
+    async fn run_turn(
+        &mut self,
+        frontend: &mut dyn Frontend,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        loop {
+            if let Some(changes) = self.files.take_changes(self.config.max_read_bytes) {
+                frontend.emit(AgentEvent::Notice(format!(
+                    "Changed outside the agent: {}",
+                    changes.paths.join(", ")
+                )));
+                self.messages.push(Message {
+                    role: "user".to_string(),
+                    content: changes.message_for_model().into(),
+                });
+                self.save_session();
+            }
+            let response_json = self
+                .client
+                .send_request(self.messages.clone(), self.enabled_tools())
+                .await?;
+            self.usage.record(&response_json);
+            frontend.emit(self.status());
+
+            if response_json["stop_reason"].as_str() != Some("tool_use") {
+                let response_text = response_json["content"][0]["text"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string();
+                self.messages.push(Message {
+                    role: "assistant".to_string(),
+                    content: response_text.clone().into(),
+                });
+                self.save_session();
+                frontend.emit(AgentEvent::AssistantText(response_text));
+                return Ok(());
+            }
+
+            match response_json["content"].as_array() {
+                Some(content_array) => {
+                    // The calls stay in the conversation so each result can answer its call
+                    self.messages.push(Message {
+                        role: "assistant".to_string(),
+                        content: MessageContent::Blocks(content_array.clone()),
+                    });
+                    self.save_session();
+                    for message_block in content_array {
+                        if message_block["type"].as_str() == Some("tool_use") {
+                            self.run_tool(message_block, frontend).await;
+                        }
+                    }
+                }
+                None => frontend.emit(AgentEvent::Notice("Content is not an ARRAY".to_string())),
+            }
+            // Loop to send the tool results back and get the model's response to them
+        }
+    }
+
+    /// Tools offered to the model, as enabled in the configuration.
+    fn enabled_tools(&self) -> Vec<ToolType> {
+        crate::tool_definitions()
+            .into_iter()
+            .filter(|tool| self.config.tool_enabled(tool.name()))
+            .collect()
+    }
+
+    /// Runs a blocking tool on the blocking pool, giving up after the configured tool timeout.
+    async fn run_blocking<T: Send + 'static>(
+        &self,
+        task: impl FnOnce() -> std::io::Result<T> + Send + 'static,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        let timeout = Duration::from_secs(self.config.tool_timeout_secs);
+        match tokio::time::timeout(timeout, tokio::task::spawn_blocking(task)).await {
+            Ok(Ok(result)) => Ok(result?),
+            // A bug in a tool costs the model this call, not the user the session
+            Ok(Err(join_error)) if join_error.is_panic() => Err(format!(
+                "the tool crashed: {}",
+                panic_message(join_error.into_panic().as_ref())
+            )
+            .into()),
+            Ok(Err(join_error)) => Err(join_error.into()),
+            Err(_) => Err(format!(
+                "the tool did not finish within {} seconds",
+                timeout.as_secs()
+            )
+            .into()),
+        }
+    }
+
+    /// Runs one tool call and adds its result to the conversation. Every failure, from a missing
+    /// tool name to a panic in the tool, becomes a failed result the model can recover from.
+    async fn run_tool(&mut self, message_block: &Value, frontend: &mut dyn Frontend) {
+        let tool_id = message_block["id"].as_str().unwrap_or_default().to_string();
+        let tool_name = message_block["name"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let input_value = message_block.get("input").cloned().unwrap_or(Value::Null);
+        debug!("Tool Name: {}", tool_name);
+
+        let detail = match tool_name.as_str() {
+            "scan_directory" => input_value
+                .get("path")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+            "read_file" => input_value
+                .get("file_path")
+                .into_iter()
+                .chain(
+                    input_value
+                        .get("file_paths")
+                        .and_then(Value::as_array)
+                        .into_iter()
+                        .flatten(),
+                )
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join(", "),
+            EDIT_TOOL_NAME => format!(
+                "{} {}",
+                input_value["command"].as_str().unwrap_or_default(),
+                input_value["path"].as_str().unwrap_or_default()
+            ),
+            _ => String::new(),
+        };
+        frontend.emit(AgentEvent::ToolCall {
+            id: tool_id.clone(),
+            name: tool_name.clone(),
+            detail: detail.clone(),
+        });
+        self.record_in_session(SessionRecord::ToolCall {
+            id: tool_id.clone(),
+            name: tool_name.clone(),
+            input: input_value.clone(),
+        });
+
+        let (success, content) = match self
+            .execute_tool(&tool_name, &input_value, &detail, frontend)
+            .await
+        {
+            Ok(content) => (true, content),
+            Err(e) => (false, format!("Tool execution failed: {}", e)),
+        };
+
+        frontend.emit(AgentEvent::ToolResult {
+            id: tool_id.clone(),
+            name: tool_name.clone(),
+            success,
+            content: content.clone(),
+        });
+        self.messages
+            .push(Message::tool_result(&tool_id, &content, success));
+        self.record_in_session(SessionRecord::ToolResult {
+            id: tool_id,
+            name: tool_name,
+            success,
+            content,
+        });
+    }
+
+    /// Checks and runs a tool call, returning what to tell the model on success.
+    async fn execute_tool(
+        &mut self,
+        tool_name: &str,
+        input_value: &Value,
+        detail: &str,
+        frontend: &mut dyn Frontend,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        if tool_name.is_empty() {
+            return Err("the tool call has no tool name".into());
+        }
+        if !input_value.is_object() {
+            return Err(format!("the {} call has no input object", tool_name).into());
+        }
+        if !matches!(tool_name, "scan_directory" | "read_file" | EDIT_TOOL_NAME) {
+            return Err(format!("there is no tool named {}", tool_name).into());
+        }
+        // The tools get the checked path, not the one the model sent
+        let resolved = self
+            .authorize(tool_name, input_value, detail, frontend)
+            .await?;
+
+        match tool_name {
+            // Read-only tools run on the blocking pool so an interrupt doesn't have to wait for them
+            "scan_directory" => {
+                let mut options = ScanOptions::from_tool_input(input_value)?;
+                options.budget_tokens = Some(self.config.scan_budget_tokens);
+                let directory =
+                    resolved.map(|path| path_patterns::relative_to(&path, &self.working_dir));
+                let tree_structure = match directory {
+                    // Other allowed directories are scanned from their own root, without the project's index
+                    Some(directory) if directory.is_absolute() => {
+                        options.path = PathBuf::new();
+                        let ignore = IgnoreRules::new(&directory, &self.config.ignore_patterns);
+                        self.run_blocking(move || {
+                            scan_directory::scan_directory(&directory, &options, &ignore)
+                        })
+                        .await?
+                    }
+                    directory => {
+                        if let Some(directory) = directory {
+                            options.path = directory;
+                        }
+                        let index = self.workspace_index();
+                        self.run_blocking(move || index.scan(&options)).await?
+                    }
+                };
+                Ok(format!("Here is the tree structure: {}", tree_structure))
+            }
+            "read_file" => {
+                let mut options = ReadOptions::from_tool_input(input_value)?;
+                if let Some(path) = resolved {
+                    options.paths[0] = path_patterns::relative_to(&path, &self.working_dir)
+                        .display()
+                        .to_string();
+                }
+                options.max_bytes = Some(self.config.max_read_bytes);
+                options.budget_tokens = Some(self.config.read_budget_tokens);
+                self.read_files(options).await
+            }
+            _ => {
+                debug!("Input value: {:?}", input_value);
+                let input_value = &resolved.map_or_else(
+                    || input_value.clone(),
+                    |path| with_path(input_value, &path, &self.working_dir),
+                );
+                let model_name = &self.client.config.model_name;
+                let max_read_bytes = self.config.max_read_bytes;
+                // Edits are small writes and run inline, so an interrupt or the tool timeout never
+                // leaves one landing after the index, file tracker and transcript below are updated.
+                // A bug in an edit command costs the model this call, not the user the session
+                let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                    text_editor::handle_text_editor_tool(
+                        input_value,
+                        model_name,
+                        max_read_bytes,
+                        &self.working_dir,
+                        &mut self.edits,
+                    )
+                }))
+                .map_err(|panic| {
+                    format!(
+                        "the {} tool crashed: {}",
+                        tool_name,
+                        panic_message(panic.as_ref())
+                    )
+                })??;
+                if let Some(path) = input_value.get("path").and_then(Value::as_str) {
+                    if let Some(index) = self.index.as_ref().filter(|_| result.changes_made) {
+                        index.invalidate(&self.working_dir.join(path));
+                    }
+                    if result.success {
+                        self.files
+                            .record(Path::new(path), None, self.config.max_read_bytes);
+                    }
+                }
+                if let Some(diff) = &result.diff {
+                    frontend.emit(AgentEvent::FileDiff {
+                        path: diff.path.clone(),
+                        diff: diff.unified.clone(),
+                    });
+                    self.record_in_session(SessionRecord::FileDiff {
+                        path: diff.path.clone(),
+                        diff: diff.unified.clone(),
+                    });
+                }
+                if !result.success {
+                    return Err(result.message.into());
+                }
+
+                // Mutating commands report a compact diff rather than echoing the whole file back
+                Ok(if let Some(diff) = &result.diff {
+                    format!(
+                        "Tool execution successful: {}\n\n{}",
+                        result.message,
+                        diff.summary_for_model()
+                    )
+                } else if let Some(file_content) = result.file_content {
+                    format!(
+                        "Tool execution successful: {}\n\nFile content:\n{}",
+                        result.message, file_content
+                    )
+                } else {
+                    format!("Tool execution successful: {}", result.message)
+                })
+            }
+        }
+    }
+
+    /// Reads one file, or a batch of files and globs with a block per file.
+    async fn read_files(
+        &mut self,
+        options: ReadOptions,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        if options.is_single(&self.working_dir) {
+            let path = self.working_dir.join(&options.paths[0]);
+            let task_path = path.clone();
+            let read = self
+                .run_blocking(move || {
+                    read_file::read_file(
+                        &task_path,
+                        &options.paths[0],
+                        &options,
+                        options.budget_chars(),
+                    )
+                })
+                .await?;
+            if let Some(text) = read.text {
+                self.files
+                    .record(&path, Some(text), self.config.max_read_bytes);
+            }
+            return Ok(format!("Here are the contents of the file {}", read.output));
+        }
+
+        let index = self.workspace_index();
+        let requested = options.paths.clone();
+        let root = self.working_dir.clone();
+        let expanded = self
+            .run_blocking(move || {
+                Ok(requested
+                    .into_iter()
+                    .map(|path| {
+                        let found = if read_file::is_glob(&path, &root) {
+                            PathPatterns::new(std::slice::from_ref(&path))
+                                .map_err(|e| format!("Invalid pattern: {}", e))
+                                .and_then(|patterns| {
+                                    index.find_files(&patterns).map_err(|e| e.to_string())
+                                })
+                        } else {
+                            Ok(vec![PathBuf::from(&path)])
+                        };
+                        (path, found)
+                    })
+                    .collect::<Vec<_>>())
+            })
+            .await?;
+
+        // Each file gets the same checks a single read would
+        let mut files = Vec::new();
+        let mut seen = HashSet::new();
+        for (requested, found) in expanded {
+            match found {
+                Ok(paths) if paths.is_empty() => files.push(BatchFile {
+                    display: requested,
+                    path: Err("No files match this pattern".to_string()),
+                }),
+                Ok(paths) => {
+                    for path in paths {
+                        let relative = path_patterns::relative_to(&path, &self.working_dir);
+                        if !seen.insert(relative.clone()) {
+                            continue;
+                        }
+                        files.push(BatchFile {
+                            display: path.display().to_string(),
+                            path: self.resolve_path(&relative),
+                        });
+                    }
+                }
+                Err(reason) => files.push(BatchFile {
+                    display: requested,
+                    path: Err(reason),
+                }),
+            }
+        }
+
+        let batch = self
+            .run_blocking(move || Ok(read_file::read_batch(&files, &options)))
+            .await?;
+        for (path, text) in batch.texts {
+            self.files
+                .record(&path, Some(text), self.config.max_read_bytes);
+        }
+        Ok(batch.output)
+    }
+
+    /// Where `path` (relative to the project) really leads, or why the tools may not touch it.
+    fn resolve_path(&self, path: &Path) -> Result<PathBuf, String> {
+        resolve_tool_path(&self.working_dir, &self.config, self.permission_mode, path)
+    }
+
+    fn record_in_session(&mut self, record: SessionRecord) {
+        if let Some(session) = &mut self.session {
+            if let Err(e) = session.record(&self.messages, &self.client.config, record) {
+                warn!("Could not save the session transcript: {}", e);
+            }
+        }
+    }
+
+    /// Applies the permission mode to a tool call, asking the user when needed.
+    /// Returns where the call's path really leads, or the reason to report to the model when the call must not run.
+    async fn authorize(
+        &mut self,
+        tool_name: &str,
+        input: &Value,
+        summary: &str,
+        frontend: &mut dyn Frontend,
+    ) -> Result<Option<PathBuf>, String> {
+        if !self.config.tool_enabled(tool_name) {
+            return Err(format!(
+                "The {} tool is disabled in the configuration.",
+                tool_name
+            ));
+        }
+        let side_effecting = tool_name == EDIT_TOOL_NAME && text_editor::is_mutating_command(input);
+        // Batch reads check each file as it is read
+        let path = match tool_name {
+            "read_file" => ReadOptions::from_tool_input(input)
+                .ok()
+                .filter(|options| options.is_single(&self.working_dir))
+                .map(|options| options.paths[0].clone()),
+            "scan_directory" | EDIT_TOOL_NAME => input["path"].as_str().map(str::to_string),
+            _ => None,
+        }
+        .map(|path| path_patterns::relative_to(Path::new(&path), &self.working_dir));
+        let resolved = path.map(|path| self.resolve_path(&path)).transpose()?;
+        let relative = resolved
+            .as_deref()
+            .map(|resolved| path_patterns::relative_to(resolved, &self.working_dir));
+        match permissions::check_tool_call(
+            self.permission_mode,
+            &self.config.permissions,
+            relative.as_deref(),
+            side_effecting,
+        ) {
+            PermissionCheck::Allowed => Ok(resolved),
+            PermissionCheck::Denied(reason) => Err(reason),
+            PermissionCheck::NeedsApproval => {
+                let input = resolved.as_deref().map_or_else(
+                    || input.clone(),
+                    |path| with_path(input, path, &self.working_dir),
+                );
+                let request = ApprovalRequest {
+                    tool_name: tool_name.to_string(),
+                    summary: summary.to_string(),
+                    // Previewed inline like the edit itself, which reads no more than max_read_bytes
+                    diff: text_editor::preview_change(
+                        &input,
+                        self.config.max_read_bytes,
+                        &self.working_dir,
+                        &self.edits,
+                    )
+                    .map(|diff| diff.unified),
+                };
+                match frontend.request_approval(&request).await {
+                    ApprovalDecision::Approve => Ok(resolved),
+                    ApprovalDecision::ApproveForSession => {
+                        self.permission_mode = PermissionMode::AcceptEdits;
+                        frontend.emit(AgentEvent::Notice(
+                            "Edits will be applied without asking for the rest of the session."
+                                .to_string(),
+                        ));
+                        frontend.emit(self.status());
+                        Ok(resolved)
+                    }
+                    ApprovalDecision::Reject(reason) => {
+                        Err(ApprovalDecision::rejection_message(&reason))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Where `path` (relative to `working_dir`) really leads, or why the tools may not touch it.
+/// The ignore and deny rules see the resolved path, so neither `..` nor a symlink gets around them.
+pub fn resolve_tool_path(
+    working_dir: &Path,
+    config: &Config,
+    permission_mode: PermissionMode,
+    path: &Path,
+) -> Result<PathBuf, String> {
+    let workspace = Workspace::new(working_dir, &config.allowed_directories);
+    let relative = workspace
+        .resolve(path)
+        .map(|real| workspace.relative(&real))
+        .map_err(|e| e.to_string())?;
+    let resolved = working_dir.join(&relative);
+    if IgnoreRules::new(working_dir, &config.ignore_patterns)
+        .is_ignored(&resolved, resolved.is_dir())
+    {
+        return Err(format!(
+            "{} is excluded by the project's ignore rules (.gitignore, .simplecoderignore or ignore_patterns). \
+             The user can add `!{}` to .simplecoderignore to make it available.",
+            relative.display(),
+            relative.display()
+        ));
+    }
+    match permissions::check_tool_call(permission_mode, &config.permissions, Some(&relative), false)
+    {
+        PermissionCheck::Denied(reason) => Err(reason),
+        PermissionCheck::Allowed | PermissionCheck::NeedsApproval => Ok(resolved),
+    }
+}
+
+/// `input` with its `path` replaced by the checked path, relative to `root` so that is what the
+/// user and the model see.
+fn with_path(input: &Value, path: &Path, root: &Path) -> Value {
+    let mut input = input.clone();
+    input["path"] = Value::String(path_patterns::relative_to(path, root).display().to_string());
+    input
+}
+
+/// The message a panic was raised with.
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// Frontend for the plain line-based mode: prints everything to stdout.
+///
+/// In one-shot mode (`-p` or piped stdin) only the model's replies go to stdout, so they can be
+/// redirected cleanly; tool activity and notices go to stderr.
+pub struct PlainOutput {
+    one_shot: bool,
+}
+
+impl PlainOutput {
+    pub fn new(one_shot: bool) -> Self {
+        Self { one_shot }
+    }
+
+    fn progress(&self, text: &str) {
+        if self.one_shot {
+            eprintln!("{}", text);
+        } else {
+            println!("{}", text);
+        }
+    }
+
+    fn progress_supports_color(&self) -> bool {
+        if self.one_shot {
+            markdown::supports_color(&std::io::stderr())
+        } else {
+            markdown::stdout_supports_color()
+        }
+    }
+}
+
+impl Frontend for PlainOutput {
+    fn emit(&mut self, event: AgentEvent) {
+        match event {
+            AgentEvent::AssistantText(text) => println!("{}", markdown::render_for_terminal(&text)),
+            AgentEvent::ToolCall { name, detail, .. } => {
+                if detail.is_empty() {
+                    self.progress(&format!("Tool Call: {}", name));
+                } else {
+                    self.progress(&format!("Tool Call: {} {}", name, detail));
+                }
+            }
+            AgentEvent::ToolResult {
+                name,
+                success,
+                content,
+                ..
+            } => {
+                if !success {
+                    self.progress(&format!("{}: {}", name, content));
+                }
+            }
+            AgentEvent::FileDiff { diff, .. } => {
+                self.progress(&diff::colorize(&diff, self.progress_supports_color()))
+            }
+            AgentEvent::Notice(text) => self.progress(&text),
+            AgentEvent::Status { .. } => {}
+        }
+    }
+
+    fn request_approval<'a>(&'a mut self, request: &'a ApprovalRequest) -> ApprovalFuture<'a> {
+        Box::pin(async move {
+            // Piped stdin has already been consumed as input, so there is nobody to ask
+            if !std::io::stdin().is_terminal() {
+                self.progress(&format!(
+                    "Skipped {} {}: edits need approval, which can't be given without a terminal.",
+                    request.tool_name, request.summary
+                ));
+                return ApprovalDecision::Reject(
+                    "The change needs the user's approval, but the agent is running non-interactively. Rerun with --permission-mode accept-edits to allow edits.".to_string(),
+                );
+            }
+            println!(
+                "The model wants to run {}: {}",
+                request.tool_name, request.summary
+            );
+            if let Some(diff) = &request.diff {
+                println!(
+                    "{}",
+                    diff::colorize(diff, markdown::stdout_supports_color())
+                );
+            }
+            let Some(answer) =
+                ask("Apply this change? [y]es / [n]o / [a]lways for this session: ").await
+            else {
+                return ApprovalDecision::Reject("interrupted by the user".to_string());
+            };
+            let reason = match ApprovalDecision::from_answer(&answer, "") {
+                ApprovalDecision::Reject(_) => ask("Reason to give the model (optional): ")
+                    .await
+                    .unwrap_or_default(),
+                _ => String::new(),
+            };
+            ApprovalDecision::from_answer(&answer, &reason)
+        })
+    }
+}
+
+/// Asks a question on the terminal and reads one line of answer, off the async runtime.
+///
+/// Returns `None` when the user presses Ctrl-C. Read errors count as no answer.
+async fn ask(question: &'static str) -> Option<String> {
+    let read = tokio::task::spawn_blocking(move || {
+        let mut editor = rustyline::DefaultEditor::new()?;
+        editor.readline(question)
+    });
+    match read.await {
+        Ok(Ok(answer)) => Some(answer.trim().to_string()),
+        Ok(Err(rustyline::error::ReadlineError::Interrupted)) => None,
+        _ => Some(String::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Default)]
+    struct Recorder {
+        results: Vec<(bool, String)>,
+    }
+
+    impl Frontend for Recorder {
+        fn emit(&mut self, event: AgentEvent) {
+            if let AgentEvent::ToolResult {
+                success, content, ..
+            } = event
+            {
+                self.results.push((success, content));
+            }
+        }
+
+        fn request_approval<'a>(&'a mut self, _request: &'a ApprovalRequest) -> ApprovalFuture<'a> {
+            Box::pin(async { ApprovalDecision::Approve })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_failures_become_results() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path().to_path_buf();
+        let config = Config::default();
+        let client = AnthropicClient::new(
+            config.model_config("test-key".to_string()),
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        let mut agent = Agent::new(client, root.clone(), config);
+        agent.session = None;
+        let missing = root.join("missing.rs").display().to_string();
+
+        let mut recorder = Recorder::default();
+        let calls = [
+            json!({"type": "tool_use", "id": "1", "name": "read_file", "input": {"file_path": missing}}),
+            json!({"type": "tool_use", "id": "2", "name": "scan_directory", "input": {"path": "no/such/dir"}}),
+            json!({"type": "tool_use", "id": "3", "name": EDIT_TOOL_NAME, "input": {"command": "str_replace", "path": missing}}),
+            json!({"type": "tool_use", "id": "4", "name": "shell", "input": {}}),
+            json!({"type": "tool_use", "id": "5", "name": "read_file"}),
+        ];
+        for call in &calls {
+            agent.run_tool(call, &mut recorder).await;
+        }
+
+        // Every call gets a failed result in the conversation and the turn carries on
+        assert_eq!(recorder.results.len(), calls.len());
+        assert!(recorder
+            .results
+            .iter()
+            .all(|(success, content)| !success && content.starts_with("Tool execution failed: ")));
+        assert!(recorder.results[3].1.contains("no tool named shell"));
+        assert!(recorder.results[4].1.contains("no input"));
+        // Each failure answers its call as an error result
+        assert_eq!(agent.messages.len(), calls.len());
+        for ((message, call), (_, content)) in
+            agent.messages.iter().zip(&calls).zip(&recorder.results)
+        {
+            assert_eq!(message.role, "user");
+            assert_eq!(
+                message.content,
+                MessageContent::Blocks(vec![json!({
+                    "type": "tool_result",
+                    "tool_use_id": call["id"],
+                    "content": content,
+                    "is_error": true,
+                })])
+            );
+        }
+    }
+
+    #[test]
+    fn test_request_messages() {
+        let text = |role: &str, text: &str| Message {
+            role: role.to_string(),
+            content: text.to_string().into(),
+        };
+        let calls = Message {
+            role: "assistant".to_string(),
+            content: MessageContent::Blocks(vec![
+                json!({"type": "text", "text": "Reading both."}),
+                json!({"type": "tool_use", "id": "a", "name": "read_file", "input": {}}),
+                json!({"type": "tool_use", "id": "b", "name": "read_file", "input": {}}),
+            ]),
+        };
+        let messages = [
+            // A result saved before tool calls were kept has no call to answer
+            Message::tool_result("old", "Here is the tree structure: src/", true),
+            text("user", "Read them"),
+            calls,
+            Message::tool_result("a", "fn main() {}", true),
+            text("user", INTERRUPTED_MARKER),
+        ];
+
+        let sent = crate::request_messages(&messages);
+        assert_eq!(sent.len(), 3);
+        assert_eq!(
+            sent[0].content,
+            MessageContent::Blocks(vec![
+                json!({"type": "text", "text": "Here is the tree structure: src/"}),
+                json!({"type": "text", "text": "Read them"}),
+            ])
+        );
+        // The call cut short by the interrupt is answered too, results ahead of the text
+        let MessageContent::Blocks(blocks) = &sent[2].content else {
+            panic!("expected blocks")
+        };
+        assert_eq!(
+            blocks
+                .iter()
+                .map(|block| block["type"].as_str().unwrap())
+                .collect::<Vec<_>>(),
+            ["tool_result", "tool_result", "text"]
+        );
+        assert_eq!(
+            blocks[0],
+            json!({"type": "tool_result", "tool_use_id": "a", "content": "fn main() {}", "is_error": false})
+        );
+        assert_eq!(blocks[1]["tool_use_id"], "b");
+        assert_eq!(blocks[1]["is_error"], true);
+    }
+
+    #[tokio::test]
+    async fn test_tools_stay_in_the_workspace() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path().join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(directory.path().join("secret.txt"), "outside\n").unwrap();
+        let mut config = Config::default();
+        config.permissions.mode = PermissionMode::AcceptEdits;
+        let client = AnthropicClient::new(
+            config.model_config("test-key".to_string()),
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        let mut agent = Agent::new(client, root.clone(), config);
+        agent.session = None;
+        let created = directory.path().join("escape/created.txt");
+
+        let mut recorder = Recorder::default();
+        let calls = [
+            json!({"name": "read_file", "input": {"file_path": "../secret.txt"}}),
+            json!({"name": "read_file", "input": {"file_paths": ["../secret.txt", "*.md"]}}),
+            json!({"name": "read_file", "input": {"file_paths": ["../secret.txt"]}}),
+            json!({"name": "scan_directory", "input": {"path": ".."}}),
+            json!({"name": EDIT_TOOL_NAME, "input": {"command": "create", "path": created, "file_text": "x"}}),
+        ];
+        for call in &calls {
+            agent.run_tool(call, &mut recorder).await;
+        }
+
+        assert!(
+            !recorder.results[0].0
+                && recorder.results[0]
+                    .1
+                    .contains("../secret.txt is outside the project")
+        );
+        // A batch reports the refusal in the file's block
+        assert!(recorder.results[1].1.contains(
+            "<file path=\"../secret.txt\">\nError: ../secret.txt is outside the project"
+        ));
+        assert!(recorder.results[2..]
+            .iter()
+            .all(|(success, content)| !success && content.contains("is outside the project")));
+        assert!(!created.exists() && !created.parent().unwrap().exists());
+    }
+
+    /// A project with `secrets/**` denied and `build/**` ignored, for checking that paths can't get around them.
+    /// `outside.txt` sits next to the project directory.
+    fn guarded_project() -> (tempfile::TempDir, Agent) {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path().join("project");
+        std::fs::write(directory.path().join("outside.txt"), "outside\n").unwrap();
+        for (path, text) in [
+            ("secrets/key", "hunter2\n"),
+            ("build/out.txt", "generated\n"),
+            ("src/lib.rs", "pub fn f() {}\n"),
+        ] {
+            std::fs::create_dir_all(root.join(path).parent().unwrap()).unwrap();
+            std::fs::write(root.join(path), text).unwrap();
+        }
+        let mut config = Config::default();
+        config.permissions.mode = PermissionMode::AcceptEdits;
+        config.permissions.deny = PathPatterns::new(&["secrets/**".to_string()]).unwrap();
+        config.ignore_patterns = PathPatterns::new(&["build/**".to_string()]).unwrap();
+        let client = AnthropicClient::new(
+            config.model_config("test-key".to_string()),
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        let mut agent = Agent::new(client, root, config);
+        agent.session = None;
+        (directory, agent)
+    }
+
+    #[tokio::test]
+    async fn test_rules_apply_after_parent_components() {
+        let (_directory, mut agent) = guarded_project();
+        let mut recorder = Recorder::default();
+        let calls = [
+            json!({"name": "read_file", "input": {"file_path": "src/../secrets/key"}}),
+            json!({"name": "read_file", "input": {"file_paths": ["src/../secrets/key", "src/lib.rs"]}}),
+            json!({"name": EDIT_TOOL_NAME, "input": {"command": "str_replace", "path": "src/../secrets/key", "old_str": "hunter2", "new_str": "x"}}),
+            json!({"name": "read_file", "input": {"file_path": "src/../build/out.txt"}}),
+        ];
+        for call in &calls {
+            agent.run_tool(call, &mut recorder).await;
+        }
+
+        let denied = "Access to secrets/key is denied";
+        assert!(
+            !recorder.results[0].0 && recorder.results[0].1.contains(denied),
+            "{}",
+            recorder.results[0].1
+        );
+        assert!(
+            recorder.results[1].1.contains(denied)
+                && recorder.results[1].1.contains("pub fn f() {}")
+        );
+        assert!(!recorder.results[2].0 && recorder.results[2].1.contains(denied));
+        assert!(
+            !recorder.results[3].0
+                && recorder.results[3]
+                    .1
+                    .contains("build/out.txt is excluded by the project's ignore rules")
+        );
+        assert_eq!(
+            std::fs::read_to_string(agent.working_dir.join("secrets/key")).unwrap(),
+            "hunter2\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tools_show_project_relative_paths() {
+        let (_directory, mut agent) = guarded_project();
+        let root = agent.working_dir.canonicalize().unwrap();
+        let lib = root.join("src/../src/lib.rs");
+        let mut recorder = Recorder::default();
+        let calls = [
+            json!({"name": "read_file", "input": {"file_path": lib}}),
+            json!({"name": EDIT_TOOL_NAME, "input": {"command": "str_replace", "path": lib, "old_str": "f()", "new_str": "g()"}}),
+        ];
+        for call in &calls {
+            agent.run_tool(call, &mut recorder).await;
+        }
+
+        assert!(
+            recorder.results[0]
+                .1
+                .starts_with("Here are the contents of the file src/lib.rs ("),
+            "{}",
+            recorder.results[0].1
+        );
+        assert!(
+            recorder.results[1]
+                .1
+                .contains("Successfully replaced text in src/lib.rs"),
+            "{}",
+            recorder.results[1].1
+        );
+        assert!(recorder.results[1]
+            .1
+            .contains("--- a/src/lib.rs\n+++ b/src/lib.rs"));
+        assert!(recorder
+            .results
+            .iter()
+            .all(|(_, content)| !content.contains(&*root.to_string_lossy())));
+        assert_eq!(
+            std::fs::read_to_string(root.join("src/lib.rs")).unwrap(),
+            "pub fn g() {}\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_undo_goes_through_the_agent() {
+        let (_directory, mut agent) = guarded_project();
+        let lib = agent.working_dir.join("src/lib.rs");
+        let mut recorder = Recorder::default();
+        let edit = json!({"name": EDIT_TOOL_NAME, "input": {"command": "str_replace", "path": "src/lib.rs", "old_str": "f()", "new_str": "g()"}});
+        agent.run_tool(&edit, &mut recorder).await;
+        let messages = agent.messages.len();
+
+        agent.permission_mode = PermissionMode::ReadOnly;
+        agent.handle_input("/undo", &mut recorder).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&lib).unwrap(), "pub fn g() {}\n");
+        assert_eq!(agent.messages.len(), messages);
+
+        agent.permission_mode = PermissionMode::AcceptEdits;
+        agent.handle_input("/undo", &mut recorder).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&lib).unwrap(), "pub fn f() {}\n");
+        let MessageContent::Text(note) = &agent.messages.last().unwrap().content else {
+            panic!("expected text")
+        };
+        assert!(note.starts_with("The user reverted your last edit to src/lib.rs with /undo."));
+        // The revert is known, so it isn't reported as an outside change
+        assert!(agent.files.take_changes(u64::MAX).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_scans_use_the_checked_path() {
+        let (directory, mut agent) = guarded_project();
+        let shared = directory.path().join("shared");
+        std::fs::create_dir_all(shared.join("docs")).unwrap();
+        std::fs::write(shared.join("docs/notes.md"), "notes\n").unwrap();
+        agent.config.allowed_directories = vec!["../shared".to_string()];
+        let mut recorder = Recorder::default();
+        let calls = [
+            json!({"name": "scan_directory", "input": {"path": agent.working_dir.join("src")}}),
+            json!({"name": "scan_directory", "input": {"path": "build/../src"}}),
+            json!({"name": "scan_directory", "input": {"path": shared}}),
+            json!({"name": "scan_directory", "input": {"path": "../shared/docs"}}),
+        ];
+        for call in &calls {
+            agent.run_tool(call, &mut recorder).await;
+        }
+
+        for (success, content) in &recorder.results {
+            assert!(success, "{}", content);
+        }
+        assert!(
+            recorder.results[0].1.contains("src\n└── lib.rs"),
+            "{}",
+            recorder.results[0].1
+        );
+        assert_eq!(recorder.results[0].1, recorder.results[1].1);
+        assert!(
+            recorder.results[2].1.contains("notes.md"),
+            "{}",
+            recorder.results[2].1
+        );
+        assert!(
+            recorder.results[3].1.contains("notes.md"),
+            "{}",
+            recorder.results[3].1
+        );
+    }
+
+    #[test]
+    fn test_mentions_get_the_tool_checks() {
+        let (_directory, agent) = guarded_project();
+        let line = "Look at @secrets/key, @src/../secrets/key, @build/out.txt, @../outside.txt and @src/lib.rs";
+        let expanded = mentions::expand_mentions(line, &agent.working_dir, u64::MAX, |path| {
+            agent.resolve_path(path)
+        });
+
+        assert_eq!(expanded.attached, vec!["src/lib.rs (1 lines)"]);
+        assert_eq!(expanded.text.matches("<attached_file").count(), 1);
+        assert!(!expanded.text.contains("hunter2"));
+        assert!(expanded.errors[0]
+            .starts_with("Not attaching @secrets/key: Access to secrets/key is denied"));
+        assert!(expanded.errors[1]
+            .starts_with("Not attaching @src/../secrets/key: Access to secrets/key is denied"));
+        assert!(
+            expanded.errors[2].contains("build/out.txt is excluded by the project's ignore rules")
+        );
+        assert!(expanded.errors[3]
+            .starts_with("Not attaching @../outside.txt: ../outside.txt is outside the project"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_rules_apply_through_symlinks() {
+        let (_directory, mut agent) = guarded_project();
+        let root = agent.working_dir.clone();
+        std::os::unix::fs::symlink(root.join("secrets/key"), root.join("src/key")).unwrap();
+        std::os::unix::fs::symlink(root.join("build"), root.join("out")).unwrap();
+        std::os::unix::fs::symlink(root.join("src/lib.rs"), root.join("lib.rs")).unwrap();
+        let mut recorder = Recorder::default();
+        let calls = [
+            json!({"name": "read_file", "input": {"file_path": "src/key"}}),
+            json!({"name": "read_file", "input": {"file_paths": ["src/key", "out/out.txt"]}}),
+            json!({"name": EDIT_TOOL_NAME, "input": {"command": "create", "path": "src/key", "file_text": "x"}}),
+            json!({"name": "read_file", "input": {"file_path": "out/out.txt"}}),
+            // Allowed targets are used where they really are
+            json!({"name": EDIT_TOOL_NAME, "input": {"command": "str_replace", "path": "lib.rs", "old_str": "f()", "new_str": "g()"}}),
+        ];
+        for call in &calls {
+            agent.run_tool(call, &mut recorder).await;
+        }
+
+        let denied = "Access to secrets/key is denied";
+        assert!(
+            !recorder.results[0].0 && recorder.results[0].1.contains(denied),
+            "{}",
+            recorder.results[0].1
+        );
+        assert!(
+            recorder.results[1].1.contains(denied)
+                && recorder.results[1].1.contains("build/out.txt is excluded")
+        );
+        assert!(!recorder.results[2].0 && recorder.results[2].1.contains(denied));
+        assert!(
+            !recorder.results[3].0 && recorder.results[3].1.contains("build/out.txt is excluded")
+        );
+        assert!(recorder.results[4].0, "{}", recorder.results[4].1);
+        assert_eq!(
+            std::fs::read_to_string(root.join("secrets/key")).unwrap(),
+            "hunter2\n"
+        );
+        assert!(root
+            .join("lib.rs")
+            .symlink_metadata()
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(
+            std::fs::read_to_string(root.join("src/lib.rs")).unwrap(),
+            "pub fn g() {}\n"
+        );
+    }
+
+    /// Answers one request on a local port with `status` and `body`, returning the base URL to use.
+    async fn serve_once(status: &'static str, body: &'static str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            // Read the whole request so the client isn't cut off mid-send
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            loop {
+                let read = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let length = text[..end]
+                        .lines()
+                        .find_map(|line| {
+                            line.to_ascii_lowercase()
+                                .strip_prefix("content-length:")
+                                .map(|n| n.trim().parse::<usize>().unwrap())
+                        })
+                        .unwrap_or(0);
+                    if request.len() >= end + 4 + length {
+                        break;
+                    }
+                }
+                if read == 0 {
+                    break;
+                }
+            }
+            let response = format!(
+                "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn test_api_errors_leave_the_conversation_alone() {
+        let responses = [
+            (
+                "529 Overloaded",
+                r#"{"type": "error", "error": {"type": "overloaded_error", "message": "Overloaded"}}"#,
+            ),
+            (
+                "200 OK",
+                r#"{"type": "error", "error": {"type": "api_error", "message": "Internal server error"}}"#,
+            ),
+            ("502 Bad Gateway", "<html>Bad Gateway</html>"),
+        ];
+        for (status, body) in responses {
+            let directory = tempfile::tempdir().unwrap();
+            let config = Config::default();
+            let mut model_config = config.model_config("test-key".to_string());
+            model_config.api_base_url = Some(serve_once(status, body).await);
+            let client = AnthropicClient::new(model_config, Duration::from_secs(5)).unwrap();
+            let mut agent = Agent::new(client, directory.path().to_path_buf(), config);
+            agent.session = None;
+            agent.messages.push(Message {
+                role: "user".to_string(),
+                content: "Hello".to_string().into(),
+            });
+            let before = agent.messages.clone();
+
+            let error = agent
+                .run_turn(&mut Recorder::default())
+                .await
+                .unwrap_err()
+                .to_string();
+            assert!(
+                error.contains(status.split(' ').next().unwrap()),
+                "{}",
+                error
+            );
+            assert_eq!(agent.messages, before);
+        }
+    }
+}
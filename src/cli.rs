@@ -0,0 +1,94 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use crate::config::{ConfigLayer, PermissionLayer};
+use crate::export::{ExportFormat, DEFAULT_COLLAPSE_LINES};
+use crate::permissions::PermissionMode;
+
+/// An AI pair programmer for your terminal.
+#[derive(Debug, Parser)]
+#[command(name = "simple-coder", version)]
+pub struct Cli {
+    /// Answer this prompt and exit instead of starting a conversation. Piped stdin is attached as context.
+    #[arg(short, long, value_name = "PROMPT", conflicts_with = "tui")]
+    pub prompt: Option<String>,
+
+    /// Run in full-screen terminal UI mode instead of the plain line-based mode
+    #[arg(long)]
+    pub tui: bool,
+
+    /// How file edits are approved: ask before each one, accept them all, or refuse them all
+    #[arg(long, value_enum)]
+    pub permission_mode: Option<PermissionMode>,
+
+    /// Named profile from the configuration files, e.g. `careful` for `[profiles.careful]`
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Model to use, overriding the configuration files
+    #[arg(long)]
+    pub model: Option<String>,
+
+    /// Sampling temperature between 0 and 1
+    #[arg(long)]
+    pub temperature: Option<f32>,
+
+    /// Maximum number of tokens in each response
+    #[arg(long)]
+    pub max_tokens: Option<usize>,
+
+    /// Base URL of the Anthropic API, e.g. for a proxy
+    #[arg(long, value_name = "URL")]
+    pub base_url: Option<String>,
+
+    /// Print the effective configuration, with where each value comes from, and exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Continue a saved session of this project: the given id, or the most recent one
+    #[arg(long, value_name = "ID", num_args = 0..=1, default_missing_value = "")]
+    pub resume: Option<String>,
+
+    /// List the saved sessions of this project and exit
+    #[arg(long)]
+    pub list_sessions: bool,
+
+    /// Export a saved session (the given id, or the most recent one) to Markdown or HTML and exit
+    #[arg(long, value_name = "ID", num_args = 0..=1, default_missing_value = "")]
+    pub export: Option<String>,
+
+    /// File to export to; `.html` gives HTML, anything else Markdown (default: `<id>.md`)
+    #[arg(long, short, value_name = "FILE", requires = "export")]
+    pub output: Option<PathBuf>,
+
+    /// Export format, overriding the output file extension
+    #[arg(long, value_enum, requires = "export")]
+    pub format: Option<ExportFormat>,
+
+    /// Collapse exported tool outputs longer than this many lines (0 never collapses)
+    #[arg(long, value_name = "LINES", default_value_t = DEFAULT_COLLAPSE_LINES, requires = "export")]
+    pub collapse_lines: usize,
+
+    /// Replace file contents in the export with line counts
+    #[arg(long, requires = "export")]
+    pub redact: bool,
+}
+
+impl Cli {
+    /// The configuration layer formed by the flags that were given.
+    pub fn config_layer(&self) -> ConfigLayer {
+        ConfigLayer {
+            profile: self.profile.clone(),
+            model: self.model.clone(),
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            base_url: self.base_url.clone(),
+            permissions: PermissionLayer {
+                mode: self.permission_mode,
+                ..PermissionLayer::default()
+            },
+            ..ConfigLayer::default()
+        }
+    }
+}
@@ -0,0 +1,710 @@
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::agent;
+use crate::config::{Config, ConfigSource};
+use crate::credentials;
+use crate::diff::FileDiff;
+use crate::export::{self, ExportOptions};
+use crate::path_patterns;
+use crate::permissions::{self, PermissionCheck, PermissionMode};
+use crate::session::{self, Session};
+use crate::text_file;
+use crate::tools::text_editor::{self, EditHistory};
+use crate::usage::TokenUsage;
+use crate::{AnthropicClient, Message};
+
+const DEFAULT_CONVERSATION_FILE: &str = "conversation.json";
+
+/// What the main loop should do after a slash command has run.
+pub enum CommandOutcome {
+    Continue,
+    Exit,
+}
+
+/// Session state a slash command is allowed to inspect or modify.
+pub struct CommandContext<'a> {
+    pub messages: &'a mut Vec<Message>,
+    pub client: &'a mut AnthropicClient,
+    pub usage: &'a TokenUsage,
+    pub permission_mode: &'a mut PermissionMode,
+    pub session: &'a mut Option<Session>,
+    pub config: &'a mut Config,
+    pub working_dir: &'a Path,
+    /// Edits made this session, for `/undo` and `/diff`
+    pub edits: &'a mut EditHistory,
+    /// Files `/undo` restored; the agent records them like edits made by the tools
+    pub reverted_files: Vec<FileDiff>,
+    /// Diffs to show after the output, colored by the frontend
+    pub diffs: Vec<FileDiff>,
+    /// Text to show the user once the command finishes
+    pub output: String,
+}
+
+type CommandHandler =
+    fn(&mut CommandContext, &str) -> Result<CommandOutcome, Box<dyn std::error::Error>>;
+
+pub struct SlashCommand {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub description: &'static str,
+    pub handler: CommandHandler,
+}
+
+/// Every slash command known to the REPL. To add a command, add an entry here.
+pub const COMMANDS: &[SlashCommand] = &[
+    SlashCommand {
+        name: "help",
+        usage: "/help",
+        description: "List the available commands",
+        handler: help_command,
+    },
+    SlashCommand {
+        name: "clear",
+        usage: "/clear",
+        description: "Forget the conversation so far",
+        handler: clear_command,
+    },
+    SlashCommand {
+        name: "model",
+        usage: "/model [name]",
+        description: "Show or switch the model",
+        handler: model_command,
+    },
+    SlashCommand {
+        name: "cost",
+        usage: "/cost",
+        description: "Show token usage and estimated cost",
+        handler: cost_command,
+    },
+    SlashCommand {
+        name: "save",
+        usage: "/save [file]",
+        description: "Save the conversation to a JSON file",
+        handler: save_command,
+    },
+    SlashCommand {
+        name: "load",
+        usage: "/load [file]",
+        description: "Load a conversation from a JSON file",
+        handler: load_command,
+    },
+    SlashCommand {
+        name: "sessions",
+        usage: "/sessions",
+        description: "List saved sessions of this project",
+        handler: sessions_command,
+    },
+    SlashCommand {
+        name: "resume",
+        usage: "/resume [id]",
+        description: "Continue a saved session (default: the latest)",
+        handler: resume_command,
+    },
+    SlashCommand {
+        name: "export",
+        usage: "/export [file] [--redact] [--collapse N]",
+        description: "Export this session to Markdown, or HTML for a .html file",
+        handler: export_command,
+    },
+    SlashCommand {
+        name: "undo",
+        usage: "/undo",
+        description: "Revert the most recent file edit",
+        handler: undo_command,
+    },
+    SlashCommand {
+        name: "tools",
+        usage: "/tools",
+        description: "List the tools offered to the model",
+        handler: tools_command,
+    },
+    SlashCommand {
+        name: "diff",
+        usage: "/diff",
+        description: "Show every file change made this session",
+        handler: diff_command,
+    },
+    SlashCommand {
+        name: "permissions",
+        usage: "/permissions [mode]",
+        description: "Show or set the edit approval mode (ask, accept-edits, read-only)",
+        handler: permissions_command,
+    },
+    SlashCommand {
+        name: "profile",
+        usage: "/profile [name]",
+        description: "List the configured profiles or switch to one",
+        handler: profile_command,
+    },
+    SlashCommand {
+        name: "config",
+        usage: "/config",
+        description: "Show the effective configuration and where each value came from",
+        handler: config_command,
+    },
+    SlashCommand {
+        name: "exit",
+        usage: "/exit",
+        description: "Quit the program",
+        handler: exit_command,
+    },
+];
+
+/// Returns true when the input should be handled as a slash command rather than sent to the model.
+///
+/// A message that starts with an absolute path, like `/src/main.rs panics`, goes to the model.
+pub fn is_command(line: &str) -> bool {
+    let Some(rest) = line.strip_prefix('/') else {
+        return false;
+    };
+    let name = rest.split(char::is_whitespace).next().unwrap_or_default();
+    find_command(name).is_some() || !name.contains('/')
+}
+
+pub fn find_command(name: &str) -> Option<&'static SlashCommand> {
+    COMMANDS.iter().find(|command| command.name == name)
+}
+
+/// Parses `/name args...` and runs the matching command.
+pub fn dispatch(
+    line: &str,
+    context: &mut CommandContext,
+) -> Result<CommandOutcome, Box<dyn std::error::Error>> {
+    let line = line.trim_start_matches('/');
+    let (name, args) = match line.split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim()),
+        None => (line, ""),
+    };
+
+    match find_command(name) {
+        Some(command) => (command.handler)(context, args),
+        None => {
+            writeln!(
+                context.output,
+                "Unknown command: /{}. Type /help to see the available commands.",
+                name
+            )?;
+            Ok(CommandOutcome::Continue)
+        }
+    }
+}
+
+fn help_command(
+    context: &mut CommandContext,
+    _args: &str,
+) -> Result<CommandOutcome, Box<dyn std::error::Error>> {
+    writeln!(context.output, "Available commands:")?;
+    for command in COMMANDS {
+        writeln!(
+            context.output,
+            "  {:<20} {}",
+            command.usage, command.description
+        )?;
+    }
+    Ok(CommandOutcome::Continue)
+}
+
+fn clear_command(
+    context: &mut CommandContext,
+    _args: &str,
+) -> Result<CommandOutcome, Box<dyn std::error::Error>> {
+    context.messages.clear();
+    context.edits.clear();
+    writeln!(context.output, "Conversation cleared.")?;
+    Ok(CommandOutcome::Continue)
+}
+
+fn model_command(
+    context: &mut CommandContext,
+    args: &str,
+) -> Result<CommandOutcome, Box<dyn std::error::Error>> {
+    if args.is_empty() {
+        writeln!(context.output, "Current model: {}", context.config.model)?;
+    } else {
+        // The configuration is what status, sessions and /config report; the client follows it
+        context.config.set_model(args, "/model");
+        context.client.config.model_name = context.config.model.clone();
+        writeln!(context.output, "Switched model to {}", args)?;
+    }
+    Ok(CommandOutcome::Continue)
+}
+
+fn cost_command(
+    context: &mut CommandContext,
+    _args: &str,
+) -> Result<CommandOutcome, Box<dyn std::error::Error>> {
+    let usage = context.usage;
+    writeln!(context.output, "Requests:      {}", usage.requests)?;
+    writeln!(context.output, "Input tokens:  {}", usage.input_tokens)?;
+    writeln!(context.output, "Output tokens: {}", usage.output_tokens)?;
+    writeln!(
+        context.output,
+        "Estimated cost: ${:.4}",
+        usage.estimated_cost(&context.client.config.model_name)
+    )?;
+    Ok(CommandOutcome::Continue)
+}
+
+fn save_command(
+    context: &mut CommandContext,
+    args: &str,
+) -> Result<CommandOutcome, Box<dyn std::error::Error>> {
+    let path = if args.is_empty() {
+        DEFAULT_CONVERSATION_FILE
+    } else {
+        args
+    };
+    fs::write(path, serde_json::to_string_pretty(&context.messages)?)?;
+    writeln!(
+        context.output,
+        "Saved {} messages to {}",
+        context.messages.len(),
+        path
+    )?;
+    Ok(CommandOutcome::Continue)
+}
+
+fn load_command(
+    context: &mut CommandContext,
+    args: &str,
+) -> Result<CommandOutcome, Box<dyn std::error::Error>> {
+    let path = if args.is_empty() {
+        DEFAULT_CONVERSATION_FILE
+    } else {
+        args
+    };
+    let messages: Vec<Message> = serde_json::from_str(&fs::read_to_string(path)?)?;
+    writeln!(
+        context.output,
+        "Loaded {} messages from {}",
+        messages.len(),
+        path
+    )?;
+    *context.messages = messages;
+    context.edits.clear();
+    Ok(CommandOutcome::Continue)
+}
+
+fn sessions_command(
+    context: &mut CommandContext,
+    _args: &str,
+) -> Result<CommandOutcome, Box<dyn std::error::Error>> {
+    let mut sessions = session::list_sessions(context.working_dir)?;
+    // The running session is not worth resuming
+    if let Some(current) = context.session.as_ref() {
+        sessions.retain(|summary| summary.id != current.id);
+    }
+    writeln!(
+        context.output,
+        "{}",
+        session::format_session_list(&sessions)
+    )?;
+    Ok(CommandOutcome::Continue)
+}
+
+fn resume_command(
+    context: &mut CommandContext,
+    args: &str,
+) -> Result<CommandOutcome, Box<dyn std::error::Error>> {
+    let id = if args.is_empty() { None } else { Some(args) };
+    let description = session::resume_into(
+        context.working_dir,
+        id,
+        context.messages,
+        &mut context.client.config,
+        context.session,
+    )?;
+    let model = context.client.config.model_name.clone();
+    context.config.set_model(&model, "/resume");
+    context.edits.clear();
+    writeln!(context.output, "{}", description)?;
+    Ok(CommandOutcome::Continue)
+}
+
+fn export_command(
+    context: &mut CommandContext,
+    args: &str,
+) -> Result<CommandOutcome, Box<dyn std::error::Error>> {
+    let mut path = None;
+    let mut options = ExportOptions::default();
+    let mut words = args.split_whitespace();
+    while let Some(word) = words.next() {
+        match word {
+            "--redact" => options.redact_file_contents = true,
+            "--collapse" => {
+                let lines = words.next().ok_or("--collapse needs a line count")?;
+                options.collapse_lines = lines
+                    .parse()
+                    .map_err(|_| format!("Invalid line count: {}", lines))?;
+            }
+            _ if path.is_none() => path = Some(Path::new(word)),
+            _ => return Err(format!("Unexpected argument: {}", word).into()),
+        }
+    }
+
+    let session = context
+        .session
+        .as_ref()
+        .ok_or("Sessions are not being saved, so there is nothing to export.")?;
+    let records = session.records()?;
+    if records.is_empty() {
+        writeln!(context.output, "Nothing to export yet.")?;
+        return Ok(CommandOutcome::Continue);
+    }
+    let (path, format) = export::resolve_output(&session.id, path, None);
+    options.format = format;
+    fs::write(&path, export::export(&records, &options))?;
+    writeln!(
+        context.output,
+        "Exported this session to {}",
+        path.display()
+    )?;
+    Ok(CommandOutcome::Continue)
+}
+
+fn undo_command(
+    context: &mut CommandContext,
+    _args: &str,
+) -> Result<CommandOutcome, Box<dyn std::error::Error>> {
+    let Some(file_path) = context.edits.last_edited() else {
+        writeln!(context.output, "No edits to undo")?;
+        return Ok(CommandOutcome::Continue);
+    };
+    // The revert is a file change like any other, so the sandbox, the rules and the mode apply
+    let relative = path_patterns::relative_to(Path::new(file_path), context.working_dir);
+    let check = agent::resolve_tool_path(
+        context.working_dir,
+        context.config,
+        *context.permission_mode,
+        &relative,
+    )
+    .map(|_| {
+        permissions::check_tool_call(
+            *context.permission_mode,
+            &context.config.permissions,
+            Some(&relative),
+            true,
+        )
+    });
+    if let Err(reason) | Ok(PermissionCheck::Denied(reason)) = check {
+        writeln!(
+            context.output,
+            "Can't undo the edit to {}: {}",
+            file_path, reason
+        )?;
+        return Ok(CommandOutcome::Continue);
+    }
+
+    // Asking for the undo is the user's approval
+    let result = text_editor::undo_last_edit(context.edits);
+    writeln!(context.output, "{}", result.message)?;
+    context.reverted_files.extend(result.diff);
+    Ok(CommandOutcome::Continue)
+}
+
+fn tools_command(
+    context: &mut CommandContext,
+    _args: &str,
+) -> Result<CommandOutcome, Box<dyn std::error::Error>> {
+    writeln!(context.output, "Tools available to the model:")?;
+    for tool in crate::tool_definitions() {
+        let status = if context.config.tool_enabled(tool.name()) {
+            ""
+        } else {
+            " (disabled)"
+        };
+        writeln!(
+            context.output,
+            "  {:<28} {}{}",
+            tool.name(),
+            tool.summary(),
+            status
+        )?;
+    }
+    Ok(CommandOutcome::Continue)
+}
+
+fn diff_command(
+    context: &mut CommandContext,
+    _args: &str,
+) -> Result<CommandOutcome, Box<dyn std::error::Error>> {
+    let mut listing = String::new();
+    for (path, original) in context.edits.changes() {
+        // Read like the tools read, so files in other encodings diff as text; a deleted file diffs as empty
+        let current = match text_file::read_text(&context.working_dir.join(&path), u64::MAX) {
+            Ok(file) => file.text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                writeln!(listing, "  {}: can't show the changes: {}", path, e)?;
+                continue;
+            }
+        };
+        let diff = FileDiff::new(&path, original.as_deref(), &current);
+        if diff.is_empty() {
+            continue;
+        }
+        writeln!(listing, "  {} ({})", path, diff.stats())?;
+        context.diffs.push(diff);
+    }
+    if listing.is_empty() {
+        writeln!(context.output, "No files have been changed this session.")?;
+    } else {
+        write!(context.output, "Changed this session:\n{}", listing)?;
+    }
+    Ok(CommandOutcome::Continue)
+}
+
+fn permissions_command(
+    context: &mut CommandContext,
+    args: &str,
+) -> Result<CommandOutcome, Box<dyn std::error::Error>> {
+    if args.is_empty() {
+        writeln!(
+            context.output,
+            "Permission mode: {}",
+            context.permission_mode
+        )?;
+    } else {
+        *context.permission_mode = args.parse()?;
+        writeln!(
+            context.output,
+            "Permission mode set to {}",
+            context.permission_mode
+        )?;
+    }
+    Ok(CommandOutcome::Continue)
+}
+
+fn config_command(
+    context: &mut CommandContext,
+    _args: &str,
+) -> Result<CommandOutcome, Box<dyn std::error::Error>> {
+    writeln!(context.output, "{}", context.config.describe())?;
+    Ok(CommandOutcome::Continue)
+}
+
+fn profile_command(
+    context: &mut CommandContext,
+    args: &str,
+) -> Result<CommandOutcome, Box<dyn std::error::Error>> {
+    if args.is_empty() {
+        let names = context.config.profile_names();
+        if names.is_empty() {
+            writeln!(
+                context.output,
+                "No profiles configured. Add [profiles.<name>] tables to the configuration file."
+            )?;
+        }
+        for name in names {
+            let marker = if context.config.profile.as_deref() == Some(name) {
+                "*"
+            } else {
+                " "
+            };
+            writeln!(context.output, "{} {}", marker, name)?;
+        }
+        return Ok(CommandOutcome::Continue);
+    }
+
+    let mut config = context.config.with_profile(args)?;
+    // A model picked during the session outlasts the switch unless the profile names its own
+    let mut model_note = String::new();
+    if let ConfigSource::Command(command) = context.config.source("model") {
+        if matches!(config.source("model"), ConfigSource::Profile(..)) {
+            model_note = format!(
+                " (replaces {} chosen with {})",
+                context.config.model, command
+            );
+        } else {
+            config.set_model(&context.config.model, &command);
+        }
+    }
+    // The key belongs to its endpoint, so another helper or base URL means finding it again
+    let api_key = if config.api_key_helper != context.config.api_key_helper
+        || config.base_url != context.config.base_url
+    {
+        credentials::resolve_api_key(&config)?
+    } else {
+        context.client.config.api_key.clone()
+    };
+    *context.client = AnthropicClient::new(
+        config.model_config(api_key),
+        Duration::from_secs(config.request_timeout_secs),
+    )?;
+    *context.permission_mode = config.permissions.mode;
+    writeln!(
+        context.output,
+        "Switched to profile {}: {} {}{} (temperature {}), permission mode {}, tools: {}",
+        args,
+        config.provider,
+        config.model,
+        model_note,
+        config.temperature,
+        config.permissions.mode,
+        config.enabled_tools.join(", ")
+    )?;
+    *context.config = config;
+    Ok(CommandOutcome::Continue)
+}
+
+fn exit_command(
+    _context: &mut CommandContext,
+    _args: &str,
+) -> Result<CommandOutcome, Box<dyn std::error::Error>> {
+    Ok(CommandOutcome::Exit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_names_are_unique() {
+        for (i, command) in COMMANDS.iter().enumerate() {
+            assert!(COMMANDS[i + 1..]
+                .iter()
+                .all(|other| other.name != command.name));
+            assert!(command.usage.starts_with(&format!("/{}", command.name)));
+        }
+    }
+
+    #[test]
+    fn test_is_command() {
+        assert!(is_command("/help"));
+        assert!(!is_command("help me fix /src/main.rs"));
+        assert!(is_command("/model claude-x"));
+        assert!(is_command("/nonsense"));
+        assert!(!is_command("/src/main.rs panics on startup"));
+        assert!(!is_command("/etc/hosts"));
+        assert!(find_command("diff").is_some());
+        assert!(find_command("nope").is_none());
+    }
+
+    fn run(
+        line: &str,
+        config: &mut Config,
+        client: &mut AnthropicClient,
+        edits: &mut EditHistory,
+        working_dir: &Path,
+    ) -> String {
+        let (mut messages, usage, mut mode, mut session) = (
+            Vec::new(),
+            TokenUsage::default(),
+            PermissionMode::default(),
+            None,
+        );
+        let mut context = CommandContext {
+            messages: &mut messages,
+            client,
+            usage: &usage,
+            permission_mode: &mut mode,
+            session: &mut session,
+            config,
+            working_dir,
+            edits,
+            reverted_files: Vec::new(),
+            diffs: Vec::new(),
+            output: String::new(),
+        };
+        dispatch(line, &mut context).unwrap();
+        let diffs: Vec<String> = context.diffs.into_iter().map(|diff| diff.unified).collect();
+        format!("{}{}", context.output, diffs.concat())
+    }
+
+    #[test]
+    fn test_model_command_updates_the_config() {
+        let mut config = Config::default();
+        let mut client = AnthropicClient::new(
+            config.model_config("test-key".to_string()),
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        run(
+            "/model claude-3-5-haiku-latest",
+            &mut config,
+            &mut client,
+            &mut EditHistory::new(),
+            Path::new("."),
+        );
+        assert_eq!(client.config.model_name, "claude-3-5-haiku-latest");
+        assert_eq!(config.model, "claude-3-5-haiku-latest");
+        assert_eq!(
+            config.source("model"),
+            crate::config::ConfigSource::Command("/model".to_string())
+        );
+    }
+
+    #[test]
+    fn test_diff_reads_files_in_their_encoding() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("latin.txt");
+        fs::write(&path, b"caf\xe9 = 1\n").unwrap();
+        let input = serde_json::json!({"command": "str_replace", "path": "latin.txt", "old_str": "1", "new_str": "2"});
+        let mut edits = EditHistory::new();
+        assert!(
+            text_editor::handle_text_editor_tool(
+                &input,
+                "claude-sonnet-4",
+                u64::MAX,
+                directory.path(),
+                &mut edits,
+            )
+            .unwrap()
+            .success
+        );
+
+        let mut config = Config::default();
+        let mut client = AnthropicClient::new(
+            config.model_config("test-key".to_string()),
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        let output = run(
+            "/diff",
+            &mut config,
+            &mut client,
+            &mut edits,
+            directory.path(),
+        );
+        assert!(
+            output.contains("-café = 1") && output.contains("+café = 2"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_profile_switch_keeps_the_chosen_model() {
+        let directory = tempfile::tempdir().unwrap();
+        fs::write(
+            directory.path().join(crate::config::PROJECT_CONFIG_FILE),
+            "[profiles.fast]\nmodel = \"claude-3-5-haiku-latest\"\n\n[profiles.careful]\ntemperature = 0.1\n",
+        )
+        .unwrap();
+        let mut config = Config::load_from(
+            directory.path(),
+            None,
+            |_| None,
+            crate::config::ConfigLayer::default(),
+        )
+        .unwrap();
+        let mut client = AnthropicClient::new(
+            config.model_config("test-key".to_string()),
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        let mut edits = EditHistory::new();
+        let mut run = |line: &str| run(line, &mut config, &mut client, &mut edits, Path::new("."));
+
+        run("/model claude-opus-4-1");
+        assert!(run("/profile careful").contains("claude-opus-4-1 (temperature 0.1)"));
+        assert!(run("/profile fast")
+            .contains("claude-3-5-haiku-latest (replaces claude-opus-4-1 chosen with /model)"));
+        assert_eq!(client.config.model_name, "claude-3-5-haiku-latest");
+        assert_eq!(client.config.api_key, "test-key");
+    }
+}
@@ -0,0 +1,877 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use serde::Deserialize;
+
+use crate::path_patterns::PathPatterns;
+use crate::permissions::PermissionMode;
+use crate::ModelConfig;
+
+/// Project-local configuration, looked up in the working directory
+pub const PROJECT_CONFIG_FILE: &str = ".simple-coder.toml";
+const APP_DIR_NAME: &str = "simple-coder";
+const GLOBAL_CONFIG_FILE: &str = "config.toml";
+const ENV_PREFIX: &str = "SIMPLE_CODER_";
+
+pub const DEFAULT_MODEL: &str = "claude-sonnet-4-20250514";
+pub const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+/// The only API spoken so far; local models work through an Anthropic-compatible `base_url`
+pub const DEFAULT_PROVIDER: &str = "anthropic";
+const PROVIDERS: &[&str] = &[DEFAULT_PROVIDER];
+
+/// How a setting is written in an environment variable.
+#[derive(Clone, Copy)]
+enum ValueKind {
+    Text,
+    Float,
+    Integer,
+    /// Comma-separated in environment variables
+    List,
+}
+
+/// Every setting, as a dotted TOML key. Also the order `describe` prints them in.
+const KEYS: &[(&str, ValueKind)] = &[
+    ("profile", ValueKind::Text),
+    ("provider", ValueKind::Text),
+    ("model", ValueKind::Text),
+    ("temperature", ValueKind::Float),
+    ("max_tokens", ValueKind::Integer),
+    ("base_url", ValueKind::Text),
+    ("api_key_helper", ValueKind::Text),
+    ("request_timeout_secs", ValueKind::Integer),
+    ("tool_timeout_secs", ValueKind::Integer),
+    ("scan_budget_tokens", ValueKind::Integer),
+    ("max_read_bytes", ValueKind::Integer),
+    ("read_budget_tokens", ValueKind::Integer),
+    ("enabled_tools", ValueKind::List),
+    ("ignore_patterns", ValueKind::List),
+    ("allowed_directories", ValueKind::List),
+    ("permissions.mode", ValueKind::Text),
+    ("permissions.allow_edits", ValueKind::List),
+    ("permissions.deny", ValueKind::List),
+];
+
+/// Where the effective value of a setting came from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigSource {
+    Default,
+    File(PathBuf),
+    Env(String),
+    Cli(String),
+    /// A `[profiles.<name>]` table in this file
+    Profile(String, PathBuf),
+    /// A slash command run during the session
+    Command(String),
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Default => f.write_str("default"),
+            ConfigSource::File(path) => write!(f, "{}", path.display()),
+            ConfigSource::Env(name) => write!(f, "env {}", name),
+            ConfigSource::Cli(flag) => write!(f, "flag {}", flag),
+            ConfigSource::Profile(name, path) => {
+                write!(f, "profile {} in {}", name, path.display())
+            }
+            ConfigSource::Command(command) => write!(f, "command {}", command),
+        }
+    }
+}
+
+/// One configuration layer. Every field is optional; unset fields leave lower layers in place.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigLayer {
+    /// Name of the profile to apply on top of the files
+    pub profile: Option<String>,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<usize>,
+    /// Where requests (with the API key) go; only honored outside the project file
+    pub base_url: Option<String>,
+    /// Shell command printing the API key; only honored outside the project file
+    pub api_key_helper: Option<String>,
+    pub request_timeout_secs: Option<u64>,
+    pub tool_timeout_secs: Option<u64>,
+    pub scan_budget_tokens: Option<usize>,
+    pub max_read_bytes: Option<u64>,
+    pub read_budget_tokens: Option<usize>,
+    pub enabled_tools: Option<Vec<String>>,
+    pub ignore_patterns: Option<Vec<String>>,
+    /// Directories outside the project the tools may use; only honored outside the project file
+    pub allowed_directories: Option<Vec<String>>,
+    #[serde(default)]
+    pub permissions: PermissionLayer,
+    /// Named sets of settings, declared as `[profiles.<name>]` tables
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ConfigLayer>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PermissionLayer {
+    pub mode: Option<PermissionMode>,
+    pub allow_edits: Option<Vec<String>>,
+    pub deny: Option<Vec<String>>,
+}
+
+/// Path rules applied on top of the permission mode.
+#[derive(Debug, Clone, Default)]
+pub struct PermissionSettings {
+    pub mode: PermissionMode,
+    /// Edits to matching paths are applied without asking, even in `ask` mode
+    pub allow_edits: PathPatterns,
+    /// Matching paths can be neither read nor edited by tools
+    pub deny: PathPatterns,
+}
+
+/// The layers a configuration is merged from, kept so it can be rebuilt with another profile.
+#[derive(Debug, Clone, Default)]
+struct Layers {
+    files: Vec<(PathBuf, ConfigLayer)>,
+    env: ConfigLayer,
+    cli: ConfigLayer,
+}
+
+/// The effective configuration, merged from defaults, the global and project files, the
+/// selected profile, `SIMPLE_CODER_*` environment variables and command-line flags, in that order.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The selected profile, if any
+    pub profile: Option<String>,
+    pub provider: String,
+    pub model: String,
+    pub temperature: f32,
+    pub max_tokens: usize,
+    pub base_url: String,
+    /// Shell command whose output is the API key
+    pub api_key_helper: Option<String>,
+    pub request_timeout_secs: u64,
+    /// How long a read or scan may take; edits always run to completion
+    pub tool_timeout_secs: u64,
+    /// Roughly how many tokens a `scan_directory` result may use before it is condensed; 0 for no limit
+    pub scan_budget_tokens: usize,
+    /// Larger files are not read or edited by the tools
+    pub max_read_bytes: u64,
+    /// Roughly how many tokens one `read_file` call may return in total; 0 for no limit
+    pub read_budget_tokens: usize,
+    pub enabled_tools: Vec<String>,
+    /// Extra paths hidden from the tools, on top of `.gitignore` and `.simplecoderignore`
+    pub ignore_patterns: PathPatterns,
+    /// Directories outside the project the tools may use, absolute or relative to the project
+    pub allowed_directories: Vec<String>,
+    pub permissions: PermissionSettings,
+    /// Every profile declared in the files, with the file that declares it
+    profiles: BTreeMap<String, (PathBuf, ConfigLayer)>,
+    sources: BTreeMap<&'static str, ConfigSource>,
+    layers: Layers,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            profile: None,
+            provider: DEFAULT_PROVIDER.to_string(),
+            model: DEFAULT_MODEL.to_string(),
+            temperature: 0.5,
+            max_tokens: 2000, // Enough for tool usage (700 tokens) plus a response
+            base_url: DEFAULT_BASE_URL.to_string(),
+            api_key_helper: None,
+            request_timeout_secs: 60,
+            tool_timeout_secs: 30,
+            scan_budget_tokens: 4000,
+            max_read_bytes: 10_000_000,
+            read_budget_tokens: 20000,
+            enabled_tools: crate::tool_definitions()
+                .iter()
+                .map(|tool| tool.name().to_string())
+                .collect(),
+            ignore_patterns: PathPatterns::empty(),
+            allowed_directories: Vec::new(),
+            permissions: PermissionSettings::default(),
+            profiles: BTreeMap::new(),
+            sources: BTreeMap::new(),
+            layers: Layers::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads every layer for a project. `cli` holds the values given as command-line flags.
+    pub fn load(working_dir: &Path, cli: ConfigLayer) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::load_from(
+            working_dir,
+            global_config_path(),
+            |name| std::env::var(name).ok(),
+            cli,
+        )
+    }
+
+    /// Loads like `load`, reading the global file from `global` and variables through `lookup`.
+    pub(crate) fn load_from(
+        working_dir: &Path,
+        global: Option<PathBuf>,
+        lookup: impl Fn(&str) -> Option<String>,
+        cli: ConfigLayer,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut files = Vec::new();
+        let project_file = working_dir.join(PROJECT_CONFIG_FILE);
+        for path in [global, Some(project_file.clone())].into_iter().flatten() {
+            if path.exists() {
+                let layer = check_layer(&path, &project_file, read_layer(&path)?)?;
+                files.push((path, layer));
+            }
+        }
+        let env = env_layer(lookup)?;
+        Self::build(Layers { files, env, cli }, None)
+    }
+
+    /// The same layers merged again with `name` as the selected profile, as `/profile` does.
+    pub fn with_profile(&self, name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::build(self.layers.clone(), Some(name))
+    }
+
+    /// Switches the model for the rest of the session, as `command` (like `/model`) does.
+    pub fn set_model(&mut self, model: &str, command: &str) {
+        self.model = model.to_string();
+        self.sources
+            .insert("model", ConfigSource::Command(command.to_string()));
+    }
+
+    /// Names of the profiles declared in the configuration files.
+    pub fn profile_names(&self) -> Vec<&str> {
+        self.profiles.keys().map(String::as_str).collect()
+    }
+
+    /// Merges `layers` in order; a profile picked at runtime takes precedence over every layer's choice.
+    fn build(layers: Layers, selected: Option<&str>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config = Config::default();
+        for (path, layer) in &layers.files {
+            for (name, profile) in &layer.profiles {
+                if profile.profile.is_some() || !profile.profiles.is_empty() {
+                    return Err(format!(
+                        "Profile '{}' in {} cannot select or declare other profiles",
+                        name,
+                        path.display()
+                    )
+                    .into());
+                }
+                config
+                    .profiles
+                    .insert(name.clone(), (path.clone(), profile.clone()));
+            }
+            config.apply(layer.clone(), |_| ConfigSource::File(path.clone()))?;
+        }
+
+        let profile = match selected {
+            Some(name) => Some(name.to_string()),
+            None => layers
+                .cli
+                .profile
+                .clone()
+                .or_else(|| layers.env.profile.clone())
+                .or_else(|| config.profile.clone()),
+        };
+        if let Some(name) = &profile {
+            let Some((path, layer)) = config.profiles.get(name).cloned() else {
+                let available = if config.profiles.is_empty() {
+                    "none".to_string()
+                } else {
+                    config.profile_names().join(", ")
+                };
+                return Err(format!(
+                    "Unknown profile '{}'. Available profiles: {}",
+                    name, available
+                )
+                .into());
+            };
+            config.apply(layer, |_| ConfigSource::Profile(name.clone(), path.clone()))?;
+        }
+
+        config.apply(layers.env.clone(), |key| {
+            ConfigSource::Env(env_var_name(key))
+        })?;
+        config.apply(layers.cli.clone(), |key| {
+            ConfigSource::Cli(format!(
+                "--{}",
+                key.trim_start_matches("permissions.").replace('_', "-")
+            ))
+        })?;
+        if let Some(name) = selected {
+            config.profile = Some(name.to_string());
+            config
+                .sources
+                .insert("profile", ConfigSource::Command("/profile".to_string()));
+        }
+        config.layers = layers;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// The client settings for this configuration.
+    pub fn model_config(&self, api_key: String) -> ModelConfig {
+        ModelConfig {
+            provider: self.provider.clone(),
+            model_name: self.model.clone(),
+            api_key,
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            api_base_url: Some(self.base_url.clone()),
+        }
+    }
+
+    /// Overrides settings with the ones set in `layer`, recording `source` for each.
+    fn apply(
+        &mut self,
+        layer: ConfigLayer,
+        source: impl Fn(&'static str) -> ConfigSource,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut changed = Vec::new();
+
+        if let Some(profile) = layer.profile {
+            self.profile = Some(profile);
+            changed.push("profile");
+        }
+        if let Some(provider) = layer.provider {
+            self.provider = provider;
+            changed.push("provider");
+        }
+        if let Some(model) = layer.model {
+            self.model = model;
+            changed.push("model");
+        }
+        if let Some(temperature) = layer.temperature {
+            self.temperature = temperature;
+            changed.push("temperature");
+        }
+        if let Some(max_tokens) = layer.max_tokens {
+            self.max_tokens = max_tokens;
+            changed.push("max_tokens");
+        }
+        if let Some(base_url) = layer.base_url {
+            self.base_url = base_url;
+            changed.push("base_url");
+        }
+        if let Some(helper) = layer.api_key_helper {
+            self.api_key_helper = Some(helper);
+            changed.push("api_key_helper");
+        }
+        if let Some(seconds) = layer.request_timeout_secs {
+            self.request_timeout_secs = seconds;
+            changed.push("request_timeout_secs");
+        }
+        if let Some(seconds) = layer.tool_timeout_secs {
+            self.tool_timeout_secs = seconds;
+            changed.push("tool_timeout_secs");
+        }
+        if let Some(tokens) = layer.scan_budget_tokens {
+            self.scan_budget_tokens = tokens;
+            changed.push("scan_budget_tokens");
+        }
+        if let Some(bytes) = layer.max_read_bytes {
+            self.max_read_bytes = bytes;
+            changed.push("max_read_bytes");
+        }
+        if let Some(tokens) = layer.read_budget_tokens {
+            self.read_budget_tokens = tokens;
+            changed.push("read_budget_tokens");
+        }
+        if let Some(tools) = layer.enabled_tools {
+            self.enabled_tools = tools;
+            changed.push("enabled_tools");
+        }
+        if let Some(patterns) = layer.ignore_patterns {
+            self.ignore_patterns = compile_patterns("ignore_patterns", &patterns)?;
+            changed.push("ignore_patterns");
+        }
+        if let Some(directories) = layer.allowed_directories {
+            self.allowed_directories = directories;
+            changed.push("allowed_directories");
+        }
+        if let Some(mode) = layer.permissions.mode {
+            self.permissions.mode = mode;
+            changed.push("permissions.mode");
+        }
+        if let Some(patterns) = layer.permissions.allow_edits {
+            self.permissions.allow_edits = compile_patterns("permissions.allow_edits", &patterns)?;
+            changed.push("permissions.allow_edits");
+        }
+        if let Some(patterns) = layer.permissions.deny {
+            self.permissions.deny = compile_patterns("permissions.deny", &patterns)?;
+            changed.push("permissions.deny");
+        }
+
+        for key in changed {
+            self.sources.insert(key, source(key));
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if !PROVIDERS.contains(&self.provider.as_str()) {
+            return Err(format!(
+                "Unknown provider '{}' ({}). Available providers: {}",
+                self.provider,
+                self.source("provider"),
+                PROVIDERS.join(", ")
+            )
+            .into());
+        }
+        if !(0.0..=1.0).contains(&self.temperature) {
+            return Err(format!(
+                "temperature must be between 0 and 1, got {} ({})",
+                self.temperature,
+                self.source("temperature")
+            )
+            .into());
+        }
+        if self.max_tokens == 0 {
+            return Err(format!(
+                "max_tokens must be greater than 0 ({})",
+                self.source("max_tokens")
+            )
+            .into());
+        }
+        for (key, seconds) in [
+            ("request_timeout_secs", self.request_timeout_secs),
+            ("tool_timeout_secs", self.tool_timeout_secs),
+        ] {
+            if seconds == 0 {
+                return Err(
+                    format!("{} must be greater than 0 ({})", key, self.source(key)).into(),
+                );
+            }
+        }
+        if !self.base_url.starts_with("http://") && !self.base_url.starts_with("https://") {
+            return Err(format!(
+                "base_url must start with http:// or https://, got {} ({})",
+                self.base_url,
+                self.source("base_url")
+            )
+            .into());
+        }
+        let known: Vec<String> = crate::tool_definitions()
+            .iter()
+            .map(|tool| tool.name().to_string())
+            .collect();
+        if let Some(unknown) = self.enabled_tools.iter().find(|tool| !known.contains(tool)) {
+            return Err(format!(
+                "Unknown tool '{}' in enabled_tools ({}). Available tools: {}",
+                unknown,
+                self.source("enabled_tools"),
+                known.join(", ")
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    pub fn source(&self, key: &str) -> ConfigSource {
+        self.sources
+            .get(key)
+            .cloned()
+            .unwrap_or(ConfigSource::Default)
+    }
+
+    pub fn tool_enabled(&self, name: &str) -> bool {
+        self.enabled_tools.iter().any(|tool| tool == name)
+    }
+
+    /// The effective configuration as TOML, each value annotated with where it came from.
+    pub fn describe(&self) -> String {
+        let mut lines = Vec::new();
+        for (key, _) in KEYS {
+            if (*key == "profile" && self.profile.is_none())
+                || (*key == "api_key_helper" && self.api_key_helper.is_none())
+            {
+                continue;
+            }
+            lines.push((format!("{} = {}", key, self.value(key)), self.source(key)));
+        }
+        let width = lines
+            .iter()
+            .map(|(line, _)| line.chars().count())
+            .max()
+            .unwrap_or(0);
+
+        let mut output = String::new();
+        for (line, source) in lines {
+            let _ = writeln!(output, "{:<width$}  # {}", line, source, width = width);
+        }
+        if !self.profiles.is_empty() {
+            let _ = writeln!(output, "# profiles: {}", self.profile_names().join(", "));
+        }
+        output.trim_end().to_string()
+    }
+
+    fn value(&self, key: &str) -> toml::Value {
+        let list = |items: &[String]| {
+            toml::Value::Array(items.iter().cloned().map(toml::Value::String).collect())
+        };
+        match key {
+            "profile" => toml::Value::String(self.profile.clone().unwrap_or_default()),
+            "provider" => toml::Value::String(self.provider.clone()),
+            "model" => toml::Value::String(self.model.clone()),
+            // Via the shortest decimal form, so 0.3 doesn't print as 0.30000001192092896
+            "temperature" => {
+                toml::Value::Float(self.temperature.to_string().parse().unwrap_or_default())
+            }
+            "max_tokens" => toml::Value::Integer(self.max_tokens as i64),
+            "base_url" => toml::Value::String(self.base_url.clone()),
+            "api_key_helper" => {
+                toml::Value::String(self.api_key_helper.clone().unwrap_or_default())
+            }
+            "request_timeout_secs" => toml::Value::Integer(self.request_timeout_secs as i64),
+            "tool_timeout_secs" => toml::Value::Integer(self.tool_timeout_secs as i64),
+            "scan_budget_tokens" => toml::Value::Integer(self.scan_budget_tokens as i64),
+            "max_read_bytes" => toml::Value::Integer(self.max_read_bytes as i64),
+            "read_budget_tokens" => toml::Value::Integer(self.read_budget_tokens as i64),
+            "enabled_tools" => list(&self.enabled_tools),
+            "ignore_patterns" => list(self.ignore_patterns.patterns()),
+            "allowed_directories" => list(&self.allowed_directories),
+            "permissions.mode" => toml::Value::String(self.permissions.mode.to_string()),
+            "permissions.allow_edits" => list(self.permissions.allow_edits.patterns()),
+            "permissions.deny" => list(self.permissions.deny.patterns()),
+            _ => unreachable!("unknown config key {}", key),
+        }
+    }
+}
+
+/// `~/.config/simple-coder/config.toml` on Linux, or the platform equivalent.
+pub fn global_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|directory| directory.join(APP_DIR_NAME).join(GLOBAL_CONFIG_FILE))
+}
+
+fn read_layer(path: &Path) -> Result<ConfigLayer, Box<dyn std::error::Error>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    toml::from_str(&text)
+        .map_err(|e| format!("Invalid configuration in {}: {}", path.display(), e).into())
+}
+
+/// Refuses settings a project file may not contain, and drops the approval settings from it,
+/// when `path` is the project file.
+fn check_layer(
+    path: &Path,
+    project_file: &Path,
+    mut layer: ConfigLayer,
+) -> Result<ConfigLayer, Box<dyn std::error::Error>> {
+    // Compared exactly: run from the home directory, the global file is also under the working directory
+    if path != project_file {
+        return Ok(layer);
+    }
+    // A checked-out project must not approve the agent's edits on the user's behalf; `deny` only restricts, so it stays
+    for permissions in std::iter::once(&mut layer.permissions).chain(
+        layer
+            .profiles
+            .values_mut()
+            .map(|profile| &mut profile.permissions),
+    ) {
+        for (key, set) in [
+            ("permissions.mode", permissions.mode.take().is_some()),
+            (
+                "permissions.allow_edits",
+                permissions.allow_edits.take().is_some(),
+            ),
+        ] {
+            if set {
+                warn!("Ignoring {} in {}: edit approval can only be configured in the global configuration, the environment or with flags", key, path.display());
+            }
+        }
+    }
+    if let Some((key, reason)) = restricted_setting(&layer) {
+        return Err(format!(
+            "{} is not allowed in {}: {}. Set it in {} instead.",
+            key,
+            path.display(),
+            reason,
+            global_config_path().map_or("the global configuration".to_string(), |global| global
+                .display()
+                .to_string())
+        )
+        .into());
+    }
+    Ok(layer)
+}
+
+/// A setting a project file may not contain, with the reason, if `layer` or one of its profiles sets one.
+fn restricted_setting(layer: &ConfigLayer) -> Option<(&'static str, &'static str)> {
+    std::iter::once(layer).chain(layer.profiles.values()).find_map(|layer| {
+        if layer.api_key_helper.is_some() {
+            Some(("api_key_helper", "a checked-out project must not run commands"))
+        } else if layer.base_url.is_some() {
+            Some(("base_url", "a checked-out project must not send requests, and the API key with them, to another server"))
+        } else if layer.allowed_directories.is_some() {
+            Some(("allowed_directories", "a checked-out project must not open the tools to directories outside it"))
+        } else {
+            None
+        }
+    })
+}
+
+fn compile_patterns(
+    key: &str,
+    patterns: &[String],
+) -> Result<PathPatterns, Box<dyn std::error::Error>> {
+    PathPatterns::new(patterns).map_err(|e| format!("Invalid pattern in {}: {}", key, e).into())
+}
+
+/// `permissions.mode` -> `SIMPLE_CODER_PERMISSIONS_MODE`
+fn env_var_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.replace('.', "_").to_uppercase())
+}
+
+/// Reads `SIMPLE_CODER_*` variables through `lookup` into a layer.
+fn env_layer(
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<ConfigLayer, Box<dyn std::error::Error>> {
+    let mut root = toml::Table::new();
+    for (key, kind) in KEYS {
+        let name = env_var_name(key);
+        let Some(raw) = lookup(&name) else {
+            continue;
+        };
+        let value = match kind {
+            ValueKind::Text => toml::Value::String(raw),
+            ValueKind::Float => toml::Value::Float(
+                raw.trim()
+                    .parse()
+                    .map_err(|_| format!("{} must be a number, got '{}'", name, raw))?,
+            ),
+            ValueKind::Integer => toml::Value::Integer(
+                raw.trim()
+                    .parse()
+                    .map_err(|_| format!("{} must be a whole number, got '{}'", name, raw))?,
+            ),
+            ValueKind::List => toml::Value::Array(
+                raw.split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(|item| toml::Value::String(item.to_string()))
+                    .collect(),
+            ),
+        };
+
+        match key.split_once('.') {
+            Some((table, field)) => {
+                let table = root
+                    .entry(table)
+                    .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                    .as_table_mut()
+                    .expect("nested config keys are tables");
+                table.insert(field.to_string(), value);
+            }
+            None => {
+                root.insert(key.to_string(), value);
+            }
+        }
+    }
+    root.try_into()
+        .map_err(|e| format!("Invalid environment configuration: {}", e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layers_and_sources() {
+        let directory = tempfile::tempdir().unwrap();
+        let global_file = directory.path().join("global.toml");
+        fs::write(
+            &global_file,
+            "model = \"claude-3-5-haiku-latest\"\n\n[permissions]\nmode = \"accept-edits\"\n",
+        )
+        .unwrap();
+        let project_file = directory.path().join(PROJECT_CONFIG_FILE);
+        fs::write(
+            &project_file,
+            "model = \"claude-opus-4\"\ntemperature = 0.2\nignore_patterns = [\"*.log\"]\n\n[permissions]\nmode = \"read-only\"\ndeny = [\".env\"]\n",
+        )
+        .unwrap();
+
+        let env = |name: &str| match name {
+            "SIMPLE_CODER_TEMPERATURE" => Some("0.7".to_string()),
+            "SIMPLE_CODER_ENABLED_TOOLS" => Some("read_file, scan_directory".to_string()),
+            _ => None,
+        };
+        let cli = ConfigLayer {
+            max_tokens: Some(4000),
+            ..ConfigLayer::default()
+        };
+        let config =
+            Config::load_from(directory.path(), Some(global_file.clone()), env, cli).unwrap();
+
+        assert_eq!(config.model, "claude-opus-4");
+        assert_eq!(config.temperature, 0.7);
+        assert_eq!(config.max_tokens, 4000);
+        // The project file's approval mode is dropped; the global file's applies
+        assert_eq!(config.permissions.mode, PermissionMode::AcceptEdits);
+        assert_eq!(
+            config.source("permissions.mode"),
+            ConfigSource::File(global_file)
+        );
+        assert!(!config.tool_enabled("str_replace_based_edit_tool"));
+        assert!(config.permissions.deny.matches(Path::new("sub/.env")));
+        assert_eq!(
+            config.source("model"),
+            ConfigSource::File(project_file.clone())
+        );
+        assert_eq!(
+            config.source("temperature"),
+            ConfigSource::Env("SIMPLE_CODER_TEMPERATURE".to_string())
+        );
+        assert_eq!(
+            config.source("max_tokens"),
+            ConfigSource::Cli("--max-tokens".to_string())
+        );
+        assert_eq!(config.source("base_url"), ConfigSource::Default);
+
+        let description = config.describe();
+        assert!(description.contains("model = \"claude-opus-4\""));
+        assert!(description.contains("# env SIMPLE_CODER_TEMPERATURE"));
+        assert!(description.contains("permissions.deny = [\".env\"]"));
+    }
+
+    #[test]
+    fn test_profiles() {
+        let directory = tempfile::tempdir().unwrap();
+        let project_file = directory.path().join(PROJECT_CONFIG_FILE);
+        fs::write(
+            &project_file,
+            "profile = \"explore\"\ntemperature = 0.2\n\n[profiles.explore]\nmodel = \"claude-3-5-haiku-latest\"\ntemperature = 0.8\nenabled_tools = [\"read_file\", \"scan_directory\"]\npermissions.mode = \"read-only\"\n\n[profiles.careful]\ntemperature = 0.1\n",
+        )
+        .unwrap();
+        let layers = Layers {
+            files: vec![(project_file.clone(), read_layer(&project_file).unwrap())],
+            env: ConfigLayer::default(),
+            cli: ConfigLayer {
+                temperature: Some(0.4),
+                ..ConfigLayer::default()
+            },
+        };
+
+        let config = Config::build(layers, None).unwrap();
+        assert_eq!(config.profile.as_deref(), Some("explore"));
+        assert_eq!(config.model, "claude-3-5-haiku-latest");
+        assert_eq!(config.permissions.mode, PermissionMode::ReadOnly);
+        assert!(!config.tool_enabled("str_replace_based_edit_tool"));
+        // Flags still win over the profile
+        assert_eq!(config.temperature, 0.4);
+        assert_eq!(
+            config.source("model"),
+            ConfigSource::Profile("explore".to_string(), project_file.clone())
+        );
+        assert_eq!(config.profile_names(), vec!["careful", "explore"]);
+
+        let careful = config.with_profile("careful").unwrap();
+        assert_eq!(careful.model, DEFAULT_MODEL);
+        assert_eq!(careful.permissions.mode, PermissionMode::Ask);
+        assert_eq!(
+            careful.source("profile"),
+            ConfigSource::Command("/profile".to_string())
+        );
+        assert!(careful.describe().contains("profile = \"careful\""));
+
+        let error = config.with_profile("offline").unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "Unknown profile 'offline'. Available profiles: careful, explore"
+        );
+    }
+
+    #[test]
+    fn test_project_file_restrictions() {
+        let directory = tempfile::tempdir().unwrap();
+        let project_file = directory.path().join(PROJECT_CONFIG_FILE);
+        // Only the project file, whatever the machine running the test has configured
+        let load = || Config::load_from(directory.path(), None, |_| None, ConfigLayer::default());
+        for setting in [
+            "base_url = \"https://example.com\"",
+            "api_key_helper = \"cat key\"",
+            "[profiles.x]\nbase_url = \"https://example.com\"",
+        ] {
+            fs::write(&project_file, setting).unwrap();
+            let error = load().unwrap_err().to_string();
+            assert!(
+                error.contains("is not allowed in") && error.contains(PROJECT_CONFIG_FILE),
+                "{}",
+                error
+            );
+        }
+        // The same keys are fine in another file that happens to be under the working directory
+        let global = directory.path().join(".config/simple-coder/config.toml");
+        fs::create_dir_all(global.parent().unwrap()).unwrap();
+        fs::write(&global, "base_url = \"https://proxy.internal\"\n").unwrap();
+        let layer = check_layer(&global, &project_file, read_layer(&global).unwrap()).unwrap();
+        assert_eq!(layer.base_url.as_deref(), Some("https://proxy.internal"));
+
+        // Approval settings in the project file are ignored, the deny list is kept
+        fs::write(
+            &project_file,
+            "[permissions]\nmode = \"accept-edits\"\nallow_edits = [\"**\"]\ndeny = [\".env\"]\n\n[profiles.fast.permissions]\nmode = \"accept-edits\"\n",
+        )
+        .unwrap();
+        let config = load().unwrap();
+        assert_eq!(config.permissions.mode, PermissionMode::Ask);
+        assert!(config.permissions.allow_edits.is_empty());
+        assert!(config.permissions.deny.matches(Path::new(".env")));
+        assert_eq!(
+            config.with_profile("fast").unwrap().permissions.mode,
+            PermissionMode::Ask
+        );
+    }
+
+    #[test]
+    fn test_invalid_configuration() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join(PROJECT_CONFIG_FILE);
+        fs::write(&path, "modle = \"typo\"\n").unwrap();
+        assert!(read_layer(&path)
+            .unwrap_err()
+            .to_string()
+            .contains("unknown field"));
+
+        assert!(
+            env_layer(|name| (name == "SIMPLE_CODER_MAX_TOKENS").then(|| "lots".to_string()))
+                .is_err()
+        );
+
+        let mut config = Config::default();
+        config
+            .apply(
+                ConfigLayer {
+                    enabled_tools: Some(vec!["shell".to_string()]),
+                    ..ConfigLayer::default()
+                },
+                |_| ConfigSource::Default,
+            )
+            .unwrap();
+        assert!(config
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("Unknown tool 'shell'"));
+
+        let mut config = Config::default();
+        config
+            .apply(
+                ConfigLayer {
+                    tool_timeout_secs: Some(0),
+                    ..ConfigLayer::default()
+                },
+                |_| ConfigSource::Default,
+            )
+            .unwrap();
+        assert!(config
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("tool_timeout_secs must be greater than 0"));
+    }
+}
@@ -0,0 +1,350 @@
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{OnceLock, RwLock};
+
+use regex::Regex;
+
+use crate::config::{self, Config};
+
+/// Environment variable (also read from `.env`) holding the key
+pub const API_KEY_ENV: &str = "ANTHROPIC_API_KEY";
+const CREDENTIALS_FILE: &str = "credentials";
+const REDACTED: &str = "[REDACTED]";
+
+/// Keys handed out by `resolve_api_key`, redacted verbatim wherever they show up in logs
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Finds the API key, trying in order: the `ANTHROPIC_API_KEY` variable, the configured
+/// `api_key_helper` command and the credentials file. When none is set up and stdin is a
+/// terminal, asks for the key and offers to save it; otherwise explains how to provide one.
+pub fn resolve_api_key(config: &Config) -> Result<String, Box<dyn std::error::Error>> {
+    let key = match std::env::var(API_KEY_ENV)
+        .ok()
+        .filter(|key| !key.trim().is_empty())
+    {
+        Some(key) => key.trim().to_string(),
+        None => match &config.api_key_helper {
+            Some(helper) => run_helper(helper)?,
+            None => match credentials_path().filter(|path| path.exists()) {
+                Some(path) => read_credentials(&path)?,
+                None if io::stdin().is_terminal() => first_run_setup()?,
+                None => return Err(missing_key_message().into()),
+            },
+        },
+    };
+    register_secret(&key);
+    Ok(key)
+}
+
+/// `~/.config/simple-coder/credentials` on Linux, or the platform equivalent.
+pub fn credentials_path() -> Option<PathBuf> {
+    config::global_config_path().map(|path| path.with_file_name(CREDENTIALS_FILE))
+}
+
+/// Runs the `api_key_helper` command through the shell and takes its trimmed output as the key.
+fn run_helper(helper: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", helper]).output()
+    } else {
+        Command::new("sh").args(["-c", helper]).output()
+    }
+    .map_err(|e| format!("Could not run api_key_helper '{}': {}", helper, e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!(
+            "api_key_helper '{}' failed ({}): {}",
+            helper,
+            output.status,
+            redact(stderr.trim())
+        )
+        .into());
+    }
+    let key = String::from_utf8(output.stdout)
+        .map_err(|_| format!("api_key_helper '{}' printed invalid UTF-8", helper))?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(format!("api_key_helper '{}' printed nothing", helper).into());
+    }
+    Ok(key.to_string())
+}
+
+/// Reads the key from the credentials file, refusing files other users can read.
+fn read_credentials(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    check_permissions(path)?;
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let key = text.trim();
+    if key.is_empty() {
+        return Err(format!(
+            "{} is empty. Put your API key in it, or delete it to run the setup again.",
+            path.display()
+        )
+        .into());
+    }
+    Ok(key.to_string())
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(path)?.permissions().mode() & 0o777;
+    if mode & 0o077 != 0 {
+        return Err(format!(
+            "{} can be read by other users (permissions {:o}). Run `chmod 600 {}` and try again.",
+            path.display(),
+            mode,
+            path.display()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    Ok(())
+}
+
+/// Writes the key to `path`, readable only by the current user.
+fn save_credentials(path: &Path, key: &str) -> io::Result<()> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    writeln!(file, "{}", key)
+}
+
+fn missing_key_message() -> String {
+    let file = credentials_path().map_or("the credentials file".to_string(), |path| {
+        path.display().to_string()
+    });
+    format!(
+        "No Anthropic API key found. Provide one in any of these ways:\n  \
+         - set {} in the environment or in a .env file\n  \
+         - set api_key_helper in the global configuration to a command that prints the key\n  \
+         - put the key in {} (readable only by you)\n\
+         or run the program once in a terminal to set it up interactively.",
+        API_KEY_ENV, file
+    )
+}
+
+/// Asks for the key on the terminal and offers to store it in the credentials file.
+fn first_run_setup() -> Result<String, Box<dyn std::error::Error>> {
+    println!("Welcome! No Anthropic API key is set up yet.");
+    println!("Create one at https://console.anthropic.com/settings/keys and paste it below.");
+    let key = read_hidden("API key (input hidden): ")?;
+    let key = key.trim().to_string();
+    if key.is_empty() {
+        return Err(missing_key_message().into());
+    }
+
+    if let Some(path) = credentials_path() {
+        print!("Save the key to {} for next time? [Y/n] ", path.display());
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if !answer.trim().eq_ignore_ascii_case("n") {
+            save_credentials(&path, &key)
+                .map_err(|e| format!("Could not save {}: {}", path.display(), e))?;
+            println!("Saved. Delete {} to forget the key.", path.display());
+        }
+    }
+    Ok(key)
+}
+
+/// Reads a line from the terminal without echoing it.
+fn read_hidden(prompt: &str) -> io::Result<String> {
+    use ratatui::crossterm::event::{
+        self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEventKind,
+        KeyModifiers,
+    };
+    use ratatui::crossterm::{execute, terminal};
+
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    execute!(io::stdout(), EnableBracketedPaste)?;
+    terminal::enable_raw_mode()?;
+    let mut line = String::new();
+    let result = loop {
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind != KeyEventKind::Release => match key.code {
+                KeyCode::Enter => break Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    break Err(io::Error::new(
+                        io::ErrorKind::Interrupted,
+                        "setup cancelled",
+                    ));
+                }
+                KeyCode::Char(_)
+                    if key
+                        .modifiers
+                        .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {}
+                KeyCode::Char(c) => line.push(c),
+                KeyCode::Backspace => {
+                    line.pop();
+                }
+                _ => {}
+            },
+            // Pasted text arrives as one event, so a pasted newline doesn't submit early
+            Ok(Event::Paste(text)) => line.push_str(&text),
+            Ok(_) => {}
+            Err(e) => break Err(e),
+        }
+    };
+    terminal::disable_raw_mode()?;
+    execute!(io::stdout(), DisableBracketedPaste)?;
+    println!();
+    result.map(|()| line)
+}
+
+/// Remembers `secret` so `redact` removes it even when it doesn't look like a key.
+pub fn register_secret(secret: &str) {
+    if secret.len() < 8 {
+        return;
+    }
+    if let Ok(mut secrets) = SECRETS.write() {
+        if !secrets.iter().any(|known| known == secret) {
+            secrets.push(secret.to_string());
+        }
+    }
+}
+
+/// Replaces API keys and the values of authorization-like headers or fields in `text`.
+pub fn redact(text: &str) -> Cow<'_, str> {
+    static PATTERNS: OnceLock<[Regex; 2]> = OnceLock::new();
+    let [keys, fields] = PATTERNS.get_or_init(|| {
+        [
+            Regex::new(r"sk-ant-[A-Za-z0-9_\-]+").expect("valid key pattern"),
+            Regex::new(r#"(?i)\b(x-api-key|api[_-]?key|authorization|proxy-authorization)("?\s*[:=]\s*"?)(bearer\s+|basic\s+)?[^\s",}]+"#)
+                .expect("valid field pattern"),
+        ]
+    });
+
+    let mut redacted = Cow::Borrowed(text);
+    if let Ok(secrets) = SECRETS.read() {
+        for secret in secrets.iter() {
+            if redacted.contains(secret.as_str()) {
+                redacted = Cow::Owned(redacted.replace(secret.as_str(), REDACTED));
+            }
+        }
+    }
+    if keys.is_match(&redacted) {
+        redacted = Cow::Owned(keys.replace_all(&redacted, REDACTED).into_owned());
+    }
+    if fields.is_match(&redacted) {
+        redacted = Cow::Owned(
+            fields
+                .replace_all(&redacted, format!("${{1}}${{2}}${{3}}{}", REDACTED))
+                .into_owned(),
+        );
+    }
+    redacted
+}
+
+/// Sets up `env_logger` (configured by `RUST_LOG`) with every message passed through `redact`.
+///
+/// Logs go to `log_file` when given, as the full-screen UI needs, and are dropped if it can't be opened.
+pub fn init_logger(log_file: Option<&Path>) {
+    let mut builder = env_logger::Builder::from_default_env();
+    builder.format(|buf, record| {
+        let message = record.args().to_string();
+        writeln!(
+            buf,
+            "[{} {} {}] {}",
+            buf.timestamp(),
+            record.level(),
+            record.target(),
+            redact(&message)
+        )
+    });
+    if let Some(path) = log_file {
+        let file = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::OpenOptions::new().create(true).append(true).open(path));
+        match file {
+            Ok(file) => {
+                builder.target(env_logger::Target::Pipe(Box::new(file)));
+            }
+            // Nowhere to log without drawing over the screen
+            Err(_) => return,
+        }
+    }
+    builder.init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(
+            redact("using sk-ant-api03-abc_DEF-123 now"),
+            "using [REDACTED] now"
+        );
+        assert_eq!(redact("x-api-key: abc123def"), "x-api-key: [REDACTED]");
+        assert_eq!(
+            redact("Authorization: Bearer abc.def.ghi"),
+            "Authorization: Bearer [REDACTED]"
+        );
+        assert_eq!(
+            redact(r#"{"api_key":"hunter22","model":"m"}"#),
+            r#"{"api_key":"[REDACTED]","model":"m"}"#
+        );
+        assert!(matches!(redact("nothing secret here"), Cow::Borrowed(_)));
+
+        register_secret("custom-gateway-token-42");
+        assert_eq!(
+            redact("token custom-gateway-token-42 leaked"),
+            "token [REDACTED] leaked"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_credentials_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("nested").join(CREDENTIALS_FILE);
+        save_credentials(&path, "sk-ant-test-key").unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        assert_eq!(read_credentials(&path).unwrap(), "sk-ant-test-key");
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(read_credentials(&path)
+            .unwrap_err()
+            .to_string()
+            .contains("chmod 600"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_helper() {
+        assert_eq!(run_helper("echo '  from-helper  '").unwrap(), "from-helper");
+        assert!(run_helper("echo oops >&2; exit 3")
+            .unwrap_err()
+            .to_string()
+            .contains("oops"));
+        assert!(run_helper("true")
+            .unwrap_err()
+            .to_string()
+            .contains("printed nothing"));
+    }
+}
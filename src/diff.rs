@@ -0,0 +1,175 @@
+use similar::{ChangeTag, TextDiff};
+
+/// Diffs longer than this are truncated in the summary sent back to the model
+const MAX_MODEL_DIFF_LINES: usize = 80;
+const CONTEXT_LINES: usize = 3;
+
+/// A change made to one file, as a unified diff against its previous content.
+#[derive(Debug, Clone)]
+pub struct FileDiff {
+    pub path: String,
+    pub unified: String,
+    pub added: usize,
+    pub removed: usize,
+    /// The file did not exist before the change
+    pub created: bool,
+}
+
+impl FileDiff {
+    /// Diffs `old` against `new`; `old` is `None` for newly created files.
+    pub fn new(path: &str, old: Option<&str>, new: &str) -> Self {
+        let old_text = old.unwrap_or("");
+        let text_diff = TextDiff::from_lines(old_text, new);
+        let mut added = 0;
+        let mut removed = 0;
+        for change in text_diff.iter_all_changes() {
+            match change.tag() {
+                ChangeTag::Insert => added += 1,
+                ChangeTag::Delete => removed += 1,
+                ChangeTag::Equal => {}
+            }
+        }
+        let old_header = if old.is_some() {
+            format!("a/{}", path)
+        } else {
+            "/dev/null".to_string()
+        };
+        let unified = text_diff
+            .unified_diff()
+            .context_radius(CONTEXT_LINES)
+            .header(&old_header, &format!("b/{}", path))
+            .to_string();
+
+        Self {
+            path: path.to_string(),
+            unified,
+            added,
+            removed,
+            created: old.is_none(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added == 0 && self.removed == 0
+    }
+
+    /// Short "+3 -1" style line count summary.
+    pub fn stats(&self) -> String {
+        format!("+{} -{}", self.added, self.removed)
+    }
+
+    /// Compact description of the change for the model, instead of the whole new file.
+    ///
+    /// New files are summarised by line count only, since the model wrote the content itself.
+    pub fn summary_for_model(&self) -> String {
+        if self.created {
+            return format!(
+                "Created {} ({} {})",
+                self.path,
+                self.added,
+                if self.added == 1 { "line" } else { "lines" }
+            );
+        }
+        if self.is_empty() {
+            return format!("No changes to {}", self.path);
+        }
+
+        let lines: Vec<&str> = self.unified.lines().collect();
+        let changed = self.added.max(self.removed);
+        let mut summary = format!(
+            "Changed {} ({} {})\n",
+            self.path,
+            self.stats(),
+            if changed == 1 { "line" } else { "lines" }
+        );
+        summary.push_str(&lines[..lines.len().min(MAX_MODEL_DIFF_LINES)].join("\n"));
+        if lines.len() > MAX_MODEL_DIFF_LINES {
+            summary.push_str(&format!(
+                "\n... diff truncated, {} more lines. View the file to see the rest.",
+                lines.len() - MAX_MODEL_DIFF_LINES
+            ));
+        }
+        summary
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffLineKind {
+    FileHeader,
+    HunkHeader,
+    Added,
+    Removed,
+    Context,
+}
+
+pub fn classify_line(line: &str) -> DiffLineKind {
+    if line.starts_with("+++") || line.starts_with("---") {
+        DiffLineKind::FileHeader
+    } else if line.starts_with("@@") {
+        DiffLineKind::HunkHeader
+    } else if line.starts_with('+') {
+        DiffLineKind::Added
+    } else if line.starts_with('-') {
+        DiffLineKind::Removed
+    } else {
+        DiffLineKind::Context
+    }
+}
+
+/// Adds ANSI colors to a unified diff: green additions, red removals, cyan hunk headers.
+pub fn colorize(unified: &str, color: bool) -> String {
+    if !color {
+        return unified.to_string();
+    }
+    unified
+        .lines()
+        .map(|line| {
+            let code = match classify_line(line) {
+                DiffLineKind::FileHeader => "1",
+                DiffLineKind::HunkHeader => "36",
+                DiffLineKind::Added => "32",
+                DiffLineKind::Removed => "31",
+                DiffLineKind::Context => return line.to_string(),
+            };
+            format!("\x1b[{}m{}\x1b[0m", code, line)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_diff_counts_and_summary() {
+        let diff = FileDiff::new("src/lib.rs", Some("a\nb\nc\n"), "a\nB\nc\nd\n");
+        assert_eq!((diff.added, diff.removed), (2, 1));
+        assert!(diff.unified.contains("--- a/src/lib.rs"));
+        assert!(diff.unified.contains("+B"));
+        let summary = diff.summary_for_model();
+        assert!(summary.starts_with("Changed src/lib.rs (+2 -1 lines)"));
+        assert!(summary.contains("-b"));
+
+        let created = FileDiff::new("new.rs", None, "x\ny\n");
+        assert!(created.unified.contains("--- /dev/null"));
+        assert_eq!(created.summary_for_model(), "Created new.rs (2 lines)");
+        let one_line = FileDiff::new("one.rs", Some("a\n"), "b\n");
+        assert!(one_line
+            .summary_for_model()
+            .starts_with("Changed one.rs (+1 -1 line)\n"));
+        assert_eq!(
+            FileDiff::new("one.rs", None, "a\n").summary_for_model(),
+            "Created one.rs (1 line)"
+        );
+    }
+
+    #[test]
+    fn test_colorize() {
+        let diff = FileDiff::new("f", Some("a\n"), "b\n");
+        let colored = colorize(&diff.unified, true);
+        assert!(colored.contains("\x1b[31m-a\x1b[0m"));
+        assert!(colored.contains("\x1b[32m+b\x1b[0m"));
+        assert_eq!(colorize(&diff.unified, false), diff.unified);
+    }
+}
@@ -0,0 +1,691 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde_json::Value;
+
+use crate::diff::{classify_line, DiffLineKind};
+use crate::file_tracker::CHANGES_NOTICE;
+use crate::markdown;
+use crate::session::SessionRecord;
+
+/// Tool outputs longer than this are collapsed by default
+pub const DEFAULT_COLLAPSE_LINES: usize = 20;
+
+/// Tools whose results are file contents, hidden when redacting
+const FILE_CONTENT_TOOLS: &[&str] = &["read_file", "str_replace_based_edit_tool"];
+/// Tool input fields that hold file contents, hidden when redacting
+const FILE_CONTENT_FIELDS: &[&str] = &["file_text", "old_str", "new_str"];
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ExportFormat {
+    Markdown,
+    Html,
+}
+
+impl ExportFormat {
+    /// Picks the format from a file extension: `.html`/`.htm` is HTML, anything else Markdown.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension)
+                if extension.eq_ignore_ascii_case("html")
+                    || extension.eq_ignore_ascii_case("htm") =>
+            {
+                ExportFormat::Html
+            }
+            _ => ExportFormat::Markdown,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    /// Tool results with more lines than this are collapsed; 0 keeps everything expanded
+    pub collapse_lines: usize,
+    /// Replace file contents in tool inputs, results, diffs and attachments with a line count
+    pub redact_file_contents: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            format: ExportFormat::Markdown,
+            collapse_lines: DEFAULT_COLLAPSE_LINES,
+            redact_file_contents: false,
+        }
+    }
+}
+
+/// Works out where an export goes and in which format.
+///
+/// An explicit format wins, then the file extension; without a path the file is `<session id>.<ext>`.
+pub fn resolve_output(
+    session_id: &str,
+    path: Option<&Path>,
+    format: Option<ExportFormat>,
+) -> (PathBuf, ExportFormat) {
+    let format = format.unwrap_or_else(|| {
+        path.map(ExportFormat::from_path)
+            .unwrap_or(ExportFormat::Markdown)
+    });
+    let path = path
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from(format!("{}.{}", session_id, format.extension())));
+    (path, format)
+}
+
+/// One block of the exported document.
+enum Entry {
+    User(String),
+    Assistant(String),
+    ToolCall {
+        name: String,
+        input: String,
+    },
+    ToolResult {
+        name: String,
+        success: bool,
+        content: String,
+    },
+    Diff {
+        path: String,
+        diff: String,
+    },
+    Note(String),
+}
+
+/// A session transcript reduced to what gets exported.
+struct Transcript {
+    id: String,
+    title: String,
+    started: Option<String>,
+    working_dir: Option<String>,
+    /// Model the session started with; later switches show up as notes
+    model: Option<String>,
+    entries: Vec<Entry>,
+}
+
+/// Renders a session transcript as a Markdown or self-contained HTML document.
+pub fn export(records: &[SessionRecord], options: &ExportOptions) -> String {
+    let transcript = build_transcript(records, options);
+    match options.format {
+        ExportFormat::Markdown => to_markdown(&transcript, options),
+        ExportFormat::Html => to_html(&transcript, options),
+    }
+}
+
+fn build_transcript(records: &[SessionRecord], options: &ExportOptions) -> Transcript {
+    let mut transcript = Transcript {
+        id: String::new(),
+        title: "Untitled session".to_string(),
+        started: None,
+        working_dir: None,
+        model: None,
+        entries: Vec::new(),
+    };
+
+    let mut current_model: Option<&str> = None;
+    for record in records {
+        match record {
+            SessionRecord::Start {
+                id,
+                created_at,
+                working_dir,
+            } => {
+                transcript.id = id.clone();
+                transcript.started = Some(
+                    created_at
+                        .with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M")
+                        .to_string(),
+                );
+                transcript.working_dir = Some(working_dir.clone());
+            }
+            SessionRecord::Settings { model, .. } => {
+                match current_model {
+                    None => transcript.model = Some(model.clone()),
+                    Some(current) if current != model => transcript
+                        .entries
+                        .push(Entry::Note(format!("Switched model to {}", model))),
+                    Some(_) => {}
+                }
+                current_model = Some(model);
+            }
+            SessionRecord::Title { title } => transcript.title = title.clone(),
+            // Tool calls and results have records of their own; a message holding only those adds nothing
+            SessionRecord::Message(message) if message.content.text().is_empty() => {}
+            SessionRecord::Message(message) if message.role == "assistant" => transcript
+                .entries
+                .push(Entry::Assistant(message.content.text())),
+            SessionRecord::Message(message) => {
+                let text = message.content.text();
+                let text = if options.redact_file_contents {
+                    redact_user_text(&text)
+                } else {
+                    text
+                };
+                transcript.entries.push(Entry::User(text));
+            }
+            SessionRecord::Reset { messages } => {
+                transcript.entries.push(Entry::Note(format!(
+                    "Conversation replaced ({} messages)",
+                    messages.len()
+                )));
+            }
+            SessionRecord::ToolCall { name, input, .. } => {
+                let mut input = input.clone();
+                if options.redact_file_contents {
+                    redact_input(&mut input);
+                }
+                transcript.entries.push(Entry::ToolCall {
+                    name: name.clone(),
+                    input: serde_json::to_string_pretty(&input).unwrap_or_default(),
+                });
+            }
+            SessionRecord::ToolResult {
+                name,
+                success,
+                content,
+                ..
+            } => {
+                let content = if options.redact_file_contents
+                    && *success
+                    && FILE_CONTENT_TOOLS.contains(&name.as_str())
+                {
+                    redact_result(content)
+                } else {
+                    content.clone()
+                };
+                transcript.entries.push(Entry::ToolResult {
+                    name: name.clone(),
+                    success: *success,
+                    content,
+                });
+            }
+            SessionRecord::FileDiff { path, diff } => {
+                let diff = if options.redact_file_contents {
+                    redact_diff(diff)
+                } else {
+                    diff.clone()
+                };
+                transcript.entries.push(Entry::Diff {
+                    path: path.clone(),
+                    diff,
+                });
+            }
+        }
+    }
+    transcript
+}
+
+fn redacted_line_count(text: &str) -> String {
+    format!("[redacted: {} lines]", text.lines().count())
+}
+
+fn redact_input(input: &mut Value) {
+    if let Some(fields) = input.as_object_mut() {
+        for (key, value) in fields.iter_mut() {
+            if let (true, Some(text)) =
+                (FILE_CONTENT_FIELDS.contains(&key.as_str()), value.as_str())
+            {
+                *value = Value::String(redacted_line_count(text));
+            }
+        }
+    }
+}
+
+/// Keeps the first line (the status message) and drops the file contents after it.
+fn redact_result(content: &str) -> String {
+    match content.split_once('\n') {
+        Some((first, rest)) if !rest.trim().is_empty() => {
+            format!("{}\n{}", first.trim_end(), redacted_line_count(rest))
+        }
+        _ => content.to_string(),
+    }
+}
+
+/// Opening and closing tags of the blocks in user messages that carry file or piped contents
+const REDACTED_BLOCKS: &[(&str, &str)] = &[
+    ("<attached_file ", "</attached_file>"),
+    ("<stdin>", "</stdin>"),
+];
+
+/// Replaces the contents of `<attached_file>` and `<stdin>` blocks with a line count, keeping the
+/// tags, and reduces a notice about outside changes to the summary line of each file.
+fn redact_user_text(text: &str) -> String {
+    if let Some(summaries) = text.strip_prefix(CHANGES_NOTICE) {
+        let summaries: Vec<String> = summaries
+            .trim_start_matches('\n')
+            .split("\n\n")
+            .map(redact_result)
+            .collect();
+        return format!("{}\n\n{}", CHANGES_NOTICE, summaries.join("\n\n"));
+    }
+    let mut redacted = String::new();
+    let mut rest = text;
+    while let Some((start, close)) = REDACTED_BLOCKS
+        .iter()
+        .filter_map(|(open, close)| rest.find(open).map(|start| (start, close)))
+        .min_by_key(|(start, _)| *start)
+    {
+        let Some(body_start) = rest[start..].find('>').map(|end| start + end + 1) else {
+            break;
+        };
+        let Some(body_end) = rest[body_start..].find(close).map(|end| body_start + end) else {
+            break;
+        };
+        redacted.push_str(&rest[..body_start]);
+        let _ = write!(
+            redacted,
+            "\n{}\n",
+            redacted_line_count(rest[body_start..body_end].trim_matches('\n'))
+        );
+        rest = &rest[body_end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+/// Keeps the file headers and replaces the hunks with added/removed line counts.
+fn redact_diff(diff: &str) -> String {
+    let mut headers = Vec::new();
+    let (mut added, mut removed) = (0, 0);
+    for line in diff.lines() {
+        match classify_line(line) {
+            DiffLineKind::FileHeader => headers.push(line),
+            DiffLineKind::Added => added += 1,
+            DiffLineKind::Removed => removed += 1,
+            DiffLineKind::HunkHeader | DiffLineKind::Context => {}
+        }
+    }
+    format!(
+        "{}\n[redacted: +{} -{} lines]",
+        headers.join("\n"),
+        added,
+        removed
+    )
+}
+
+fn is_collapsed(content: &str, options: &ExportOptions) -> bool {
+    options.collapse_lines > 0 && content.lines().count() > options.collapse_lines
+}
+
+fn result_label(name: &str, success: bool, content: &str) -> String {
+    let status = if success { "Result" } else { "Failed" };
+    format!("{} of {} ({} lines)", status, name, content.lines().count())
+}
+
+/// A backtick fence longer than any run of backticks inside `content`.
+fn code_fence(content: &str) -> String {
+    let longest_run = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat(longest_run.max(2) + 1)
+}
+
+fn fenced(content: &str, language: &str) -> String {
+    let fence = code_fence(content);
+    format!(
+        "{}{}\n{}\n{}\n",
+        fence,
+        language,
+        content.trim_end_matches('\n'),
+        fence
+    )
+}
+
+fn to_markdown(transcript: &Transcript, options: &ExportOptions) -> String {
+    let mut output = String::new();
+    let _ = writeln!(output, "# {}\n", transcript.title);
+    let _ = writeln!(output, "- Session: `{}`", transcript.id);
+    if let Some(started) = &transcript.started {
+        let _ = writeln!(output, "- Started: {}", started);
+    }
+    if let Some(working_dir) = &transcript.working_dir {
+        let _ = writeln!(output, "- Project: `{}`", working_dir);
+    }
+    if let Some(model) = &transcript.model {
+        let _ = writeln!(output, "- Model: `{}`", model);
+    }
+
+    for entry in &transcript.entries {
+        output.push('\n');
+        match entry {
+            Entry::User(text) => {
+                let _ = writeln!(output, "## User\n\n{}", text.trim_end());
+            }
+            Entry::Assistant(text) => {
+                let _ = writeln!(output, "## Assistant\n\n{}", text.trim_end());
+            }
+            Entry::ToolCall { name, input } => {
+                let _ = write!(
+                    output,
+                    "**Tool call:** `{}`\n\n{}",
+                    name,
+                    fenced(input, "json")
+                );
+            }
+            Entry::ToolResult {
+                name,
+                success,
+                content,
+            } => {
+                let label = result_label(name, *success, content);
+                if is_collapsed(content, options) {
+                    let _ = write!(
+                        output,
+                        "<details>\n<summary>{}</summary>\n\n{}\n</details>\n",
+                        label,
+                        fenced(content, "text")
+                    );
+                } else {
+                    let _ = write!(output, "**{}:**\n\n{}", label, fenced(content, "text"));
+                }
+            }
+            Entry::Diff { path, diff } => {
+                let _ = write!(output, "**Changed** `{}`\n\n{}", path, fenced(diff, "diff"));
+            }
+            Entry::Note(text) => {
+                let _ = writeln!(output, "_{}_", text);
+            }
+        }
+    }
+    output
+}
+
+const HTML_STYLE: &str = "
+body { font-family: -apple-system, 'Segoe UI', Helvetica, Arial, sans-serif; line-height: 1.5; color: #24292f; background: #f6f8fa; margin: 0; }
+main { max-width: 900px; margin: 0 auto; padding: 2rem 1rem; }
+section { background: #fff; border: 1px solid #d0d7de; border-radius: 6px; padding: 0.5rem 1rem; margin: 1rem 0; }
+section.user { border-left: 4px solid #0969da; }
+section.assistant { border-left: 4px solid #8250df; }
+section.tool { border-left: 4px solid #9a6700; font-size: 0.9em; }
+h2 { font-size: 0.85em; text-transform: uppercase; letter-spacing: 0.05em; color: #57606a; margin: 0.25rem 0; }
+.user-text { white-space: pre-wrap; }
+pre { background: #f6f8fa; border-radius: 6px; padding: 0.75rem; overflow-x: auto; }
+code { font-family: ui-monospace, SFMono-Regular, Menlo, monospace; }
+.meta { color: #57606a; list-style: none; padding: 0; }
+.failed { color: #cf222e; }
+.note { color: #57606a; font-style: italic; }
+.diff-add { color: #116329; background: #dafbe1; }
+.diff-del { color: #82071e; background: #ffebe9; }
+.diff-hunk { color: #0550ae; }
+.diff-file { font-weight: bold; }
+";
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn html_diff(diff: &str) -> String {
+    diff.lines()
+        .map(|line| {
+            let class = match classify_line(line) {
+                DiffLineKind::FileHeader => "diff-file",
+                DiffLineKind::HunkHeader => "diff-hunk",
+                DiffLineKind::Added => "diff-add",
+                DiffLineKind::Removed => "diff-del",
+                DiffLineKind::Context => return escape_html(line),
+            };
+            format!("<span class=\"{}\">{}</span>", class, escape_html(line))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn to_html(transcript: &Transcript, options: &ExportOptions) -> String {
+    let mut body = String::new();
+    let _ = writeln!(
+        body,
+        "<h1>{}</h1>\n<ul class=\"meta\">",
+        escape_html(&transcript.title)
+    );
+    let _ = writeln!(
+        body,
+        "<li>Session: <code>{}</code></li>",
+        escape_html(&transcript.id)
+    );
+    if let Some(started) = &transcript.started {
+        let _ = writeln!(body, "<li>Started: {}</li>", escape_html(started));
+    }
+    if let Some(working_dir) = &transcript.working_dir {
+        let _ = writeln!(
+            body,
+            "<li>Project: <code>{}</code></li>",
+            escape_html(working_dir)
+        );
+    }
+    if let Some(model) = &transcript.model {
+        let _ = writeln!(body, "<li>Model: <code>{}</code></li>", escape_html(model));
+    }
+    body.push_str("</ul>\n");
+
+    for entry in &transcript.entries {
+        match entry {
+            Entry::User(text) => {
+                let _ = writeln!(body, "<section class=\"user\"><h2>User</h2><div class=\"user-text\">{}</div></section>", escape_html(text.trim_end()));
+            }
+            Entry::Assistant(text) => {
+                let _ = writeln!(
+                    body,
+                    "<section class=\"assistant\"><h2>Assistant</h2>{}</section>",
+                    markdown::to_html(text)
+                );
+            }
+            Entry::ToolCall { name, input } => {
+                let _ = writeln!(
+                    body,
+                    "<section class=\"tool\"><h2>Tool call: {}</h2><pre><code>{}</code></pre></section>",
+                    escape_html(name),
+                    escape_html(input)
+                );
+            }
+            Entry::ToolResult {
+                name,
+                success,
+                content,
+            } => {
+                let label = escape_html(&result_label(name, *success, content));
+                let class = if *success { "" } else { " class=\"failed\"" };
+                let pre = format!(
+                    "<pre><code>{}</code></pre>",
+                    escape_html(content.trim_end())
+                );
+                if is_collapsed(content, options) {
+                    let _ = writeln!(body, "<section class=\"tool\"><details><summary{}>{}</summary>{}</details></section>", class, label, pre);
+                } else {
+                    let _ = writeln!(
+                        body,
+                        "<section class=\"tool\"><h2{}>{}</h2>{}</section>",
+                        class, label, pre
+                    );
+                }
+            }
+            Entry::Diff { path, diff } => {
+                let _ = writeln!(
+                    body,
+                    "<section class=\"tool\"><h2>Changed {}</h2><pre><code>{}</code></pre></section>",
+                    escape_html(path),
+                    html_diff(diff)
+                );
+            }
+            Entry::Note(text) => {
+                let _ = writeln!(body, "<p class=\"note\">{}</p>", escape_html(text));
+            }
+        }
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<main>\n{}</main>\n</body>\n</html>\n",
+        escape_html(&transcript.title),
+        HTML_STYLE,
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::FileDiff;
+    use crate::Message;
+    use serde_json::json;
+
+    fn sample_records() -> Vec<SessionRecord> {
+        vec![
+            SessionRecord::Start { id: "s1".to_string(), created_at: chrono::Utc::now(), working_dir: "/work".to_string() },
+            SessionRecord::Settings { model: "claude-sonnet-4".to_string(), temperature: 0.5, max_tokens: 100 },
+            SessionRecord::Message(Message { role: "user".to_string(), content: "Show me <main.rs>".to_string().into() }),
+            SessionRecord::Title { title: "Show me main.rs".to_string() },
+            SessionRecord::ToolCall { id: "t1".to_string(), name: "read_file".to_string(), input: json!({ "file_path": "main.rs" }) },
+            SessionRecord::ToolResult {
+                id: "t1".to_string(),
+                name: "read_file".to_string(),
+                success: true,
+                content: "Here are the contents of the file main.rs :\nfn main() {\n    println!(\"```\");\n}".to_string(),
+            },
+            SessionRecord::ToolCall {
+                id: "t2".to_string(),
+                name: "str_replace_based_edit_tool".to_string(),
+                input: json!({ "command": "create", "path": "a.txt", "file_text": "secret\nstuff\n" }),
+            },
+            SessionRecord::FileDiff { path: "a.txt".to_string(), diff: "--- /dev/null\n+++ b/a.txt\n@@ -0,0 +1,2 @@\n+secret\n+stuff\n".to_string() },
+            SessionRecord::Message(Message { role: "assistant".to_string(), content: "It **prints** a fence.".to_string().into() }),
+        ]
+    }
+
+    #[test]
+    fn test_markdown_export() {
+        let options = ExportOptions {
+            collapse_lines: 2,
+            ..ExportOptions::default()
+        };
+        let markdown = export(&sample_records(), &options);
+        assert!(markdown.starts_with("# Show me main.rs\n"));
+        assert!(markdown.contains("## User\n\nShow me <main.rs>"));
+        assert!(markdown.contains("**Tool call:** `read_file`"));
+        // Three-line result is collapsed, and its fence outgrows the backticks inside it
+        assert!(markdown.contains("<summary>Result of read_file (4 lines)</summary>"));
+        assert!(markdown.contains("````text\n"));
+        assert!(markdown.contains("```diff\n--- /dev/null"));
+        assert!(markdown.contains("## Assistant\n\nIt **prints** a fence."));
+
+        let redacted = export(
+            &sample_records(),
+            &ExportOptions {
+                redact_file_contents: true,
+                ..ExportOptions::default()
+            },
+        );
+        assert!(!redacted.contains("secret"));
+        assert!(!redacted.contains("fn main"));
+        assert!(
+            redacted.contains("Here are the contents of the file main.rs :\n[redacted: 3 lines]")
+        );
+        assert!(redacted.contains("[redacted: +2 -0 lines]"));
+    }
+
+    #[test]
+    fn test_redacted_attachments() {
+        let text = "Why does this fail?\n\n<attached_file path=\"src/main.rs\" lines=\"1-2\">\nfn main() {\n    secret();\n</attached_file>\n\n\
+                    <attached_directory path=\"src/\">\nmain.rs\n</attached_directory>\n\n\
+                    <stdin>\npassword=hunter2\nerror: boom\n</stdin>";
+        let mut records = sample_records();
+        records.insert(
+            2,
+            SessionRecord::Message(Message {
+                role: "user".to_string(),
+                content: text.to_string().into(),
+            }),
+        );
+
+        let redacted = export(
+            &records,
+            &ExportOptions {
+                redact_file_contents: true,
+                ..ExportOptions::default()
+            },
+        );
+        assert!(!redacted.contains("secret()"));
+        assert!(redacted.contains("Why does this fail?\n\n<attached_file path=\"src/main.rs\" lines=\"1-2\">\n[redacted: 2 lines]\n</attached_file>"));
+        assert!(
+            redacted.contains("<attached_directory path=\"src/\">\nmain.rs\n</attached_directory>")
+        );
+        assert!(!redacted.contains("hunter2"));
+        assert!(redacted.contains("<stdin>\n[redacted: 2 lines]\n</stdin>"));
+        assert!(export(&records, &ExportOptions::default()).contains("    secret();"));
+    }
+
+    #[test]
+    fn test_redacted_change_notices() {
+        let changed = FileDiff::new(
+            "src/main.rs",
+            Some("fn main() {}\n"),
+            "fn main() {\n    secret();\n}\n",
+        )
+        .summary_for_model();
+        let created = FileDiff::new("notes.md", None, "private\n").summary_for_model();
+        let notice = format!("{}\n\n{}\n\n{}", CHANGES_NOTICE, changed, created);
+        let mut records = sample_records();
+        records.insert(
+            2,
+            SessionRecord::Message(Message {
+                role: "user".to_string(),
+                content: notice.into(),
+            }),
+        );
+
+        let redacted = export(
+            &records,
+            &ExportOptions {
+                redact_file_contents: true,
+                ..ExportOptions::default()
+            },
+        );
+        assert!(!redacted.contains("secret()"));
+        assert!(redacted.contains(&format!("{}\n\nChanged src/main.rs (", CHANGES_NOTICE)));
+        assert!(redacted.contains("lines)\n[redacted: "));
+        assert!(redacted.contains("Created notes.md (1 line)"));
+    }
+
+    #[test]
+    fn test_html_export() {
+        let options = ExportOptions {
+            format: ExportFormat::Html,
+            ..ExportOptions::default()
+        };
+        let html = export(&sample_records(), &options);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("Show me &lt;main.rs&gt;"));
+        assert!(html.contains("<strong>prints</strong>"));
+        assert!(html.contains("<span class=\"diff-add\">+secret</span>"));
+        assert_eq!(
+            ExportFormat::from_path(Path::new("out.HTML")),
+            ExportFormat::Html
+        );
+        assert_eq!(
+            ExportFormat::from_path(Path::new("out.md")),
+            ExportFormat::Markdown
+        );
+        assert_eq!(
+            resolve_output("s1", None, Some(ExportFormat::Html)),
+            (PathBuf::from("s1.html"), ExportFormat::Html)
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Serialize;
+
+use crate::text_file;
+
+/// Files larger than this are not read just to count their lines
+const MAX_LINE_COUNT_BYTES: u64 = 2_000_000;
+
+const LANGUAGES: &[(&str, &str)] = &[
+    ("rs", "Rust"),
+    ("py", "Python"),
+    ("js", "JavaScript"),
+    ("jsx", "JavaScript"),
+    ("mjs", "JavaScript"),
+    ("ts", "TypeScript"),
+    ("tsx", "TypeScript"),
+    ("go", "Go"),
+    ("java", "Java"),
+    ("kt", "Kotlin"),
+    ("c", "C"),
+    ("h", "C"),
+    ("cc", "C++"),
+    ("cpp", "C++"),
+    ("hpp", "C++"),
+    ("cs", "C#"),
+    ("rb", "Ruby"),
+    ("php", "PHP"),
+    ("swift", "Swift"),
+    ("scala", "Scala"),
+    ("sh", "Shell"),
+    ("sql", "SQL"),
+    ("html", "HTML"),
+    ("css", "CSS"),
+    ("md", "Markdown"),
+    ("toml", "TOML"),
+    ("json", "JSON"),
+    ("yaml", "YAML"),
+    ("yml", "YAML"),
+    ("xml", "XML"),
+];
+
+/// The language of a file, guessed from its extension.
+pub fn language(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .map(|(_, language)| *language)
+}
+
+/// Number of lines in a text file of `size` bytes; `None` for binary or very large files.
+pub fn count_lines(path: &Path, size: u64) -> Option<usize> {
+    if size > MAX_LINE_COUNT_BYTES {
+        return None;
+    }
+    let bytes = fs::read(path).ok()?;
+    if text_file::is_binary(&bytes) {
+        return None;
+    }
+    let newlines = bytes.iter().filter(|&&byte| byte == b'\n').count();
+    Some(if bytes.last().is_some_and(|&byte| byte != b'\n') {
+        newlines + 1
+    } else {
+        newlines
+    })
+}
+
+/// How a file differs from what git has committed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GitStatus {
+    Modified,
+    Added,
+    Deleted,
+    Renamed,
+    Untracked,
+    Conflicted,
+}
+
+impl GitStatus {
+    /// Reads the two-letter code of `git status --porcelain`.
+    fn from_code(code: &str) -> Option<Self> {
+        let mut letters = code.chars();
+        let (index, worktree) = (letters.next()?, letters.next()?);
+        Some(match (index, worktree) {
+            ('?', '?') => GitStatus::Untracked,
+            ('U', _) | (_, 'U') | ('A', 'A') | ('D', 'D') => GitStatus::Conflicted,
+            ('R', _) | (_, 'R') => GitStatus::Renamed,
+            ('A', _) => GitStatus::Added,
+            ('D', _) | (_, 'D') => GitStatus::Deleted,
+            (' ', ' ') => return None,
+            _ => GitStatus::Modified,
+        })
+    }
+}
+
+/// The git status of every changed file under a directory. Empty outside a git repository.
+#[derive(Debug, Default)]
+pub struct GitStatuses {
+    /// Keyed by path relative to the directory the statuses were loaded for
+    statuses: HashMap<PathBuf, GitStatus>,
+}
+
+impl GitStatuses {
+    pub fn load(root: &Path) -> Self {
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .arg("-C")
+                .arg(root)
+                .args(args)
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .map(|output| output.stdout)
+        };
+        let Some(toplevel) = git(&["rev-parse", "--show-toplevel"]) else {
+            return Self::default();
+        };
+        let toplevel = PathBuf::from(String::from_utf8_lossy(&toplevel).trim());
+        let Some(output) = git(&["status", "--porcelain=v1", "-z", "--untracked-files=all"]) else {
+            return Self::default();
+        };
+        // Paths are relative to the repository root, which may be above `root`
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        let toplevel = toplevel.canonicalize().unwrap_or(toplevel);
+        Self::parse(&String::from_utf8_lossy(&output), &toplevel, &root)
+    }
+
+    /// Parses `git status --porcelain=v1 -z` output, whose paths are relative to `toplevel`.
+    fn parse(output: &str, toplevel: &Path, root: &Path) -> Self {
+        let mut statuses = HashMap::new();
+        let mut fields = output.split('\0').filter(|field| !field.is_empty());
+        while let Some(field) = fields.next() {
+            let (Some(code), Some(path)) = (field.get(..2), field.get(3..)) else {
+                continue;
+            };
+            // A rename is followed by the original path
+            if code.contains('R') {
+                fields.next();
+            }
+            if let (Some(status), Ok(relative)) = (
+                GitStatus::from_code(code),
+                toplevel.join(path).strip_prefix(root),
+            ) {
+                statuses.insert(relative.to_path_buf(), status);
+            }
+        }
+        Self { statuses }
+    }
+
+    pub fn get(&self, relative_path: &Path) -> Option<GitStatus> {
+        self.statuses.get(relative_path).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata() {
+        let directory = tempfile::tempdir().unwrap();
+        let text = directory.path().join("main.rs");
+        fs::write(&text, "fn main() {\n}").unwrap();
+        let binary = directory.path().join("logo.png");
+        fs::write(&binary, [0x89, b'P', b'N', b'G', 0, 1]).unwrap();
+        assert_eq!(count_lines(&text, 13), Some(2));
+        assert_eq!(count_lines(&binary, 6), None);
+        assert_eq!(language(&text), Some("Rust"));
+        assert_eq!(language(&binary), None);
+
+        let statuses = GitStatuses::parse(
+            " M src/main.rs\0?? notes.txt\0R  src/new.rs\0src/old.rs\0UU Cargo.lock\0M  other/x.rs\0",
+            Path::new("/repo"),
+            Path::new("/repo/src"),
+        );
+        assert_eq!(
+            statuses.get(Path::new("main.rs")),
+            Some(GitStatus::Modified)
+        );
+        assert_eq!(statuses.get(Path::new("new.rs")), Some(GitStatus::Renamed));
+        assert_eq!(statuses.get(Path::new("old.rs")), None);
+        assert_eq!(statuses.statuses.len(), 2);
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use log::warn;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::diff::FileDiff;
+use crate::text_file;
+
+/// Remembers the content of every file the model has read or edited, so edits made outside
+/// the agent (say, in the user's editor) can be reported before the model acts on stale text.
+///
+/// The directories holding those files are watched, and only files the watcher reported are
+/// read again. When watching fails, every tracked file is checked before each request.
+pub struct FileTracker {
+    root: PathBuf,
+    /// What the model last saw of each file, by absolute path
+    seen: BTreeMap<PathBuf, String>,
+    /// Paths the watcher saw change since the last check
+    changed: Arc<Mutex<HashSet<PathBuf>>>,
+    watcher: Option<RecommendedWatcher>,
+    watched: HashSet<PathBuf>,
+    watch_failed: bool,
+}
+
+impl FileTracker {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            seen: BTreeMap::new(),
+            changed: Arc::new(Mutex::new(HashSet::new())),
+            watcher: None,
+            watched: HashSet::new(),
+            watch_failed: false,
+        }
+    }
+
+    /// Records that the model has seen `path` as `content`, or as it is on disk when `None`
+    /// (after an edit). Files that can't be read as text, or are over `max_bytes`, are no longer tracked.
+    pub fn record(&mut self, path: &Path, content: Option<String>, max_bytes: u64) {
+        let path = self.absolute(path);
+        match content.map_or_else(
+            || text_file::read_text(&path, max_bytes).map(|file| file.text),
+            Ok,
+        ) {
+            Ok(content) => {
+                if let Some(directory) = path.parent() {
+                    self.watch(directory.to_path_buf());
+                }
+                self.seen.insert(path, content);
+            }
+            Err(_) => {
+                self.seen.remove(&path);
+            }
+        }
+    }
+
+    /// Describes, with diffs, the tracked files that changed since the model last saw them,
+    /// and takes their current content as seen. Files that grew over `max_bytes` are reported
+    /// and no longer tracked. `None` when nothing changed.
+    pub fn take_changes(&mut self, max_bytes: u64) -> Option<ExternalChanges> {
+        let candidates: Vec<PathBuf> = if self.watch_failed {
+            self.seen.keys().cloned().collect()
+        } else {
+            let mut changed = self
+                .changed
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let mut candidates: Vec<PathBuf> = changed
+                .drain()
+                .filter(|path| self.seen.contains_key(path))
+                .collect();
+            candidates.sort();
+            candidates
+        };
+
+        let mut changes = ExternalChanges::default();
+        for path in candidates {
+            let display = path
+                .strip_prefix(&self.root)
+                .unwrap_or(&path)
+                .display()
+                .to_string();
+            match text_file::read_text(&path, max_bytes).map(|file| file.text) {
+                Ok(current) => {
+                    let previous = self.seen.insert(path, current.clone()).unwrap_or_default();
+                    if previous != current {
+                        changes.summaries.push(
+                            FileDiff::new(&display, Some(&previous), &current).summary_for_model(),
+                        );
+                        changes.paths.push(display);
+                    }
+                }
+                Err(e) => {
+                    self.seen.remove(&path);
+                    changes.summaries.push(if e.kind() == io::ErrorKind::NotFound {
+                        format!("Deleted {}", display)
+                    } else if e.kind() == io::ErrorKind::FileTooLarge {
+                        format!("Changed {} (now over the {} byte read limit, so no longer compared)", display, max_bytes)
+                    } else {
+                        format!("Changed {} (can no longer be read as text: {})", display, e)
+                    });
+                    changes.paths.push(display);
+                }
+            }
+        }
+        (!changes.paths.is_empty()).then_some(changes)
+    }
+
+    /// `path` as an absolute path without `.` components, matching the paths the watcher reports.
+    fn absolute(&self, path: &Path) -> PathBuf {
+        self.root
+            .join(path)
+            .components()
+            .filter(|component| *component != Component::CurDir)
+            .collect()
+    }
+
+    fn watch(&mut self, directory: PathBuf) {
+        if self.watch_failed || self.watched.contains(&directory) {
+            return;
+        }
+        if self.watcher.is_none() {
+            let changed = Arc::clone(&self.changed);
+            let handler = move |result: notify::Result<Event>| {
+                if let Ok(event) = result {
+                    if !matches!(event.kind, EventKind::Access(_)) {
+                        changed
+                            .lock()
+                            .unwrap_or_else(|poisoned| poisoned.into_inner())
+                            .extend(event.paths);
+                    }
+                }
+            };
+            match notify::recommended_watcher(handler) {
+                Ok(watcher) => self.watcher = Some(watcher),
+                Err(e) => return self.stop_watching(e),
+            }
+        }
+        // Watching the directory rather than the file also catches editors that save by renaming
+        if let Some(watcher) = &mut self.watcher {
+            match watcher.watch(&directory, RecursiveMode::NonRecursive) {
+                Ok(()) => {
+                    self.watched.insert(directory);
+                }
+                Err(e) => self.stop_watching(e),
+            }
+        }
+    }
+
+    fn stop_watching(&mut self, error: notify::Error) {
+        warn!("Could not watch files for outside changes, checking them before every request instead: {}", error);
+        self.watcher = None;
+        self.watch_failed = true;
+    }
+}
+
+/// Tracked files that changed outside the agent.
+#[derive(Debug, Default)]
+pub struct ExternalChanges {
+    /// Relative paths, for telling the user
+    pub paths: Vec<String>,
+    summaries: Vec<String>,
+}
+
+/// First line of the notice about outside changes, followed by one summary per file
+pub const CHANGES_NOTICE: &str =
+    "[Files you read or edited earlier were changed outside this session, probably by the user. Work from their current content:]";
+
+impl ExternalChanges {
+    /// The notice added to the conversation before the next request.
+    pub fn message_for_model(&self) -> String {
+        format!("{}\n\n{}", CHANGES_NOTICE, self.summaries.join("\n\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const LIMIT: u64 = 1000;
+
+    #[test]
+    fn test_external_changes() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path();
+        fs::write(root.join("main.rs"), "fn main() {\n}\n").unwrap();
+        fs::write(root.join("lib.rs"), "pub mod a;\n").unwrap();
+
+        let mut tracker = FileTracker::new(root);
+        // Check every tracked file, as when the watcher can't start, so the test doesn't wait on events
+        tracker.watch_failed = true;
+        tracker.record(
+            Path::new("./main.rs"),
+            Some("fn main() {\n}\n".to_string()),
+            LIMIT,
+        );
+        tracker.record(Path::new("lib.rs"), None, LIMIT);
+        assert!(tracker.take_changes(LIMIT).is_none());
+
+        // The agent's own edits are recorded, so they are not reported back
+        fs::write(root.join("lib.rs"), "pub mod a;\npub mod b;\n").unwrap();
+        tracker.record(Path::new("lib.rs"), None, LIMIT);
+        fs::write(root.join("main.rs"), "fn main() {\n    run();\n}\n").unwrap();
+        let changes = tracker.take_changes(LIMIT).unwrap();
+        assert_eq!(changes.paths, ["main.rs"]);
+        assert!(changes.message_for_model().contains("+    run();"));
+        assert!(tracker.take_changes(LIMIT).is_none());
+
+        fs::remove_file(root.join("lib.rs")).unwrap();
+        assert!(tracker
+            .take_changes(LIMIT)
+            .unwrap()
+            .message_for_model()
+            .contains("Deleted lib.rs"));
+        assert!(tracker.take_changes(LIMIT).is_none());
+    }
+
+    #[test]
+    fn test_large_files_are_not_tracked() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path();
+        fs::write(root.join("big.txt"), "x".repeat(2000)).unwrap();
+        fs::write(root.join("main.rs"), "fn main() {}\n").unwrap();
+
+        let mut tracker = FileTracker::new(root);
+        tracker.watch_failed = true;
+        tracker.record(Path::new("big.txt"), None, LIMIT);
+        tracker.record(Path::new("main.rs"), None, LIMIT);
+        assert_eq!(tracker.seen.len(), 1);
+
+        fs::write(root.join("main.rs"), "x".repeat(2000)).unwrap();
+        let changes = tracker.take_changes(LIMIT).unwrap();
+        assert!(changes
+            .message_for_model()
+            .contains("main.rs (now over the 1000 byte read limit"));
+        assert!(tracker.seen.is_empty());
+    }
+}
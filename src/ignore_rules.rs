@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use log::warn;
+
+use crate::path_patterns::{self, PathPatterns};
+
+/// Per-directory ignore files, in priority order: a `!pattern` in `.simplecoderignore` can
+/// make a git-ignored file visible to the tools again.
+const IGNORE_FILES: &[&str] = &[".simplecoderignore", ".gitignore"];
+
+/// Which paths the tools treat as absent: everything git ignores, plus `.simplecoderignore`
+/// files and the configured `ignore_patterns`.
+///
+/// Like git, `.gitignore` files apply in the directory they are in and below, deeper files
+/// win over shallower ones, and nothing inside an ignored directory can be re-included.
+/// `.git/info/exclude` and the global excludes file (`core.excludesFile`) come last.
+pub struct IgnoreRules {
+    root: PathBuf,
+    /// The repository root when `root` is inside a git repository, so parent `.gitignore`s count
+    base: PathBuf,
+    patterns: PathPatterns,
+    exclude: Gitignore,
+    global: Gitignore,
+    /// The ignore files of each directory visited, loaded on first use
+    directories: Mutex<HashMap<PathBuf, Arc<Vec<Gitignore>>>>,
+}
+
+impl IgnoreRules {
+    pub fn new(root: &Path, patterns: &PathPatterns) -> Self {
+        let base = root
+            .ancestors()
+            .find(|directory| directory.join(".git").exists())
+            .unwrap_or(root)
+            .to_path_buf();
+        let exclude = load_matcher(&base, &base.join(".git").join("info").join("exclude"));
+        let (global, error) = Gitignore::global();
+        if let Some(e) = error {
+            warn!("Problem reading the global git excludes file: {}", e);
+        }
+        Self {
+            root: root.to_path_buf(),
+            base,
+            patterns: patterns.clone(),
+            exclude,
+            global,
+            directories: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// True when `path` or any directory above it, up to the root, is ignored.
+    /// Paths outside the root are never ignored.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        // `src/../secrets/key` is checked as `secrets/key`, so anchored rules see the real path
+        let path = path_patterns::normalize(path);
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        let components: Vec<&std::ffi::OsStr> = relative
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+
+        let mut prefix = self.root.clone();
+        for (index, name) in components.iter().enumerate() {
+            prefix.push(name);
+            let prefix_is_dir = index + 1 < components.len() || is_dir;
+            if self.is_ignored_entry(&prefix, prefix_is_dir) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Checks `path` alone, for walkers that have already checked its parent directories.
+    pub fn is_ignored_entry(&self, path: &Path, is_dir: bool) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        if self.patterns.matches(relative) {
+            return true;
+        }
+
+        let Some(parent) = path.parent() else {
+            return false;
+        };
+        for directory in parent
+            .ancestors()
+            .take_while(|directory| directory.starts_with(&self.base))
+        {
+            for matcher in self.directory_matchers(directory).iter() {
+                let matched = matcher.matched(path, is_dir);
+                if matched.is_ignore() {
+                    return true;
+                }
+                if matched.is_whitelist() {
+                    return false;
+                }
+            }
+        }
+
+        let from_base = path.strip_prefix(&self.base).unwrap_or(path);
+        for matcher in [&self.exclude, &self.global] {
+            let matched = matcher.matched(from_base, is_dir);
+            if matched.is_ignore() || matched.is_whitelist() {
+                return matched.is_ignore();
+            }
+        }
+        false
+    }
+
+    fn directory_matchers(&self, directory: &Path) -> Arc<Vec<Gitignore>> {
+        let mut directories = self
+            .directories
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        directories
+            .entry(directory.to_path_buf())
+            .or_insert_with(|| {
+                Arc::new(
+                    IGNORE_FILES
+                        .iter()
+                        .map(|name| directory.join(name))
+                        .filter(|path| path.is_file())
+                        .map(|path| load_matcher(directory, &path))
+                        .collect(),
+                )
+            })
+            .clone()
+    }
+}
+
+/// Reads one ignore file; bad lines are logged and skipped rather than failing the tool.
+fn load_matcher(directory: &Path, path: &Path) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(directory);
+    if path.is_file() {
+        if let Some(e) = builder.add(path) {
+            warn!("Problem reading {}: {}", path.display(), e);
+        }
+    }
+    builder.build().unwrap_or_else(|e| {
+        warn!("Ignoring {}: {}", path.display(), e);
+        Gitignore::empty()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_ignore_rules() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path();
+        fs::create_dir_all(root.join(".git/info")).unwrap();
+        fs::create_dir_all(root.join("data/raw")).unwrap();
+        fs::create_dir_all(root.join("src/generated")).unwrap();
+        fs::write(root.join(".gitignore"), "*.log\n/data/\n.env\n").unwrap();
+        fs::write(root.join("src/.gitignore"), "generated/\n!keep.log\n").unwrap();
+        fs::write(root.join(".git/info/exclude"), "notes.txt\n").unwrap();
+        fs::write(
+            root.join(".simplecoderignore"),
+            "!.env.example\nfixtures/\n",
+        )
+        .unwrap();
+
+        let rules = IgnoreRules::new(root, &PathPatterns::new(&["*.bak".to_string()]).unwrap());
+        assert!(rules.is_ignored(&root.join("debug.log"), false));
+        assert!(rules.is_ignored(&root.join("data/raw/big.csv"), false));
+        assert!(rules.is_ignored(&root.join("src/generated/api.rs"), false));
+        assert!(rules.is_ignored(&root.join(".env"), false));
+        assert!(rules.is_ignored(&root.join("notes.txt"), false));
+        assert!(rules.is_ignored(&root.join("tests/fixtures"), true));
+        assert!(rules.is_ignored(&root.join("old.bak"), false));
+
+        // A deeper `.gitignore` can re-include what a parent ignores
+        assert!(!rules.is_ignored(&root.join("src/keep.log"), false));
+        assert!(!rules.is_ignored(&root.join("src/main.rs"), false));
+        assert!(!rules.is_ignored(&root.join("data"), false));
+        assert!(!rules.is_ignored(Path::new("/etc/hosts"), false));
+
+        // Anchored rules see the path `..` leads to, not the components around it
+        assert!(rules.is_ignored(&root.join("src/../data/raw/big.csv"), false));
+        assert!(!rules.is_ignored(&root.join("data/../src/main.rs"), false));
+        assert!(!rules.is_ignored(&root.join("../elsewhere.log"), false));
+    }
+}
@@ -1,12 +1,36 @@
-use serde::{Serialize, Deserialize};
+use clap::Parser;
 use reqwest::Client;
+use serde::{Deserialize, Serialize};
 use std::fs;
-use log::debug;
+mod agent;
+mod cli;
+mod commands;
+mod config;
+mod credentials;
+mod diff;
+mod export;
+mod file_metadata;
+mod file_tracker;
+mod ignore_rules;
+mod markdown;
+mod mentions;
+mod path_patterns;
+mod permissions;
+mod prompt;
+mod read_file;
 mod scan_directory;
+mod session;
+mod text_file;
+mod tui;
+mod usage;
+mod workspace;
+mod workspace_index;
 mod tools {
     pub mod text_editor;
 }
 
+/// Log file in the data directory used instead of stderr by `--tui`
+const TUI_LOG_FILE: &str = "tui.log";
 
 const SYSTEM_MESSAGE: &str = "You are an expert software architect and developer. You will work with the user for software development tasks.
 Always remember to keep your solutions simple and easy to understand. Also, if you do not provide a correct and working solution,
@@ -30,6 +54,9 @@ IMPORTANT: When users ask you to fix code, add features, or make changes, you sh
 1. Use scan_directory or read_file to understand the current state
 2. Use str_replace_based_edit_tool to make the actual changes
 3. Show the user what you changed
+
+Files and directories the user mentions as @path are already attached to their message in
+<attached_file> and <attached_directory> blocks. Use those contents directly instead of reading the files again.
 </available_tools>
 
 <tone>
@@ -48,40 +75,305 @@ When making file changes:
 
 ";
 
-const READ_FILE_TOOL_DESCRIPTION: &str = "Read the contents of a file. The input is a string that is the path to the file. 
-The output is a string that is the complete contents of the file. If the file does not exist, return an error message.
+const READ_FILE_TOOL_DESCRIPTION: &str = "Read the contents of a file. The input is the path to the file.
+The output starts with the file's size in bytes and lines, followed by the lines, each prefixed with its line number and a tab.
+The number is not part of the file: leave it out when quoting text for str_replace, and use it for insert_line.
+At most 2000 lines are returned at a time; for longer files the output says how many lines remain,
+and offset (the first line to read, from 1) and limit (how many lines) read the rest or any other range.
+If the file does not exist, return an error message.
+To read several files in one call, give file_paths instead: a list of paths and globs (like src/**/*.rs).
+Each file comes back in its own <file path=\"...\"> block, with an error in place of any file that can't be read.
+A batch is kept to a token budget; files past it are named at the end so you can read them in another call.
 ";
 
 const SCAN_DIRECTORY_TOOL_DESCRIPTION: &str = "Scan the current directory and return the tree structure. 
-The output is a string that is the tree structure.
-The input is an empty object.
+The output is a string that is the tree structure; directories end in /.
+Every input field is optional. Use path and max_depth to explore a large project one subtree at a time,
+include/exclude globs (like *.rs or tests/**) to filter, and only: \"files\" to get a flat list of file paths.
+Use format: \"json\" when you need file sizes, line counts, languages, modification times or git status.
+Symlinks are shown as name -> target; set follow_symlinks to list the contents of symlinked directories inside the project.
 You should use this tool whenever you are unsure about current directory structure.
 You should also use this tool when you don't really know where a particular file is located. 
 ";
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 struct Message {
     role: String,
-    content: String,
+    content: MessageContent,
+}
+
+/// Plain text, or content blocks such as the model's tool calls and the results sent back for them.
+/// Serialized as the API expects, so saved conversations holding only text still load.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+enum MessageContent {
+    Text(String),
+    Blocks(Vec<serde_json::Value>),
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+impl MessageContent {
+    /// The text of the message, leaving out tool calls and results.
+    fn text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Blocks(blocks) => blocks
+                .iter()
+                .filter(|block| block["type"] == "text")
+                .filter_map(|block| block["text"].as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    fn into_blocks(self) -> Vec<serde_json::Value> {
+        match self {
+            MessageContent::Text(text) if text.is_empty() => Vec::new(),
+            MessageContent::Text(text) => vec![serde_json::json!({"type": "text", "text": text})],
+            MessageContent::Blocks(blocks) => blocks,
+        }
+    }
+
+    fn tool_use_ids(&self) -> Vec<String> {
+        self.ids("tool_use", "id")
+    }
+
+    fn tool_result_ids(&self) -> Vec<String> {
+        self.ids("tool_result", "tool_use_id")
+    }
+
+    fn ids(&self, block_type: &str, field: &str) -> Vec<String> {
+        match self {
+            MessageContent::Text(_) => Vec::new(),
+            MessageContent::Blocks(blocks) => blocks
+                .iter()
+                .filter(|block| block["type"] == block_type)
+                .filter_map(|block| block[field].as_str().map(str::to_string))
+                .collect(),
+        }
+    }
+}
+
+impl Message {
+    /// The result of a tool call, sent back to the model in reply to the call with `tool_use_id`.
+    fn tool_result(tool_use_id: &str, content: &str, success: bool) -> Self {
+        Message {
+            role: "user".to_string(),
+            content: MessageContent::Blocks(vec![serde_json::json!({
+                "type": "tool_result",
+                "tool_use_id": tool_use_id,
+                "content": content,
+                "is_error": !success,
+            })]),
+        }
+    }
+}
+
+/// The conversation as the API accepts it. Consecutive messages of one role are sent as one, every
+/// tool call gets a result (calls cut short by an interrupt are reported as such), and results without
+/// their call, as in sessions saved before tool calls were kept, are sent as plain text.
+fn request_messages(messages: &[Message]) -> Vec<Message> {
+    let mut merged: Vec<Message> = Vec::new();
+    for message in messages {
+        match merged.last_mut() {
+            Some(last) if last.role == message.role => {
+                let mut blocks =
+                    std::mem::replace(&mut last.content, MessageContent::Blocks(Vec::new()))
+                        .into_blocks();
+                blocks.extend(message.content.clone().into_blocks());
+                last.content = MessageContent::Blocks(blocks);
+            }
+            _ => merged.push(message.clone()),
+        }
+    }
+
+    let mut index = 0;
+    while index < merged.len() {
+        let calls = merged[index].content.tool_use_ids();
+        if merged[index].role == "assistant" && !calls.is_empty() {
+            if merged.get(index + 1).is_none_or(|next| next.role != "user") {
+                merged.insert(
+                    index + 1,
+                    Message {
+                        role: "user".to_string(),
+                        content: MessageContent::Blocks(Vec::new()),
+                    },
+                );
+            }
+            let answered = merged[index + 1].content.tool_result_ids();
+            let missing: Vec<serde_json::Value> = calls
+                .iter()
+                .filter(|id| !answered.contains(id))
+                .flat_map(|id| {
+                    Message::tool_result(
+                        id,
+                        "The user interrupted the turn before this tool ran.",
+                        false,
+                    )
+                    .content
+                    .into_blocks()
+                })
+                .collect();
+            let next = &mut merged[index + 1];
+            let mut blocks =
+                std::mem::replace(&mut next.content, MessageContent::Blocks(Vec::new()))
+                    .into_blocks();
+            let after_results = blocks
+                .iter()
+                .take_while(|block| block["type"] == "tool_result")
+                .count();
+            blocks.splice(after_results..after_results, missing);
+            next.content = MessageContent::Blocks(blocks);
+        }
+        if merged[index].role == "user" {
+            let calls = index
+                .checked_sub(1)
+                .map(|previous| merged[previous].content.tool_use_ids())
+                .unwrap_or_default();
+            if let MessageContent::Blocks(blocks) = &mut merged[index].content {
+                for block in blocks.iter_mut() {
+                    let orphan = block["type"] == "tool_result"
+                        && !block["tool_use_id"]
+                            .as_str()
+                            .is_some_and(|id| calls.iter().any(|call| call == id));
+                    if orphan {
+                        let text = block["content"].as_str().unwrap_or_default().to_string();
+                        *block = serde_json::json!({"type": "text", "text": text});
+                    }
+                }
+            }
+        }
+        index += 1;
+    }
+    merged
 }
+
 #[derive(Debug, Serialize, Clone)]
-pub struct ToolDefinition{
-	name: String,
-	description: String,
-	input_schema: serde_json::Value,
+pub struct ToolDefinition {
+    name: String,
+    description: String,
+    input_schema: serde_json::Value,
 }
 
 #[derive(Debug, Serialize, Clone)]
-pub struct BuiltInToolDefinition{
-	r#type: String,
-	name: String
+pub struct BuiltInToolDefinition {
+    r#type: String,
+    name: String,
 }
 
 #[derive(Debug, Serialize, Clone)]
 #[serde(untagged)]
-enum ToolType{
-	BuiltIn(BuiltInToolDefinition),
-	Custom(ToolDefinition)
+enum ToolType {
+    BuiltIn(BuiltInToolDefinition),
+    Custom(ToolDefinition),
+}
+
+impl ToolType {
+    fn name(&self) -> &str {
+        match self {
+            ToolType::BuiltIn(tool) => &tool.name,
+            ToolType::Custom(tool) => &tool.name,
+        }
+    }
+
+    /// One-line description used when listing tools to the user
+    fn summary(&self) -> String {
+        match self {
+            ToolType::BuiltIn(tool) => format!("built-in ({})", tool.r#type),
+            ToolType::Custom(tool) => tool
+                .description
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+        }
+    }
+}
+
+/// Tools offered to the model with every request
+fn tool_definitions() -> Vec<ToolType> {
+    vec![
+        ToolType::Custom(ToolDefinition {
+            name: "read_file".to_string(),
+            description: READ_FILE_TOOL_DESCRIPTION.to_string(),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "file_path": {
+                        "type": "string",
+                        "description": "The path to the file to read"
+                    },
+                    "file_paths": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Several files to read at once; globs like src/**/*.rs are expanded"
+                    },
+                    "offset": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "description": "Line number to start reading at (default: 1)"
+                    },
+                    "limit": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "description": "Maximum number of lines to return (default: 2000)"
+                    }
+                }
+            }),
+        }),
+        ToolType::Custom(ToolDefinition {
+            name: "scan_directory".to_string(),
+            description: SCAN_DIRECTORY_TOOL_DESCRIPTION.to_string(),
+            input_schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "Directory to scan, relative to the project root (default: the whole project)"
+                    },
+                    "max_depth": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "description": "How many levels below path to list; deeper directories are shown but not expanded"
+                    },
+                    "include": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Only list files matching one of these globs, e.g. [\"*.rs\"]"
+                    },
+                    "exclude": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Leave out files and directories matching these globs"
+                    },
+                    "only": {
+                        "type": "string",
+                        "enum": ["all", "files", "dirs"],
+                        "description": "files: a flat list of file paths; dirs: a tree of directories only (default: all)"
+                    },
+                    "format": {
+                        "type": "string",
+                        "enum": ["tree", "json"],
+                        "description": "json returns nested entries with size, line count, language, modified time and git status (default: tree)"
+                    },
+                    "follow_symlinks": {
+                        "type": "boolean",
+                        "description": "List the contents of symlinked directories that stay inside the project; links that loop are never followed (default: false)"
+                    }
+                },
+            }),
+        }),
+        ToolType::BuiltIn(BuiltInToolDefinition {
+            r#type: "text_editor_20250429".to_string(),
+            name: "str_replace_based_edit_tool".to_string(),
+        }),
+    ]
 }
 
 #[derive(Debug, Serialize)]
@@ -90,226 +382,241 @@ struct Request {
     messages: Vec<Message>,
     max_tokens: usize,
     temperature: f32,
-	system: String,
-	tools: Option<Vec<ToolType>>
+    system: String,
+    tools: Option<Vec<ToolType>>,
 }
 
-
 #[derive(Serialize, Deserialize)]
 pub struct ModelConfig {
     pub provider: String,
     pub model_name: String,
+    /// Never written out, so the key can't end up in a saved file
+    #[serde(skip_serializing, default)]
     pub api_key: String,
     pub temperature: f32,
     pub max_tokens: usize,
     pub api_base_url: Option<String>,
 }
 
+/// Written by hand so debug output never shows the key.
+impl std::fmt::Debug for ModelConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ModelConfig")
+            .field("provider", &self.provider)
+            .field("model_name", &self.model_name)
+            .field("api_key", &"[REDACTED]")
+            .field("temperature", &self.temperature)
+            .field("max_tokens", &self.max_tokens)
+            .field("api_base_url", &self.api_base_url)
+            .finish()
+    }
+}
+
 pub struct AnthropicClient {
     client: Client,
     config: ModelConfig,
 }
 
 impl AnthropicClient {
-	pub fn new(config: ModelConfig) -> Result<Self, Box<dyn std::error::Error>>{
-		let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(60))
-            .build()?;
-
-		Ok(Self{client, config})
-	}
-
-	async fn send_request(&self, messages: Vec<Message>) -> Result<serde_json::Value, Box<dyn std::error::Error>>{
-		let tool_definitions = vec![ToolType::Custom(ToolDefinition{
-			name: "read_file".to_string(),
-			description: READ_FILE_TOOL_DESCRIPTION.to_string(),
-			input_schema: serde_json::json!({
-				"type": "object",
-				"properties": {
-					"file_path": {
-						"type": "string",
-						"description": "The path to the file to read"
-					}
-				},
-				"required": ["file_path"]
-			})
-		}),
-		ToolType::Custom(ToolDefinition{
-			name: "scan_directory".to_string(),
-			description: SCAN_DIRECTORY_TOOL_DESCRIPTION.to_string(),
-			input_schema: serde_json::json!({
-				"type": "object",
-				"properties": {},
-			})
-		}),
-		ToolType::BuiltIn(BuiltInToolDefinition{
-			r#type: "text_editor_20250429".to_string(),
-			name: "str_replace_based_edit_tool".to_string()
-		})
-		];
-
-		let request = Request {
-			model: self.config.model_name.clone(),
-			system: SYSTEM_MESSAGE.to_string(),
-			messages,
-			tools: Some(tool_definitions),
-			max_tokens: self.config.max_tokens,
-			temperature: self.config.temperature,
-		};
-
-		let response = self.client
-		.post("https://api.anthropic.com/v1/messages")
-		.header("x-api-key", self.config.api_key.clone())
-		.header("anthropic-version", "2023-06-01")
-		.json(&request)
-		.send()
-		.await?;
-
-		
-		let response_json = response.json::<serde_json::Value>().await?;
-		Ok(response_json)
-	}
+    pub fn new(
+        config: ModelConfig,
+        timeout: std::time::Duration,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let client = Client::builder().timeout(timeout).build()?;
+
+        Ok(Self { client, config })
+    }
+
+    async fn send_request(
+        &self,
+        messages: Vec<Message>,
+        tool_definitions: Vec<ToolType>,
+    ) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+        let base_url = self
+            .config
+            .api_base_url
+            .as_deref()
+            .unwrap_or(config::DEFAULT_BASE_URL);
+
+        let request = Request {
+            model: self.config.model_name.clone(),
+            system: SYSTEM_MESSAGE.to_string(),
+            messages: request_messages(&messages),
+            tools: Some(tool_definitions),
+            max_tokens: self.config.max_tokens,
+            temperature: self.config.temperature,
+        };
+
+        let response = self
+            .client
+            .post(format!("{}/v1/messages", base_url.trim_end_matches('/')))
+            .header("x-api-key", self.config.api_key.clone())
+            .header("anthropic-version", "2023-06-01")
+            .json(&request)
+            .send()
+            .await?;
+
+        // An error keeps its status and the API's explanation, and never reaches the conversation
+        let status = response.status();
+        let response_json = match response.json::<serde_json::Value>().await {
+            Ok(json) => json,
+            Err(e) if status.is_success() => return Err(e.into()),
+            Err(_) => return Err(format!("the API returned {}", status).into()),
+        };
+        if !status.is_success() || response_json["type"] == "error" {
+            let error = &response_json["error"];
+            return Err(format!(
+                "the API returned {}: {} ({})",
+                status,
+                error["message"].as_str().unwrap_or("no details"),
+                error["type"].as_str().unwrap_or("unknown error")
+            )
+            .into());
+        }
+        Ok(response_json)
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>>{
-	// Load environment variables from .env file
-	dotenv::dotenv().ok();
-	env_logger::init();
-
-	let mut messages = Vec::new();
-	let current_directory_pathbuf = std::env::current_dir()?;
-	let _tree_structure = scan_directory::scan_directory_tree_from_path(&current_directory_pathbuf)?;
-	let _current_directory_string = current_directory_pathbuf.to_string_lossy().into_owned();
-
-	// Get API key from environment variable
-	let api_key = std::env::var("ANTHROPIC_API_KEY")
-		.expect("ANTHROPIC_API_KEY environment variable must be set. Create a .env file with your API key.");
-
-	// Initialize the Anthropic client
-	let anthropic_client = AnthropicClient::new(ModelConfig {
-		provider: "anthropic".to_string(),
-		model_name: "claude-sonnet-4-20250514".to_string(),
-		api_key,
-		temperature: 0.5,
-		max_tokens: 2000,  // Increased to accommodate tool usage (700 tokens) + response
-		api_base_url: None
-	})?;
-
-	let mut ask_user = true;
-
-	// Main loop
-	loop {
-		let mut tool_use = false;
-
-		if ask_user {
-			println!("What do you want to talk about:");
-			let line = read_line().expect("Failed to read line");
-
-			if line == "exit" {
-				println!("I guess we are done here.... Bye!");
-				break;
-			}
-			
-			messages.push(Message {
-				role: "user".to_string(),
-				content: line.clone()
-			});
-		}
-
-		let response_json = anthropic_client.send_request(messages.clone()).await?;
-		
-		// Check for tool calls
-		if response_json["stop_reason"].as_str() == Some("tool_use") {
-			tool_use = true;
-			ask_user = false;  // Don't ask for new user input
-			
-			// Process tool calls...
-			if let Some(content_array) = response_json["content"].as_array() {
-				for message_block in content_array {
-					if message_block["type"].as_str() == Some("tool_use") {
-						let tool_name = message_block["name"].as_str().unwrap();
-						let input = message_block["input"].as_object().unwrap();
-						debug!("Tool Name: {}", tool_name);
-						match tool_name {
-							"scan_directory" => {
-								println!("Tool Call: scan_directory");
-								let tree_structure = scan_directory::scan_directory_tree_from_path(&current_directory_pathbuf)?;
-								let message = Message {
-									role: "user".to_string(),
-									content: format!("Here is the tree structure: {}", tree_structure)
-								};
-								messages.push(message);
-							},
-							"read_file" => {
-								let file_path = input["file_path"].as_str().unwrap_or_default();
-								let file_content = read_file(&file_path)?;
-								let message = Message {
-									role: "user".to_string(),
-									content: format!("Here are the contents of the file {} : \n {}", file_path, file_content)
-								};
-								messages.push(message);
-							},
-							"str_replace_based_edit_tool" => {
-								println!("Tool Call: str_replace_editor");
-								let model_version = &anthropic_client.config.model_name;
-								let input_value = serde_json::Value::Object(input.clone());
-								println!("Input value: {:?}", input_value);
-								let result = tools::text_editor::handle_text_editor_tool(&input_value, model_version)?;
-								
-								let response_content = if result.success {
-									if let Some(file_content) = result.file_content {
-										format!("Tool execution successful: {}\n\nFile content:\n{}", result.message, file_content)
-									} else {
-										format!("Tool execution successful: {}", result.message)
-									}
-								} else {
-									format!("Tool execution failed: {}", result.message)
-								};
-								
-								println!("Response content: {}", response_content);
-								let message = Message {
-									role: "user".to_string(),
-									content: response_content
-								};
-								messages.push(message);
-							},
-							_ => {
-								eprintln!("Tool name match not found...");
-								continue
-							}
-						}
-					}
-				}
-				// After processing tools, continue the loop to send another request
-				// This will get the AI's response to the tool results
-				continue;
-			} else {
-				eprintln!("Content is not an ARRAY");
-				continue;
-			}
-		}
-
-		// Display AI response (only when no tool use)
-		if !tool_use {
-			let response_text = response_json["content"][0]["text"].as_str().unwrap_or_default().to_string();
-			println!("{}", response_text);
-			ask_user = true;  // Ready for next user input
-		}
-	}
-
-	Ok(())
-}
-
-fn read_line() -> Result<String, std::io::Error>{
-
-	let mut line = String::new();
-	std::io::stdin().read_line(&mut line)?;
-	Ok(line.trim().to_string())
+async fn main() {
+    // Load environment variables from .env file
+    dotenv::dotenv().ok();
+
+    // Errors are printed for people, and never with a key in them
+    if let Err(e) = run().await {
+        eprintln!("Error: {}", credentials::redact(&e.to_string()));
+        std::process::exit(1);
+    }
 }
 
-fn read_file(file_path: &str) -> Result<String, std::io::Error>{
-	println!("Reading the file: {}", file_path);
-	let content = fs::read_to_string(file_path)?;
-	Ok(content)	
+async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = cli::Cli::parse();
+    // Log lines on stderr would be drawn over the full-screen UI, so it logs to a file or not at all
+    match (cli.tui, session::data_dir()) {
+        (false, _) => credentials::init_logger(None),
+        (true, Some(directory)) => credentials::init_logger(Some(&directory.join(TUI_LOG_FILE))),
+        (true, None) => {}
+    }
+
+    let current_directory_pathbuf = std::env::current_dir()?;
+    if cli.list_sessions {
+        println!(
+            "{}",
+            session::format_session_list(&session::list_sessions(&current_directory_pathbuf)?)
+        );
+        return Ok(());
+    }
+    if let Some(id) = &cli.export {
+        let id = if id.is_empty() {
+            None
+        } else {
+            Some(id.as_str())
+        };
+        let (id, records) = session::load_records(&current_directory_pathbuf, id)?;
+        let (path, format) = export::resolve_output(&id, cli.output.as_deref(), cli.format);
+        let options = export::ExportOptions {
+            format,
+            collapse_lines: cli.collapse_lines,
+            redact_file_contents: cli.redact,
+        };
+        fs::write(&path, export::export(&records, &options))?;
+        println!("Exported session {} to {}", id, path.display());
+        return Ok(());
+    }
+
+    let config = config::Config::load(&current_directory_pathbuf, cli.config_layer())?;
+    if cli.print_config {
+        println!("{}", config.describe());
+        return Ok(());
+    }
+
+    let api_key = credentials::resolve_api_key(&config)?;
+
+    let anthropic_client = AnthropicClient::new(
+        config.model_config(api_key),
+        std::time::Duration::from_secs(config.request_timeout_secs),
+    )?;
+
+    let mut agent = agent::Agent::new(anthropic_client, current_directory_pathbuf, config);
+    if let Some(id) = &cli.resume {
+        let id = if id.is_empty() {
+            None
+        } else {
+            Some(id.as_str())
+        };
+        println!("{}", agent.resume(id)?);
+    }
+
+    // Piped stdin or -p means a single non-interactive turn
+    let piped = if cli.tui {
+        None
+    } else {
+        prompt::read_piped_stdin()?
+    };
+    if cli.prompt.is_some() || piped.is_some() {
+        let message = prompt::one_shot_message(cli.prompt.as_deref(), piped.as_deref())
+            .ok_or("Nothing to send: stdin was empty and no prompt was given with -p.")?;
+        let mut output = agent::PlainOutput::new(true);
+        let result = tokio::select! {
+            result = agent.handle_input(&message, &mut output) => result,
+            _ = tokio::signal::ctrl_c() => {
+                agent.interrupt(&mut output);
+                return Err("interrupted".into());
+            },
+        };
+        result?;
+        return Ok(());
+    }
+
+    if cli.tui {
+        return tui::run(agent).await;
+    }
+
+    let mut output = agent::PlainOutput::new(false);
+    let mut prompt = prompt::Prompt::new(&agent.working_dir)?;
+
+    // Main loop
+    loop {
+        println!("What do you want to talk about:");
+        let line = match prompt.read_line()? {
+            Some(line) => line,
+            None => "exit".to_string(),
+        };
+
+        if line == "exit" {
+            println!("I guess we are done here.... Bye!");
+            break;
+        }
+
+        if line.is_empty() {
+            continue;
+        }
+
+        // The first Ctrl-C cancels the turn; a second one at the prompt exits
+        let result = tokio::select! {
+            result = agent.handle_input(&line, &mut output) => Some(result),
+            _ = tokio::signal::ctrl_c() => None,
+        };
+        let Some(result) = result else {
+            println!();
+            agent.interrupt(&mut output);
+            continue;
+        };
+
+        match result {
+            Ok(commands::CommandOutcome::Exit) => {
+                println!("I guess we are done here.... Bye!");
+                break;
+            }
+            Ok(commands::CommandOutcome::Continue) => {}
+            // Tool failures already went back to the model; anything else is reported and the conversation kept
+            Err(e) if commands::is_command(&line) => eprintln!("Command failed: {}", e),
+            Err(e) => eprintln!(
+                "Error: {}\nThe conversation is kept; send another message to try again.",
+                credentials::redact(&e.to_string())
+            ),
+        }
+    }
+
+    Ok(())
 }
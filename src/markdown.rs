@@ -0,0 +1,731 @@
+use std::fmt::Write;
+use std::io::IsTerminal;
+use std::sync::LazyLock;
+
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::ThemeSet;
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+const DEFAULT_WIDTH: usize = 80;
+const MIN_TEXT_WIDTH: usize = 20;
+const CODE_THEME: &str = "base16-ocean.dark";
+
+// Grammars and themes bundled into the binary by syntect, loaded on first use
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEMES: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
+/// Terminal-independent text style. Converted to ANSI escapes or TUI styles by the caller.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Style {
+    pub fg: Option<(u8, u8, u8)>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub dim: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub style: Style,
+}
+
+impl Span {
+    fn new(text: impl Into<String>, style: Style) -> Self {
+        Self {
+            text: text.into(),
+            style,
+        }
+    }
+}
+
+pub type StyledLine = Vec<Span>;
+
+const HEADING_COLOR: (u8, u8, u8) = (97, 175, 239);
+const INLINE_CODE_COLOR: (u8, u8, u8) = (209, 154, 102);
+const MARKER_COLOR: (u8, u8, u8) = (198, 120, 221);
+
+/// True when stdout is a terminal and neither `NO_COLOR` nor `TERM=dumb` asks for plain output.
+pub fn stdout_supports_color() -> bool {
+    supports_color(&std::io::stdout())
+}
+
+/// Same check as `stdout_supports_color`, for any output stream.
+pub fn supports_color(stream: &impl IsTerminal) -> bool {
+    stream.is_terminal()
+        && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        && std::env::var("TERM").map_or(true, |term| term != "dumb")
+}
+
+/// Renders an assistant response for stdout.
+///
+/// When stdout is not a terminal the markdown is passed through untouched so it pipes cleanly.
+/// `NO_COLOR` (or `TERM=dumb`) keeps the layout but drops all escape codes.
+pub fn render_for_terminal(markdown: &str) -> String {
+    if !std::io::stdout().is_terminal() {
+        return markdown.to_string();
+    }
+    let color = stdout_supports_color();
+    let width = ratatui::crossterm::terminal::size()
+        .map(|(columns, _)| columns as usize)
+        .unwrap_or(DEFAULT_WIDTH);
+    to_ansi(&render(markdown, width), color)
+}
+
+/// Writes styled lines as text, with ANSI escape codes when `color` is set.
+pub fn to_ansi(lines: &[StyledLine], color: bool) -> String {
+    let mut output = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            output.push('\n');
+        }
+        for span in line {
+            if !color || span.style == Style::default() {
+                output.push_str(&span.text);
+                continue;
+            }
+            let mut codes = Vec::new();
+            if span.style.bold {
+                codes.push("1".to_string());
+            }
+            if span.style.dim {
+                codes.push("2".to_string());
+            }
+            if span.style.italic {
+                codes.push("3".to_string());
+            }
+            if span.style.underline {
+                codes.push("4".to_string());
+            }
+            if let Some((r, g, b)) = span.style.fg {
+                codes.push(format!("38;2;{};{};{}", r, g, b));
+            }
+            let _ = write!(output, "\x1b[{}m{}\x1b[0m", codes.join(";"), span.text);
+        }
+    }
+    output
+}
+
+/// Lays out markdown as styled lines wrapped to `width` columns.
+///
+/// Fenced code blocks are syntax highlighted and never wrapped; everything else is word-wrapped.
+pub fn render(markdown: &str, width: usize) -> Vec<StyledLine> {
+    let mut renderer = Renderer::new(width);
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    for event in Parser::new_ext(markdown, options) {
+        renderer.handle(event);
+    }
+    renderer.finish()
+}
+
+/// Converts markdown to an HTML fragment. Raw HTML in the source is escaped rather than passed through,
+/// and links and images only keep http, https, mailto and relative destinations.
+pub fn to_html(markdown: &str) -> String {
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let events = Parser::new_ext(markdown, options).map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut output = String::new();
+    pulldown_cmark::html::push_html(&mut output, events);
+    output
+}
+
+/// `url`, or `#` when its scheme could run script or reach something other than the web,
+/// like `javascript:` or `file:`.
+fn safe_url(url: CowStr<'_>) -> CowStr<'_> {
+    let trimmed = url.trim_start_matches(|c: char| c <= ' ');
+    let scheme = trimmed
+        .find([':', '/', '?', '#'])
+        .filter(|&end| trimmed[end..].starts_with(':'))
+        .map(|end| trimmed[..end].to_ascii_lowercase());
+    match scheme.as_deref() {
+        None | Some("http" | "https" | "mailto") => url,
+        Some(_) => CowStr::Borrowed("#"),
+    }
+}
+
+struct Renderer {
+    width: usize,
+    lines: Vec<StyledLine>,
+    /// Inline content of the paragraph, heading or list item being built
+    inline: Vec<Span>,
+    bold: usize,
+    italic: usize,
+    strikethrough: usize,
+    heading: Option<HeadingLevel>,
+    link_urls: Vec<String>,
+    /// Next number for each open list (`None` for bullet lists)
+    lists: Vec<Option<u64>>,
+    /// Marker widths of the open list items, which together form the indent
+    item_widths: Vec<usize>,
+    pending_marker: Option<String>,
+    quote_depth: usize,
+    code_block: Option<(String, String)>,
+    table_rows: Vec<Vec<String>>,
+    table_cell: Option<String>,
+}
+
+impl Renderer {
+    fn new(width: usize) -> Self {
+        Self {
+            width: if width == 0 { DEFAULT_WIDTH } else { width },
+            lines: Vec::new(),
+            inline: Vec::new(),
+            bold: 0,
+            italic: 0,
+            strikethrough: 0,
+            heading: None,
+            link_urls: Vec::new(),
+            lists: Vec::new(),
+            item_widths: Vec::new(),
+            pending_marker: None,
+            quote_depth: 0,
+            code_block: None,
+            table_rows: Vec::new(),
+            table_cell: None,
+        }
+    }
+
+    fn text_style(&self) -> Style {
+        let mut style = Style {
+            bold: self.bold > 0,
+            italic: self.italic > 0,
+            dim: self.strikethrough > 0,
+            underline: !self.link_urls.is_empty(),
+            fg: None,
+        };
+        if let Some(level) = self.heading {
+            style.bold = true;
+            style.fg = Some(HEADING_COLOR);
+            style.underline |= level == HeadingLevel::H1;
+        }
+        style
+    }
+
+    fn push_inline(&mut self, text: &str, style: Style) {
+        if let Some(cell) = self.table_cell.as_mut() {
+            cell.push_str(text);
+        } else {
+            self.inline.push(Span::new(text, style));
+        }
+    }
+
+    fn handle(&mut self, event: Event) {
+        if let Some((_, code)) = self.code_block.as_mut() {
+            match event {
+                Event::Text(text) => code.push_str(&text),
+                Event::End(TagEnd::CodeBlock) => self.finish_code_block(),
+                _ => {}
+            }
+            return;
+        }
+
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) | Event::Html(text) | Event::InlineHtml(text) => {
+                let style = self.text_style();
+                self.push_inline(&text, style);
+            }
+            Event::Code(code) => {
+                let style = Style {
+                    fg: Some(INLINE_CODE_COLOR),
+                    ..self.text_style()
+                };
+                self.push_inline(&code, style);
+            }
+            Event::SoftBreak => self.push_inline(" ", Style::default()),
+            Event::HardBreak => self.push_inline("\n", Style::default()),
+            Event::Rule => {
+                self.flush_inline();
+                let rule = "─".repeat(self.width.saturating_sub(self.prefix_width()).max(3));
+                let line = vec![
+                    self.prefix_span(),
+                    Span::new(
+                        rule,
+                        Style {
+                            dim: true,
+                            ..Style::default()
+                        },
+                    ),
+                ];
+                self.lines.push(line);
+                self.blank_line();
+            }
+            Event::TaskListMarker(checked) => {
+                self.push_inline(if checked { "[x] " } else { "[ ] " }, Style::default());
+            }
+            Event::FootnoteReference(name) => {
+                let style = self.text_style();
+                self.push_inline(&format!("[^{}]", name), style);
+            }
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Heading { level, .. } => {
+                self.flush_inline();
+                self.heading = Some(level);
+            }
+            Tag::BlockQuote(_) => {
+                self.flush_inline();
+                self.quote_depth += 1;
+            }
+            Tag::CodeBlock(kind) => {
+                self.flush_inline();
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => info
+                        .split(|c: char| c == ',' || c == '{' || c.is_whitespace())
+                        .next()
+                        .unwrap_or_default()
+                        .to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.code_block = Some((language, String::new()));
+            }
+            Tag::List(start) => {
+                // A nested list ends the text of the enclosing item
+                self.flush_inline();
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.flush_inline();
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        let marker = format!("{}. ", number);
+                        *number += 1;
+                        marker
+                    }
+                    _ => "• ".to_string(),
+                };
+                self.item_widths.push(marker.chars().count());
+                self.pending_marker = Some(marker);
+            }
+            Tag::Emphasis => self.italic += 1,
+            Tag::Strong => self.bold += 1,
+            Tag::Strikethrough => self.strikethrough += 1,
+            Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. } => {
+                self.link_urls.push(dest_url.to_string())
+            }
+            Tag::Table(_) => {
+                self.flush_inline();
+                self.table_rows.clear();
+            }
+            Tag::TableHead | Tag::TableRow => self.table_rows.push(Vec::new()),
+            Tag::TableCell => self.table_cell = Some(String::new()),
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph => {
+                self.flush_inline();
+                if self.lists.is_empty() {
+                    self.blank_line();
+                }
+            }
+            TagEnd::Heading(_) => {
+                self.flush_inline();
+                self.heading = None;
+                self.blank_line();
+            }
+            TagEnd::BlockQuote(_) => {
+                self.flush_inline();
+                self.quote_depth -= 1;
+                self.blank_line();
+            }
+            TagEnd::List(_) => {
+                self.flush_inline();
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.blank_line();
+                }
+            }
+            TagEnd::Item => {
+                self.flush_inline();
+                self.item_widths.pop();
+                self.pending_marker = None;
+            }
+            TagEnd::Emphasis => self.italic = self.italic.saturating_sub(1),
+            TagEnd::Strong => self.bold = self.bold.saturating_sub(1),
+            TagEnd::Strikethrough => self.strikethrough = self.strikethrough.saturating_sub(1),
+            TagEnd::Link | TagEnd::Image => {
+                if let Some(url) = self.link_urls.pop() {
+                    let shown = self.inline.last().is_some_and(|span| span.text == url);
+                    if !shown && !url.is_empty() {
+                        self.push_inline(
+                            &format!(" ({})", url),
+                            Style {
+                                dim: true,
+                                ..Style::default()
+                            },
+                        );
+                    }
+                }
+            }
+            TagEnd::TableCell => {
+                if let (Some(cell), Some(row)) =
+                    (self.table_cell.take(), self.table_rows.last_mut())
+                {
+                    row.push(cell.trim().to_string());
+                }
+            }
+            TagEnd::Table => self.finish_table(),
+            _ => {}
+        }
+    }
+
+    fn prefix_width(&self) -> usize {
+        self.quote_depth * 2 + self.item_widths.iter().sum::<usize>()
+    }
+
+    fn prefix_span(&self) -> Span {
+        let indent = " ".repeat(self.item_widths.iter().sum());
+        Span::new(
+            format!("{}{}", "│ ".repeat(self.quote_depth), indent),
+            Style {
+                dim: true,
+                ..Style::default()
+            },
+        )
+    }
+
+    /// Prefixes for the first and following lines of the current block.
+    fn block_prefixes(&mut self) -> (Vec<Span>, Vec<Span>) {
+        let rest = vec![self.prefix_span()];
+        let first = match self.pending_marker.take() {
+            Some(marker) => {
+                let indent: usize = self.item_widths.iter().sum::<usize>() - marker.chars().count();
+                vec![
+                    Span::new(
+                        format!("{}{}", "│ ".repeat(self.quote_depth), " ".repeat(indent)),
+                        Style {
+                            dim: true,
+                            ..Style::default()
+                        },
+                    ),
+                    Span::new(
+                        marker,
+                        Style {
+                            fg: Some(MARKER_COLOR),
+                            bold: true,
+                            ..Style::default()
+                        },
+                    ),
+                ]
+            }
+            None => rest.clone(),
+        };
+        (first, rest)
+    }
+
+    fn flush_inline(&mut self) {
+        if self.inline.is_empty() {
+            return;
+        }
+        let spans = std::mem::take(&mut self.inline);
+        let (first, rest) = self.block_prefixes();
+        let text_width = self.width.max(self.prefix_width() + MIN_TEXT_WIDTH);
+        self.lines
+            .extend(wrap_spans(&spans, text_width, first, rest));
+    }
+
+    fn blank_line(&mut self) {
+        if self.lines.last().is_some_and(|line| !line_is_blank(line)) {
+            self.lines.push(Vec::new());
+        }
+    }
+
+    fn finish_code_block(&mut self) {
+        let Some((language, code)) = self.code_block.take() else {
+            return;
+        };
+        let fence_style = Style {
+            dim: true,
+            ..Style::default()
+        };
+        let (first, _) = self.block_prefixes();
+        let prefix = self.prefix_span();
+
+        let mut fence = first;
+        fence.push(Span::new(format!("```{}", language), fence_style));
+        self.lines.push(fence);
+        for highlighted in highlight_code(&code, &language) {
+            let mut line = vec![prefix.clone()];
+            line.extend(highlighted);
+            self.lines.push(line);
+        }
+        self.lines.push(vec![prefix, Span::new("```", fence_style)]);
+        if self.lists.is_empty() {
+            self.blank_line();
+        }
+    }
+
+    fn finish_table(&mut self) {
+        let rows = std::mem::take(&mut self.table_rows);
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let widths: Vec<usize> = (0..columns)
+            .map(|column| {
+                rows.iter()
+                    .filter_map(|row| row.get(column))
+                    .map(|cell| cell.chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        for (i, row) in rows.iter().enumerate() {
+            let cells: Vec<String> = widths
+                .iter()
+                .enumerate()
+                .map(|(column, width)| {
+                    format!(
+                        "{:<width$}",
+                        row.get(column).map(String::as_str).unwrap_or(""),
+                        width = width
+                    )
+                })
+                .collect();
+            let style = if i == 0 {
+                Style {
+                    bold: true,
+                    ..Style::default()
+                }
+            } else {
+                Style::default()
+            };
+            self.lines.push(vec![
+                self.prefix_span(),
+                Span::new(cells.join(" │ "), style),
+            ]);
+            if i == 0 {
+                let separator: Vec<String> =
+                    widths.iter().map(|width| "─".repeat(*width)).collect();
+                self.lines.push(vec![
+                    self.prefix_span(),
+                    Span::new(
+                        separator.join("─┼─"),
+                        Style {
+                            dim: true,
+                            ..Style::default()
+                        },
+                    ),
+                ]);
+            }
+        }
+        self.blank_line();
+    }
+
+    fn finish(mut self) -> Vec<StyledLine> {
+        self.flush_inline();
+        while self.lines.last().is_some_and(line_is_blank) {
+            self.lines.pop();
+        }
+        self.lines
+    }
+}
+
+fn line_is_blank(line: &StyledLine) -> bool {
+    line.iter().all(|span| {
+        span.text.trim().is_empty() || span.text.trim_start_matches('│').trim().is_empty()
+    })
+}
+
+/// Word-wraps spans to `width` columns, starting the first line with `first` and the rest with `rest`.
+fn wrap_spans(spans: &[Span], width: usize, first: Vec<Span>, rest: Vec<Span>) -> Vec<StyledLine> {
+    let prefix_width = |prefix: &[Span]| {
+        prefix
+            .iter()
+            .map(|span| span.text.chars().count())
+            .sum::<usize>()
+    };
+    let mut lines = Vec::new();
+    let mut line_width = prefix_width(&first);
+    let mut line = first;
+    let mut at_line_start = true;
+    let mut pending_space: Option<Style> = None;
+
+    for span in spans {
+        for piece in split_words(&span.text) {
+            if piece == "\n" {
+                lines.push(std::mem::replace(&mut line, rest.clone()));
+                line_width = prefix_width(&rest);
+                at_line_start = true;
+                pending_space = None;
+                continue;
+            }
+            if piece.chars().all(char::is_whitespace) {
+                if !at_line_start {
+                    pending_space = Some(span.style);
+                }
+                continue;
+            }
+
+            let piece_width = piece.chars().count();
+            let space_width = usize::from(pending_space.is_some());
+            if !at_line_start && line_width + space_width + piece_width > width {
+                lines.push(std::mem::replace(&mut line, rest.clone()));
+                line_width = prefix_width(&rest);
+                pending_space = None;
+            }
+            if let Some(style) = pending_space.take() {
+                line.push(Span::new(" ", style));
+                line_width += 1;
+            }
+            line.push(Span::new(piece, span.style));
+            line_width += piece_width;
+            at_line_start = false;
+        }
+    }
+    lines.push(line);
+    lines
+}
+
+/// Splits text into words, runs of spaces, and explicit line breaks.
+fn split_words(text: &str) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut previous_kind = None;
+    for (i, c) in text.char_indices() {
+        let kind = if c == '\n' {
+            0
+        } else if c.is_whitespace() {
+            1
+        } else {
+            2
+        };
+        if previous_kind.is_some_and(|previous| previous != kind || kind == 0) {
+            pieces.push(&text[start..i]);
+            start = i;
+        }
+        previous_kind = Some(kind);
+    }
+    if start < text.len() {
+        pieces.push(&text[start..]);
+    }
+    pieces
+}
+
+fn highlight_code(code: &str, language: &str) -> Vec<Vec<Span>> {
+    let syntax = SYNTAXES
+        .find_syntax_by_token(language)
+        .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text());
+    let mut highlighter = HighlightLines::new(syntax, &THEMES.themes[CODE_THEME]);
+
+    LinesWithEndings::from(code)
+        .map(|line| match highlighter.highlight_line(line, &SYNTAXES) {
+            Ok(regions) => regions
+                .into_iter()
+                .map(|(style, text)| {
+                    let color = style.foreground;
+                    Span::new(
+                        text.trim_end_matches(['\n', '\r']),
+                        Style {
+                            fg: Some((color.r, color.g, color.b)),
+                            ..Style::default()
+                        },
+                    )
+                })
+                .filter(|span| !span.text.is_empty())
+                .collect(),
+            Err(_) => vec![Span::new(
+                line.trim_end_matches(['\n', '\r']),
+                Style::default(),
+            )],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(markdown: &str, width: usize) -> String {
+        to_ansi(&render(markdown, width), false)
+    }
+
+    #[test]
+    fn test_render_structure_without_color() {
+        let rendered = plain(
+            "# Title\n\nSome *text* here.\n\n- one\n- two\n\n1. first\n2. second\n",
+            80,
+        );
+        assert_eq!(
+            rendered,
+            "Title\n\nSome text here.\n\n• one\n• two\n\n1. first\n2. second"
+        );
+        assert!(!rendered.contains('\x1b'));
+    }
+
+    #[test]
+    fn test_code_block_is_highlighted_and_not_wrapped() {
+        let markdown = "```rust\nfn main() { let a_really_long_variable_name = 1; }\n```\n";
+        let lines = render(markdown, 20);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].iter().any(|span| span.style.fg.is_some()));
+        assert_eq!(
+            to_ansi(&lines, false),
+            "```rust\nfn main() { let a_really_long_variable_name = 1; }\n```"
+        );
+        assert!(to_ansi(&lines, true).contains("\x1b[38;2;"));
+    }
+
+    #[test]
+    fn test_paragraphs_wrap_to_width() {
+        let rendered = plain(
+            "alpha beta gamma delta epsilon zeta eta theta iota kappa",
+            24,
+        );
+        assert!(rendered.lines().all(|line| line.chars().count() <= 24));
+        assert_eq!(rendered.lines().count(), 3);
+
+        let rendered = plain("- a list item that is long enough to wrap around", 24);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert!(lines[0].starts_with("• "));
+        assert!(lines[1].starts_with("  "));
+    }
+
+    #[test]
+    fn test_html_keeps_only_safe_link_destinations() {
+        let html = to_html(
+            "[a](https://example.com) [b](docs/a.md) [c](mailto:x@example.com) \
+             [d](javascript:alert(1)) [e]( JavaScript:alert(1)) ![f](data:image/svg+xml,x) [g](#top)",
+        );
+        assert!(html.contains("href=\"https://example.com\""));
+        assert!(html.contains("href=\"docs/a.md\""));
+        assert!(html.contains("href=\"mailto:x@example.com\""));
+        assert!(html.contains("href=\"#top\""));
+        assert!(!html.to_lowercase().contains("javascript"), "{}", html);
+        assert!(!html.contains("data:"), "{}", html);
+    }
+}
@@ -0,0 +1,362 @@
+use std::fmt::Write;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::text_file;
+
+/// Files larger than this are cut off when attached
+const MAX_ATTACHMENT_BYTES: usize = 100_000;
+/// Characters that commonly follow a mention in prose, e.g. "look at @src/main.rs."
+const TRAILING_PUNCTUATION: &[char] = &[',', '.', ';', ':', '!', '?', ')', ']', '\'', '"'];
+
+/// User input with its `@path` mentions resolved.
+#[derive(Debug, Default)]
+pub struct ExpandedInput {
+    /// The input as typed, followed by the attached contents
+    pub text: String,
+    /// One line per attachment, for telling the user what was added
+    pub attached: Vec<String>,
+    /// Mentions that looked like paths but could not be attached
+    pub errors: Vec<String>,
+}
+
+/// A `@path` or `@path:10-40` reference found in user input.
+#[derive(Debug, PartialEq)]
+struct Mention {
+    path: String,
+    lines: Option<RangeInclusive<usize>>,
+}
+
+/// Attaches the contents of files and directories mentioned as `@path` to the input.
+///
+/// `@file` attaches the whole file, `@file:10-40` (or `@file:10`) only those lines, and `@dir/`
+/// a listing of the directory. Mentions that don't name an existing path, like `@decorator`
+/// or e-mail handles, are left alone. Files over `max_read_bytes` are reported, not attached.
+///
+/// `check` gets each mentioned path and returns where to read it, or why it may not be attached;
+/// mentions go through the same checks as the file tools. Directory listings leave out entries
+/// that fail it.
+pub fn expand_mentions(
+    input: &str,
+    working_dir: &Path,
+    max_read_bytes: u64,
+    check: impl Fn(&Path) -> Result<PathBuf, String>,
+) -> ExpandedInput {
+    let mut expanded = ExpandedInput {
+        text: input.to_string(),
+        ..ExpandedInput::default()
+    };
+    let mut seen = Vec::new();
+
+    for mention in find_mentions(input, working_dir) {
+        if seen.contains(&mention) {
+            continue;
+        }
+        let full_path = match check(Path::new(&mention.path)) {
+            Ok(full_path) => full_path,
+            Err(reason) => {
+                expanded
+                    .errors
+                    .push(format!("Not attaching @{}: {}", mention.path, reason));
+                seen.push(mention);
+                continue;
+            }
+        };
+        let attachment = if full_path.is_dir() {
+            attach_directory(&full_path, &mention.path, &check)
+        } else {
+            attach_file(&full_path, &mention, max_read_bytes)
+        };
+        match attachment {
+            Ok((block, description)) => {
+                expanded.text.push_str("\n\n");
+                expanded.text.push_str(&block);
+                expanded.attached.push(description);
+            }
+            Err(e) => expanded
+                .errors
+                .push(format!("Could not attach @{}: {}", mention.path, e)),
+        }
+        seen.push(mention);
+    }
+    expanded
+}
+
+fn find_mentions(input: &str, working_dir: &Path) -> Vec<Mention> {
+    input
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .filter_map(|reference| parse_mention(reference, working_dir))
+        .collect()
+}
+
+/// Parses the part after `@`, dropping trailing punctuation unless it is part of an existing path.
+fn parse_mention(reference: &str, working_dir: &Path) -> Option<Mention> {
+    let mut candidate = reference;
+    loop {
+        let (path, lines) = split_line_range(candidate);
+        if !path.is_empty() && working_dir.join(path).exists() {
+            return Some(Mention {
+                path: path.to_string(),
+                lines,
+            });
+        }
+        candidate = candidate.strip_suffix(TRAILING_PUNCTUATION)?;
+    }
+}
+
+/// Splits `file:10-40` into the path and line range. A suffix that isn't a range stays part of the path.
+fn split_line_range(reference: &str) -> (&str, Option<RangeInclusive<usize>>) {
+    let Some((path, range)) = reference.rsplit_once(':') else {
+        return (reference, None);
+    };
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    match (start.parse::<usize>(), end.parse::<usize>()) {
+        (Ok(start), Ok(end)) if start >= 1 && start <= end => (path, Some(start..=end)),
+        _ => (reference, None),
+    }
+}
+
+fn attach_file(
+    full_path: &Path,
+    mention: &Mention,
+    max_read_bytes: u64,
+) -> Result<(String, String), Box<dyn std::error::Error>> {
+    let content = text_file::read_text(full_path, max_read_bytes)?.text;
+    let total_lines = content.lines().count();
+
+    let (content, lines_attribute, description) = match &mention.lines {
+        Some(range) => {
+            if *range.start() > total_lines {
+                return Err(format!("it ends at line {}", total_lines).into());
+            }
+            let end = (*range.end()).min(total_lines);
+            let selected: Vec<&str> = content
+                .lines()
+                .skip(range.start() - 1)
+                .take(end + 1 - range.start())
+                .collect();
+            (
+                selected.join("\n"),
+                format!(" lines=\"{}-{}\"", range.start(), end),
+                format!(
+                    "{} (lines {}-{} of {})",
+                    mention.path,
+                    range.start(),
+                    end,
+                    total_lines
+                ),
+            )
+        }
+        None => (
+            content,
+            String::new(),
+            format!("{} ({} lines)", mention.path, total_lines),
+        ),
+    };
+
+    let (content, truncated) = truncate(&content);
+    let mut block = format!(
+        "<attached_file path=\"{}\"{}>\n{}",
+        mention.path,
+        lines_attribute,
+        content.trim_end_matches('\n')
+    );
+    if truncated {
+        let _ = write!(
+            block,
+            "\n... truncated at {} bytes; use read_file to see the rest",
+            MAX_ATTACHMENT_BYTES
+        );
+    }
+    block.push_str("\n</attached_file>");
+    Ok((block, description))
+}
+
+fn attach_directory(
+    full_path: &Path,
+    display_path: &str,
+    check: &impl Fn(&Path) -> Result<PathBuf, String>,
+) -> Result<(String, String), Box<dyn std::error::Error>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(full_path)? {
+        let entry = entry?;
+        if check(&entry.path()).is_err() {
+            continue;
+        }
+        let mut name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() {
+            name.push('/');
+        }
+        entries.push(name);
+    }
+    entries.sort();
+
+    let block = format!(
+        "<attached_directory path=\"{}\">\n{}\n</attached_directory>",
+        display_path,
+        entries.join("\n")
+    );
+    Ok((
+        block,
+        format!("{} ({} entries)", display_path, entries.len()),
+    ))
+}
+
+/// Cuts `content` to at most `MAX_ATTACHMENT_BYTES`, on a character boundary.
+fn truncate(content: &str) -> (&str, bool) {
+    if content.len() <= MAX_ATTACHMENT_BYTES {
+        return (content, false);
+    }
+    let mut end = MAX_ATTACHMENT_BYTES;
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+    (&content[..end], true)
+}
+
+/// Completes a partially typed mention path (the text after `@`) against the file system.
+///
+/// Directories are suggested with a trailing `/` so completion can continue into them.
+/// Hidden entries are only offered once the user has typed the leading dot.
+pub fn complete_path(partial: &str, working_dir: &Path) -> Vec<String> {
+    let (directory, prefix) = match partial.rfind('/') {
+        Some(slash) => (&partial[..=slash], &partial[slash + 1..]),
+        None => ("", partial),
+    };
+    let Ok(entries) = fs::read_dir(working_dir.join(directory)) else {
+        return Vec::new();
+    };
+
+    let mut candidates: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let suffix = if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                "/"
+            } else {
+                ""
+            };
+            Some(format!("{}{}{}", directory, name, suffix))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project() -> tempfile::TempDir {
+        let directory = tempfile::tempdir().unwrap();
+        fs::create_dir(directory.path().join("src")).unwrap();
+        fs::write(
+            directory.path().join("src/main.rs"),
+            "fn main() {\n    run();\n}\n",
+        )
+        .unwrap();
+        fs::write(directory.path().join("src/lib.rs"), "pub fn run() {}\n").unwrap();
+        fs::write(directory.path().join(".env"), "KEY=1\n").unwrap();
+        directory
+    }
+
+    #[test]
+    fn test_expand_mentions() {
+        let directory = project();
+        let expanded = expand_mentions(
+            "Why does @src/main.rs:2 fail? See @src/, @src/main.rs:2 and mail me@example.com",
+            directory.path(),
+            u64::MAX,
+            allow_all(directory.path()),
+        );
+        assert_eq!(
+            expanded.attached,
+            vec!["src/main.rs (lines 2-2 of 3)", "src/ (2 entries)"]
+        );
+        assert!(expanded.text.starts_with("Why does @src/main.rs:2 fail?"));
+        assert!(expanded.text.contains(
+            "<attached_file path=\"src/main.rs\" lines=\"2-2\">\n    run();\n</attached_file>"
+        ));
+        assert!(expanded.text.contains(
+            "<attached_directory path=\"src/\">\nlib.rs\nmain.rs\n</attached_directory>"
+        ));
+
+        let expanded = expand_mentions(
+            "@src/lib.rs:5-9 and @Override",
+            directory.path(),
+            u64::MAX,
+            allow_all(directory.path()),
+        );
+        assert!(expanded.attached.is_empty());
+        assert_eq!(
+            expanded.errors,
+            vec!["Could not attach @src/lib.rs: it ends at line 1"]
+        );
+
+        // Files over max_read_bytes are never read in
+        let expanded = expand_mentions(
+            "@src/main.rs",
+            directory.path(),
+            10,
+            allow_all(directory.path()),
+        );
+        assert!(expanded.attached.is_empty() && !expanded.text.contains("<attached_file"));
+        assert!(
+            expanded.errors[0].contains("over the 10 byte limit (max_read_bytes)"),
+            "{:?}",
+            expanded.errors
+        );
+    }
+
+    fn allow_all(root: &Path) -> impl Fn(&Path) -> Result<PathBuf, String> + '_ {
+        move |path| Ok(root.join(path))
+    }
+
+    #[test]
+    fn test_refused_mentions() {
+        let directory = project();
+        let check = |path: &Path| {
+            let path = path.strip_prefix(directory.path()).unwrap_or(path);
+            if path.starts_with(".env") {
+                Err(format!("Access to {} is denied", path.display()))
+            } else {
+                Ok(directory.path().join(path))
+            }
+        };
+        let expanded = expand_mentions(
+            "Compare @.env with @src/lib.rs, then list @./",
+            directory.path(),
+            u64::MAX,
+            check,
+        );
+        assert_eq!(
+            expanded.errors,
+            vec!["Not attaching @.env: Access to .env is denied"]
+        );
+        assert_eq!(
+            expanded.attached,
+            vec!["src/lib.rs (1 lines)", "./ (1 entries)"]
+        );
+        assert!(!expanded.text.contains("KEY=1"));
+        assert!(expanded
+            .text
+            .contains("<attached_directory path=\"./\">\nsrc/\n</attached_directory>"));
+    }
+
+    #[test]
+    fn test_complete_path() {
+        let directory = project();
+        assert_eq!(complete_path("s", directory.path()), vec!["src/"]);
+        assert_eq!(
+            complete_path("src/m", directory.path()),
+            vec!["src/main.rs"]
+        );
+        assert_eq!(complete_path("", directory.path()), vec!["src/"]);
+        assert_eq!(complete_path(".e", directory.path()), vec![".env"]);
+    }
+}
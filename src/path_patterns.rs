@@ -0,0 +1,144 @@
+use std::path::{Component, Path};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+/// A list of glob patterns matched against paths relative to the project root.
+///
+/// Patterns follow `.gitignore` conventions: one without a `/` (like `*.log` or `target`)
+/// matches a file or directory of that name at any depth, while one with a `/` (like
+/// `src/generated/**`) is anchored at the root. `*` never crosses a `/`; `**` does.
+#[derive(Debug, Clone)]
+pub struct PathPatterns {
+    patterns: Vec<String>,
+    /// Patterns containing a `/`, matched against the whole relative path
+    anchored: GlobSet,
+    /// Patterns without a `/`, matched against each path component
+    names: GlobSet,
+}
+
+impl PathPatterns {
+    pub fn new(patterns: &[String]) -> Result<Self, globset::Error> {
+        let mut anchored = GlobSetBuilder::new();
+        let mut names = GlobSetBuilder::new();
+        for pattern in patterns {
+            let trimmed = pattern.trim_start_matches("./").trim_end_matches('/');
+            let glob = GlobBuilder::new(trimmed.trim_start_matches('/'))
+                .literal_separator(true)
+                .build()?;
+            if trimmed.contains('/') {
+                anchored.add(glob);
+            } else {
+                names.add(glob);
+            }
+        }
+        Ok(Self {
+            patterns: patterns.to_vec(),
+            anchored: anchored.build()?,
+            names: names.build()?,
+        })
+    }
+
+    pub fn empty() -> Self {
+        Self::new(&[]).expect("an empty pattern list always compiles")
+    }
+
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// True when `relative_path`, or any directory above it, matches one of the patterns.
+    /// `.` and `..` are applied first; a path that leads above the root matches nothing.
+    pub fn matches(&self, relative_path: &Path) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        let mut prefix = std::path::PathBuf::new();
+        for component in normalize(relative_path).components() {
+            let Component::Normal(name) = component else {
+                if component == Component::ParentDir {
+                    return false;
+                }
+                continue;
+            };
+            prefix.push(name);
+            if self.names.is_match(Path::new(name)) || self.anchored.is_match(&prefix) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl Default for PathPatterns {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+/// `path` with `.` and `..` applied lexically, without looking at the file system. A `..` that
+/// would go above the start of a relative path is kept.
+pub fn normalize(path: &Path) -> std::path::PathBuf {
+    let mut normalized = std::path::PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => normalized.push(".."),
+            },
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Makes `path` relative to `root` when it points inside it; other paths are returned unchanged.
+pub fn relative_to(path: &Path, root: &Path) -> std::path::PathBuf {
+    path.strip_prefix(root).unwrap_or(path).to_path_buf()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(list: &[&str]) -> PathPatterns {
+        PathPatterns::new(
+            &list
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect::<Vec<_>>(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_matches() {
+        let rules = patterns(&["*.log", ".env", "src/generated/**", "/docs/*.md"]);
+        assert!(rules.matches(Path::new("debug.log")));
+        assert!(rules.matches(Path::new("logs/today.log")));
+        assert!(rules.matches(Path::new("./config/.env")));
+        assert!(rules.matches(Path::new("src/generated/a/b.rs")));
+        assert!(rules.matches(Path::new("docs/intro.md")));
+        assert!(!rules.matches(Path::new("docs/api/intro.md")));
+        assert!(!rules.matches(Path::new("src/main.rs")));
+        assert!(!PathPatterns::empty().matches(Path::new("anything")));
+
+        // `..` is applied before matching, and a path above the root matches nothing
+        assert!(rules.matches(Path::new("lib/../src/generated/x.rs")));
+        assert!(!rules.matches(Path::new("src/generated/../main.rs")));
+        assert!(!patterns(&["**"]).matches(Path::new("../outside")));
+        assert_eq!(normalize(Path::new("./a/b/../../../c")), Path::new("../c"));
+        assert_eq!(normalize(Path::new("/a/../../b")), Path::new("/b"));
+
+        // A matching directory covers everything inside it
+        assert!(patterns(&["target"]).matches(Path::new("target/debug/main")));
+        assert!(PathPatterns::new(&["[".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,214 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::config::PermissionSettings;
+
+/// How tool calls with side effects (file writes) are authorised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PermissionMode {
+    /// Show the proposed change and ask before every write
+    #[default]
+    Ask,
+    /// Apply edits without asking
+    AcceptEdits,
+    /// Refuse every write
+    ReadOnly,
+}
+
+impl PermissionMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PermissionMode::Ask => "ask",
+            PermissionMode::AcceptEdits => "accept-edits",
+            PermissionMode::ReadOnly => "read-only",
+        }
+    }
+
+    /// Decides what to do with a tool call, given whether it has side effects.
+    pub fn check(&self, side_effecting: bool) -> PermissionCheck {
+        if !side_effecting {
+            return PermissionCheck::Allowed;
+        }
+        match self {
+            PermissionMode::Ask => PermissionCheck::NeedsApproval,
+            PermissionMode::AcceptEdits => PermissionCheck::Allowed,
+            PermissionMode::ReadOnly => PermissionCheck::Denied(
+                "The session is in read-only mode, so file changes are not allowed.".to_string(),
+            ),
+        }
+    }
+}
+
+/// Applies the configured path rules, then the permission mode, to a tool call.
+///
+/// `path` is the file the call touches, relative to the project root. Denied paths are refused
+/// whatever the mode; edits to `allow_edits` paths skip the approval prompt unless the session is read-only.
+pub fn check_tool_call(
+    mode: PermissionMode,
+    rules: &PermissionSettings,
+    path: Option<&Path>,
+    side_effecting: bool,
+) -> PermissionCheck {
+    if let Some(path) = path {
+        if rules.deny.matches(path) {
+            return PermissionCheck::Denied(format!(
+                "Access to {} is denied by the project's permission rules.",
+                path.display()
+            ));
+        }
+        if side_effecting && mode == PermissionMode::Ask && rules.allow_edits.matches(path) {
+            return PermissionCheck::Allowed;
+        }
+    }
+    mode.check(side_effecting)
+}
+
+impl fmt::Display for PermissionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for PermissionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <PermissionMode as ValueEnum>::from_str(s.trim(), true).map_err(|_| {
+            format!(
+                "Unknown permission mode '{}'. Use ask, accept-edits or read-only.",
+                s.trim()
+            )
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PermissionCheck {
+    Allowed,
+    NeedsApproval,
+    Denied(String),
+}
+
+/// A side-effecting tool call waiting for the user's decision.
+#[derive(Debug, Clone)]
+pub struct ApprovalRequest {
+    pub tool_name: String,
+    /// One-line description, e.g. "str_replace src/main.rs"
+    pub summary: String,
+    /// Unified diff of the proposed change, when it can be computed up front
+    pub diff: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApprovalDecision {
+    Approve,
+    /// Approve this and every later edit in the session
+    ApproveForSession,
+    /// Reject, with the reason given by the user (may be empty)
+    Reject(String),
+}
+
+impl ApprovalDecision {
+    /// Parses the answer typed at the approval prompt. Anything unrecognised rejects.
+    pub fn from_answer(answer: &str, reason: &str) -> Self {
+        match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => ApprovalDecision::Approve,
+            "a" | "always" => ApprovalDecision::ApproveForSession,
+            _ => ApprovalDecision::Reject(reason.trim().to_string()),
+        }
+    }
+
+    /// Message sent back to the model as the failed tool result when the user rejects a call.
+    pub fn rejection_message(reason: &str) -> String {
+        if reason.is_empty() {
+            "The user rejected this change.".to_string()
+        } else {
+            format!("The user rejected this change: {}", reason)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path_patterns::PathPatterns;
+
+    #[test]
+    fn test_permission_checks() {
+        assert_eq!(
+            PermissionMode::ReadOnly.check(false),
+            PermissionCheck::Allowed
+        );
+        assert_eq!(
+            PermissionMode::Ask.check(true),
+            PermissionCheck::NeedsApproval
+        );
+        assert_eq!(
+            PermissionMode::AcceptEdits.check(true),
+            PermissionCheck::Allowed
+        );
+        assert!(matches!(
+            PermissionMode::ReadOnly.check(true),
+            PermissionCheck::Denied(_)
+        ));
+    }
+
+    #[test]
+    fn test_path_rules() {
+        let rules = PermissionSettings {
+            mode: PermissionMode::Ask,
+            allow_edits: PathPatterns::new(&["docs/**".to_string()]).unwrap(),
+            deny: PathPatterns::new(&[".env".to_string()]).unwrap(),
+        };
+        let check = |mode, path: &str, side_effecting| {
+            check_tool_call(mode, &rules, Some(Path::new(path)), side_effecting)
+        };
+        assert!(matches!(
+            check(PermissionMode::AcceptEdits, ".env", false),
+            PermissionCheck::Denied(_)
+        ));
+        assert_eq!(
+            check(PermissionMode::Ask, "docs/intro.md", true),
+            PermissionCheck::Allowed
+        );
+        assert_eq!(
+            check(PermissionMode::Ask, "src/main.rs", true),
+            PermissionCheck::NeedsApproval
+        );
+        assert!(matches!(
+            check(PermissionMode::ReadOnly, "docs/intro.md", true),
+            PermissionCheck::Denied(_)
+        ));
+    }
+
+    #[test]
+    fn test_parse_mode_and_answers() {
+        assert_eq!(
+            "accept-edits".parse::<PermissionMode>(),
+            Ok(PermissionMode::AcceptEdits)
+        );
+        assert_eq!(
+            "Read-Only".parse::<PermissionMode>(),
+            Ok(PermissionMode::ReadOnly)
+        );
+        assert!("sometimes".parse::<PermissionMode>().is_err());
+
+        assert_eq!(
+            ApprovalDecision::from_answer("Y", ""),
+            ApprovalDecision::Approve
+        );
+        assert_eq!(
+            ApprovalDecision::from_answer("always", ""),
+            ApprovalDecision::ApproveForSession
+        );
+        assert_eq!(
+            ApprovalDecision::from_answer("n", " wrong file "),
+            ApprovalDecision::Reject("wrong file".to_string())
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use std::io::{self, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use crate::commands::COMMANDS;
+use crate::mentions::complete_path;
+
+const PROMPT: &str = "> ";
+/// Piped input beyond this is cut from the front, since the end of a log is usually what matters
+const MAX_PIPED_BYTES: usize = 200_000;
+
+/// Line editor for the interactive loop, with history and tab completion of slash commands and `@path` mentions.
+pub struct Prompt {
+    editor: Editor<PromptHelper, DefaultHistory>,
+}
+
+impl Prompt {
+    pub fn new(working_dir: &Path) -> rustyline::Result<Self> {
+        let mut editor = Editor::new()?;
+        editor.set_helper(Some(PromptHelper {
+            working_dir: working_dir.to_path_buf(),
+        }));
+        Ok(Self { editor })
+    }
+
+    /// Reads one trimmed line. Returns `Ok(None)` on Ctrl-C or Ctrl-D.
+    pub fn read_line(&mut self) -> rustyline::Result<Option<String>> {
+        match self.editor.readline(PROMPT) {
+            Ok(line) => {
+                let line = line.trim().to_string();
+                if !line.is_empty() {
+                    self.editor.add_history_entry(line.as_str())?;
+                }
+                Ok(Some(line))
+            }
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Reads everything piped into stdin. Returns `None` when stdin is a terminal.
+pub fn read_piped_stdin() -> io::Result<Option<String>> {
+    let stdin = io::stdin();
+    if stdin.is_terminal() {
+        return Ok(None);
+    }
+    let mut bytes = Vec::new();
+    stdin.lock().read_to_end(&mut bytes)?;
+    Ok(Some(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+/// Builds the message for a non-interactive run from the `-p` prompt and whatever was piped in.
+///
+/// Piped content is attached to the prompt as context; without a prompt it is the message itself.
+/// Returns `None` when there is nothing to send.
+pub fn one_shot_message(prompt: Option<&str>, piped: Option<&str>) -> Option<String> {
+    let prompt = prompt.map(str::trim).filter(|prompt| !prompt.is_empty());
+    let piped = piped
+        .map(|piped| piped.trim_end())
+        .filter(|piped| !piped.trim().is_empty());
+    match (prompt, piped) {
+        (Some(prompt), Some(piped)) => {
+            let (piped, truncated) = keep_tail(piped, MAX_PIPED_BYTES);
+            let note = if truncated {
+                format!(
+                    "[earlier input cut, showing the last {} bytes]\n",
+                    MAX_PIPED_BYTES
+                )
+            } else {
+                String::new()
+            };
+            Some(format!(
+                "{}\n\n<stdin>\n{}{}\n</stdin>",
+                prompt, note, piped
+            ))
+        }
+        (Some(prompt), None) => Some(prompt.to_string()),
+        (None, Some(piped)) => Some(keep_tail(piped.trim_start(), MAX_PIPED_BYTES).0.to_string()),
+        (None, None) => None,
+    }
+}
+
+/// The last `max_bytes` of `text`, starting on a character boundary.
+fn keep_tail(text: &str, max_bytes: usize) -> (&str, bool) {
+    if text.len() <= max_bytes {
+        return (text, false);
+    }
+    let mut start = text.len() - max_bytes;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    (&text[start..], true)
+}
+
+pub struct PromptHelper {
+    working_dir: PathBuf,
+}
+
+impl Completer for PromptHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        Ok(complete_input(&line[..pos], &self.working_dir))
+    }
+}
+
+/// Completes the word before the cursor: an `@path` mention or a slash command.
+/// Returns the byte offset the candidates replace from, as rustyline expects.
+pub fn complete_input(before_cursor: &str, working_dir: &Path) -> (usize, Vec<Pair>) {
+    let word = before_cursor
+        .rsplit(char::is_whitespace)
+        .next()
+        .unwrap_or("");
+    if let Some(partial) = word.strip_prefix('@') {
+        let candidates = complete_path(partial, working_dir)
+            .into_iter()
+            .map(|path| Pair {
+                display: path.clone(),
+                // Finished file names get a space; directories stay open for further completion
+                replacement: if path.ends_with('/') {
+                    path
+                } else {
+                    format!("{} ", path)
+                },
+            })
+            .collect();
+        return (before_cursor.len() - partial.len(), candidates);
+    }
+    (0, complete_command(before_cursor))
+}
+
+/// Completes a partially typed slash command name, e.g. `/he` -> `/help`.
+pub fn complete_command(input: &str) -> Vec<Pair> {
+    if !input.starts_with('/') || input.contains(char::is_whitespace) {
+        return Vec::new();
+    }
+    COMMANDS
+        .iter()
+        .filter(|command| command.name.starts_with(&input[1..]))
+        .map(|command| Pair {
+            display: format!("/{}", command.name),
+            replacement: format!("/{} ", command.name),
+        })
+        .collect()
+}
+
+impl Hinter for PromptHelper {
+    type Hint = String;
+}
+
+impl Highlighter for PromptHelper {}
+
+impl Validator for PromptHelper {}
+
+impl Helper for PromptHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complete_command() {
+        let candidates = complete_command("/he");
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].replacement, "/help ");
+        assert!(complete_command("/help me").is_empty());
+        assert!(complete_command("hello").is_empty());
+    }
+
+    #[test]
+    fn test_one_shot_message() {
+        assert_eq!(
+            one_shot_message(Some("why is this failing?"), Some("test foo ... FAILED\n"))
+                .as_deref(),
+            Some("why is this failing?\n\n<stdin>\ntest foo ... FAILED\n</stdin>")
+        );
+        assert_eq!(
+            one_shot_message(None, Some("explain this\n")).as_deref(),
+            Some("explain this")
+        );
+        assert_eq!(
+            one_shot_message(Some("hi"), Some("  \n")).as_deref(),
+            Some("hi")
+        );
+        assert_eq!(one_shot_message(None, Some("")), None);
+        assert_eq!(keep_tail("aé", 1), ("", true));
+        assert_eq!(keep_tail("abc", 2), ("bc", true));
+    }
+
+    #[test]
+    fn test_complete_mention() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::create_dir(directory.path().join("src")).unwrap();
+        std::fs::write(directory.path().join("src/main.rs"), "").unwrap();
+
+        let (start, candidates) = complete_input("explain @src/ma", directory.path());
+        assert_eq!(start, "explain @".len());
+        assert_eq!(candidates[0].replacement, "src/main.rs ");
+        let (_, candidates) = complete_input("@s", directory.path());
+        assert_eq!(candidates[0].replacement, "src/");
+    }
+}
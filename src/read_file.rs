@@ -0,0 +1,450 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::text_file::{self, FileContents, TextEncoding};
+
+/// Lines returned when the model doesn't ask for a `limit`
+pub const DEFAULT_LINE_LIMIT: usize = 2000;
+/// Longer lines (minified code, data) are cut, so one line can't fill the context
+const MAX_LINE_CHARS: usize = 2000;
+const CHARS_PER_TOKEN: usize = 4;
+/// A file isn't started once less than this is left of the budget
+const MIN_FILE_CHARS: usize = 200;
+/// Files left out for the budget are named up to this many
+const MAX_SKIPPED_LISTED: usize = 20;
+
+/// Which files, and which lines of them, to read.
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    /// Paths as the model gave them; in `file_paths` they may be globs
+    pub paths: Vec<String>,
+    /// First line to return from each file, counting from 1
+    pub offset: usize,
+    /// How many lines to return from each file at most
+    pub limit: usize,
+    /// Larger files are refused; `None` for no limit
+    pub max_bytes: Option<u64>,
+    /// Roughly how many tokens all files together may take. `None` for no limit
+    pub budget_tokens: Option<usize>,
+}
+
+/// The `read_file` tool input: `file_path`, `file_paths`, or both.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReadInput {
+    file_path: Option<String>,
+    #[serde(default)]
+    file_paths: Vec<String>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+impl ReadOptions {
+    pub fn from_tool_input(input: &Value) -> Result<Self, String> {
+        let input: ReadInput = serde_json::from_value(input.clone())
+            .map_err(|e| format!("Invalid read_file input: {}", e))?;
+        if input.offset == Some(0) {
+            return Err("offset counts lines from 1".to_string());
+        }
+        if input.limit == Some(0) {
+            return Err("limit must be at least 1".to_string());
+        }
+        let paths: Vec<String> = input
+            .file_path
+            .into_iter()
+            .chain(input.file_paths)
+            .collect();
+        if paths.is_empty() {
+            return Err("Give the file to read in file_path, or several in file_paths".to_string());
+        }
+        Ok(Self {
+            paths,
+            offset: input.offset.unwrap_or(1),
+            limit: input.limit.unwrap_or(DEFAULT_LINE_LIMIT),
+            max_bytes: None,
+            budget_tokens: None,
+        })
+    }
+
+    /// True for a plain `file_path` read, answered with the file alone rather than a batch.
+    /// Paths are relative to `root`.
+    pub fn is_single(&self, root: &Path) -> bool {
+        matches!(self.paths.as_slice(), [path] if !is_glob(path, root))
+    }
+
+    pub fn budget_chars(&self) -> Option<usize> {
+        self.budget_tokens
+            .filter(|&tokens| tokens > 0)
+            .map(|tokens| tokens * CHARS_PER_TOKEN)
+    }
+}
+
+/// Whether a requested path (relative to `root`) is a pattern to expand rather than a file name.
+/// A path that exists is always a file name, so `app/[id]/page.tsx` reads that file.
+pub fn is_glob(path: &str, root: &Path) -> bool {
+    path.contains(['*', '?', '[', '{']) && root.join(path).symlink_metadata().is_err()
+}
+
+/// A read file: the requested lines for the model, and the whole text as it was read.
+#[derive(Debug)]
+pub struct FileRead {
+    /// A size header, the lines prefixed with their numbers, and what is left to read;
+    /// for binary files, their type and size
+    pub output: String,
+    /// `None` for binary files
+    pub text: Option<String>,
+}
+
+/// Reads `path` and numbers the requested lines like `cat -n`, so the model can target
+/// `insert` and follow-up reads by line number. Stops early, with a notice saying where to
+/// read on, rather than go over `max_chars`. The output names the file as `display`.
+pub fn read_file(
+    path: &Path,
+    display: &str,
+    options: &ReadOptions,
+    max_chars: Option<usize>,
+) -> io::Result<FileRead> {
+    match text_file::read(path, options.max_bytes.unwrap_or(u64::MAX))? {
+        FileContents::Text(file) => {
+            let lines = LineRange {
+                offset: options.offset,
+                limit: options.limit,
+                max_chars: max_chars.unwrap_or(usize::MAX),
+            };
+            let output = format_lines(display, &file.text, file.encoding, file.size, &lines)?;
+            Ok(FileRead {
+                output,
+                text: Some(file.text),
+            })
+        }
+        FileContents::Binary(binary) => Ok(FileRead {
+            output: format!("{}\n", binary.describe(Path::new(display))),
+            text: None,
+        }),
+    }
+}
+
+/// One file of a batch: where to read it, or why it can't be read.
+#[derive(Debug)]
+pub struct BatchFile {
+    /// As shown to the model
+    pub display: String,
+    pub path: Result<PathBuf, String>,
+}
+
+/// The result of reading several files.
+#[derive(Debug)]
+pub struct BatchRead {
+    /// One `<file>` block per file, with its lines or its error
+    pub output: String,
+    /// The full text of every text file read, for `FileTracker`
+    pub texts: Vec<(PathBuf, String)>,
+}
+
+/// Reads every file in `files`, within `options.budget_tokens` overall. A file that fails is
+/// reported in its block without affecting the others; files left once the budget runs out
+/// are named at the end so the model can read them in another call.
+pub fn read_batch(files: &[BatchFile], options: &ReadOptions) -> BatchRead {
+    let mut remaining = options.budget_chars().unwrap_or(usize::MAX);
+    let mut output = String::new();
+    let mut texts = Vec::new();
+    let (mut read, mut failed) = (0, 0);
+    let mut skipped = Vec::new();
+
+    for file in files {
+        let result = match &file.path {
+            Err(reason) => Err(reason.clone()),
+            Ok(_) if remaining < MIN_FILE_CHARS => {
+                skipped.push(file.display.as_str());
+                continue;
+            }
+            Ok(path) => read_file(path, &file.display, options, Some(remaining))
+                .map_err(|e| e.to_string())
+                .map(|contents| (path, contents)),
+        };
+        let block = match result {
+            Ok((path, contents)) => {
+                read += 1;
+                if let Some(text) = contents.text {
+                    texts.push((path.clone(), text));
+                }
+                format!(
+                    "<file path=\"{}\">\n{}</file>\n",
+                    file.display, contents.output
+                )
+            }
+            Err(reason) => {
+                failed += 1;
+                format!(
+                    "<file path=\"{}\">\nError: {}\n</file>\n",
+                    file.display, reason
+                )
+            }
+        };
+        remaining = remaining.saturating_sub(block.len());
+        output.push_str(&block);
+    }
+
+    let mut summary = format!(
+        "Read {} of {} {}",
+        read,
+        files.len(),
+        if files.len() == 1 { "file" } else { "files" }
+    );
+    if failed > 0 {
+        summary.push_str(&format!(", {} failed", failed));
+    }
+    if !skipped.is_empty() {
+        summary.push_str(&format!(
+            ", {} left out to stay within about {} tokens",
+            skipped.len(),
+            options.budget_tokens.unwrap_or_default()
+        ));
+        let mut named = skipped[..skipped.len().min(MAX_SKIPPED_LISTED)].join(", ");
+        if skipped.len() > MAX_SKIPPED_LISTED {
+            named.push_str(&format!(" and {} more", skipped.len() - MAX_SKIPPED_LISTED));
+        }
+        output.push_str(&format!(
+            "Not read: {}. Read them in another call.\n",
+            named
+        ));
+    }
+    BatchRead {
+        output: format!("{}:\n{}", summary, output),
+        texts,
+    }
+}
+
+/// Which lines `format_lines` shows.
+struct LineRange {
+    offset: usize,
+    limit: usize,
+    max_chars: usize,
+}
+
+fn format_lines(
+    display: &str,
+    text: &str,
+    encoding: TextEncoding,
+    size: u64,
+    range: &LineRange,
+) -> io::Result<String> {
+    let LineRange {
+        offset,
+        limit,
+        max_chars,
+    } = *range;
+    let lines: Vec<&str> = text.lines().collect();
+    let total = lines.len();
+    let mut output = format!(
+        "{} ({} bytes, {} {}",
+        display,
+        size,
+        total,
+        if total == 1 { "line" } else { "lines" }
+    );
+    // Edits are saved back in the same encoding
+    if encoding != TextEncoding::UTF8 {
+        output.push_str(&format!(", {}", encoding));
+    }
+    output.push(')');
+    if total == 0 {
+        output.push_str(": empty file\n");
+        return Ok(output);
+    }
+    if offset > total {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "offset {} is past the end of {} ({} lines)",
+                offset, display, total
+            ),
+        ));
+    }
+
+    let mut end = total.min(offset.saturating_add(limit) - 1);
+    let width = end.to_string().len();
+    let mut numbered = String::new();
+    for (number, line) in lines[offset - 1..end]
+        .iter()
+        .enumerate()
+        .map(|(index, line)| (offset + index, line))
+    {
+        let line = match line.char_indices().nth(MAX_LINE_CHARS) {
+            Some((cut, _)) => format!(
+                "{:>width$}\t{}… (line cut at {} characters)\n",
+                number,
+                &line[..cut],
+                MAX_LINE_CHARS
+            ),
+            None => format!("{:>width$}\t{}\n", number, line),
+        };
+        if output.len() + numbered.len() + line.len() > max_chars {
+            end = number - 1;
+            break;
+        }
+        numbered.push_str(&line);
+    }
+    if (offset == 1 && end == total) || end < offset {
+        output.push_str(":\n");
+    } else {
+        output.push_str(&format!(", lines {}-{}:\n", offset, end));
+    }
+    output.push_str(&numbered);
+    if end < total {
+        output.push_str(&format!(
+            "... file truncated, {} lines remaining. Read on with offset {}.\n",
+            total - end,
+            end + 1
+        ));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs;
+
+    fn whole_file() -> ReadOptions {
+        ReadOptions {
+            paths: Vec::new(),
+            offset: 1,
+            limit: DEFAULT_LINE_LIMIT,
+            max_bytes: None,
+            budget_tokens: None,
+        }
+    }
+
+    #[test]
+    fn test_read_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("notes.txt");
+        let text: String = (1..=12)
+            .map(|number| format!("line {}\n", number))
+            .collect();
+        fs::write(&path, &text).unwrap();
+
+        let read = read_file(&path, "notes.txt", &whole_file(), None).unwrap();
+        assert_eq!(read.text.as_deref(), Some(text.as_str()));
+        assert!(read.output.ends_with("(87 bytes, 12 lines):\n 1\tline 1\n 2\tline 2\n 3\tline 3\n 4\tline 4\n 5\tline 5\n 6\tline 6\n 7\tline 7\n 8\tline 8\n 9\tline 9\n10\tline 10\n11\tline 11\n12\tline 12\n"));
+
+        let options =
+            ReadOptions::from_tool_input(&json!({"file_path": path, "offset": 9, "limit": 2}))
+                .unwrap();
+        assert!(options.is_single(directory.path()));
+        let read = read_file(&path, "notes.txt", &options, None).unwrap();
+        assert!(read.output.ends_with(
+            "(87 bytes, 12 lines), lines 9-10:\n 9\tline 9\n10\tline 10\n... file truncated, 2 lines remaining. Read on with offset 11.\n"
+        ));
+
+        let past_end = ReadOptions {
+            offset: 13,
+            ..options
+        };
+        assert!(read_file(&path, "notes.txt", &past_end, None)
+            .unwrap_err()
+            .to_string()
+            .contains("past the end"));
+        assert!(ReadOptions::from_tool_input(&json!({"file_path": "a", "offset": 0})).is_err());
+        assert!(ReadOptions::from_tool_input(&json!({"file_path": "a", "lines": 3})).is_err());
+        assert!(ReadOptions::from_tool_input(&json!({})).is_err());
+        let range = LineRange {
+            offset: 1,
+            limit: 10,
+            max_chars: usize::MAX,
+        };
+        assert_eq!(
+            format_lines("empty.txt", "", TextEncoding::UTF8, 0, &range).unwrap(),
+            "empty.txt (0 bytes, 0 lines): empty file\n"
+        );
+
+        fs::write(&path, b"\xff\xfeh\x00i\x00").unwrap();
+        assert!(read_file(&path, "notes.txt", &whole_file(), None)
+            .unwrap()
+            .output
+            .ends_with("(6 bytes, 1 line, UTF-16LE):\n1\thi\n"));
+        fs::write(&path, b"GIF89a\x01\x00\x01\x00\x00").unwrap();
+        let read = read_file(&path, "notes.txt", &whole_file(), None).unwrap();
+        assert!(read.text.is_none() && read.output.contains("binary file (GIF image, 11 bytes)"));
+        let too_big = ReadOptions {
+            max_bytes: Some(10),
+            ..whole_file()
+        };
+        assert!(read_file(&path, "notes.txt", &too_big, None)
+            .unwrap_err()
+            .to_string()
+            .contains("max_read_bytes"));
+    }
+
+    #[test]
+    fn test_read_batch() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path();
+        fs::write(root.join("a.rs"), "fn a() {}\n").unwrap();
+        let long: String = (1..=200)
+            .map(|number| format!("let x{} = {};\n", number, number))
+            .collect();
+        fs::write(root.join("b.rs"), &long).unwrap();
+        fs::write(root.join("c.rs"), "fn c() {}\n").unwrap();
+
+        let options =
+            ReadOptions::from_tool_input(&json!({"file_paths": ["a.rs", "src/*.rs"]})).unwrap();
+        assert!(!options.is_single(root));
+
+        // Glob characters in the name of a file that exists are part of the name
+        fs::create_dir_all(root.join("app/[id]")).unwrap();
+        fs::write(root.join("app/[id]/page.tsx"), "export default Page;\n").unwrap();
+        assert!(!is_glob("app/[id]/page.tsx", root));
+        assert!(
+            ReadOptions::from_tool_input(&json!({"file_path": "app/[id]/page.tsx"}))
+                .unwrap()
+                .is_single(root)
+        );
+        assert!(is_glob("app/[id]/*.tsx", root) && is_glob("src/{a,b}.rs", root));
+        let file = |name: &str| BatchFile {
+            display: name.to_string(),
+            path: Ok(root.join(name)),
+        };
+        let files = [
+            file("a.rs"),
+            file("missing.rs"),
+            BatchFile {
+                display: ".env".to_string(),
+                path: Err("denied".to_string()),
+            },
+            file("b.rs"),
+            file("c.rs"),
+        ];
+
+        let batch = read_batch(
+            &files,
+            &ReadOptions {
+                budget_tokens: Some(300),
+                ..options
+            },
+        );
+        assert!(batch.output.starts_with("Read 2 of 5 files, 2 failed, 1 left out to stay within about 300 tokens:\n<file path=\"a.rs\">\n"));
+        assert!(batch.output.contains("\n1\tfn a() {}\n"));
+        assert!(batch
+            .output
+            .contains("<file path=\".env\">\nError: denied\n</file>\n"));
+        assert!(batch
+            .output
+            .contains("<file path=\"missing.rs\">\nError: No such file"));
+        // The long file is cut to what is left of the budget, and the rest is named
+        assert!(batch
+            .output
+            .contains("lines remaining. Read on with offset"));
+        assert!(batch
+            .output
+            .ends_with("Not read: c.rs. Read them in another call.\n"));
+        // Only the summary, tags and notes go past the budget
+        assert!(batch.output.len() < 300 * CHARS_PER_TOKEN + 300);
+        assert_eq!(batch.texts.len(), 2);
+    }
+}
@@ -1,6 +1,21 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fs;
-use std::path::{Path, PathBuf};
 use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::file_metadata::{self, GitStatus, GitStatuses};
+use crate::ignore_rules::IgnoreRules;
+use crate::path_patterns::PathPatterns;
+
+/// Why a symlinked directory's contents are not listed
+const NOT_FOLLOWED: &str = "not followed";
+const BROKEN_LINK: &str = "broken link";
+const LINK_LOOP: &str = "link loop";
+const OUTSIDE_PROJECT: &str = "outside the project";
 
 const IGNORED_DIRS: &[&str] = &[
     "target",
@@ -21,85 +36,992 @@ const IGNORED_FILES: &[&str] = &[
     // Add any other common files to ignore
 ];
 
+/// Directories with more entries than this are collapsed even when the budget would allow them
+const MAX_ENTRIES_PER_DIRECTORY: usize = 40;
+/// How many entries of a collapsed directory are still listed
+const ENTRIES_SHOWN_WHEN_COLLAPSED: usize = 15;
+/// Images, fonts and the like beyond this many per directory are only counted
+const MAX_ASSETS_LISTED: usize = 3;
+/// Rough size of a token in characters, for turning the budget into output length
+const CHARS_PER_TOKEN: usize = 4;
+/// Room kept for a "… and 312 more files (*.rs: 280, *.toml: 32)" line
+const SUMMARY_LINE_CHARS: usize = 60;
+/// Extra room for the " (12 files)" suffix of a directory that is not expanded
+const COLLAPSED_SUFFIX_CHARS: usize = 14;
+/// A JSON node with its metadata takes about this many times the room of a tree line
+const JSON_SIZE_FACTOR: usize = 5;
+
+/// File kinds listed first when a directory has to be cut short. Anything else comes after
+/// docs and config, and assets come last.
+const SOURCE_EXTENSIONS: &[&str] = &[
+    "rs", "py", "js", "jsx", "ts", "tsx", "go", "java", "kt", "c", "h", "cc", "cpp", "hpp", "cs",
+    "rb", "php", "swift", "scala", "sh", "sql", "html", "css", "vue", "svelte",
+];
+const DOC_AND_CONFIG_EXTENSIONS: &[&str] = &[
+    "md", "toml", "json", "yaml", "yml", "txt", "cfg", "ini", "xml",
+];
+const ASSET_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "svg", "ico", "webp", "bmp", "woff", "woff2", "ttf", "otf", "eot",
+    "mp3", "mp4", "wav", "ogg", "webm", "pdf", "zip", "gz", "tar", "bin", "lock", "map", "min.js",
+];
+const ASSET_DIRECTORIES: &[&str] = &[
+    "assets", "static", "public", "images", "img", "fonts", "fixtures", "testdata", "vendor",
+];
+
+/// Which entries a scan lists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    /// Files and directories, as a tree
+    #[default]
+    All,
+    /// Only files, as a flat list of paths
+    Files,
+    /// Only directories, as a tree
+    Dirs,
+}
+
+/// How a scan is returned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanFormat {
+    /// An ASCII tree
+    #[default]
+    Tree,
+    /// `ScanNode`s as JSON, with file metadata
+    Json,
+}
+
+/// Which part of the project a scan covers and which entries it keeps.
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    /// Directory to scan, relative to the project root
+    pub path: PathBuf,
+    /// How many levels below `path` to list; `None` for no limit
+    pub max_depth: Option<usize>,
+    /// When not empty, only files matching one of these patterns are listed
+    pub include: PathPatterns,
+    /// Files and directories matching these patterns are left out
+    pub exclude: PathPatterns,
+    pub only: EntryKind,
+    pub format: ScanFormat,
+    /// Roughly how many tokens the output may take; larger trees are condensed. `None` for no limit
+    pub budget_tokens: Option<usize>,
+    /// List the contents of symlinked directories inside the project, instead of just the link
+    pub follow_symlinks: bool,
+}
+
+/// The `scan_directory` tool input. Every field is optional.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScanInput {
+    path: Option<String>,
+    max_depth: Option<usize>,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(default)]
+    only: EntryKind,
+    #[serde(default)]
+    format: ScanFormat,
+    #[serde(default)]
+    follow_symlinks: bool,
+}
+
+impl ScanOptions {
+    pub fn from_tool_input(input: &Value) -> Result<Self, String> {
+        let input: ScanInput = serde_json::from_value(input.clone())
+            .map_err(|e| format!("Invalid scan_directory input: {}", e))?;
+        let patterns = |key: &str, list: &[String]| {
+            PathPatterns::new(list).map_err(|e| format!("Invalid pattern in {}: {}", key, e))
+        };
+        Ok(Self {
+            path: PathBuf::from(input.path.unwrap_or_default()),
+            max_depth: input.max_depth,
+            include: patterns("include", &input.include)?,
+            exclude: patterns("exclude", &input.exclude)?,
+            only: input.only,
+            format: input.format,
+            budget_tokens: None,
+            follow_symlinks: input.follow_symlinks,
+        })
+    }
+}
+
+/// A scanned file or directory with its metadata, as returned in JSON.
+#[derive(Debug, Serialize)]
+pub struct ScanNode {
+    /// Relative to the project root; `.` for the root itself
+    pub path: String,
+    pub kind: NodeKind,
+    /// In bytes, for files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// For text files up to a couple of megabytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lines: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DateTime<Utc>>,
+    /// Set for files with uncommitted changes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git_status: Option<GitStatus>,
+    /// What a symlink points to, as written in the link
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Why a symlinked directory's contents are not listed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_followed: Option<&'static str>,
+    /// A directory at the depth limit, whose contents were not scanned
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub unexpanded: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<ScanNode>,
+    /// What was left out of `children` to stay within the budget, e.g. `312 more files (*.rs: 280)`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub omitted: Option<String>,
+    /// On the root: how to see what was left out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    File,
+    Directory,
+    /// A link that was not followed, or is broken
+    Symlink,
+}
+
+/// A scanned file or directory.
+struct Entry {
+    name: String,
+    /// Relative to the project root
+    path: PathBuf,
+    is_dir: bool,
+    children: Vec<Entry>,
+    /// A directory at the depth limit, listed without its contents
+    unexpanded: bool,
+    link: Option<EntryLink>,
+}
+
+/// How a symlink was handled by the scan.
+struct EntryLink {
+    target: String,
+    /// Set when the link points to a directory whose contents were not listed, or nowhere
+    not_followed: Option<&'static str>,
+}
+
 /// Scans the given directory path and generates a string representation of its tree structure.
 ///
-/// It excludes predefined directories (like "target", ".git") and files (like ".DS_Store").
+/// It excludes predefined directories (like "target", ".git") and files (like ".DS_Store"),
+/// plus anything the project's `ignore` rules exclude (`.gitignore` and friends).
 ///
 /// # Arguments
 /// * `dir_path` - The path to the directory to scan.
+/// * `ignore` - The ignore rules of the project containing `dir_path`.
 ///
 /// # Returns
 /// A `Result` containing the tree string or an `io::Error`.
-pub fn scan_directory_tree_from_path(dir_path: &Path) -> io::Result<String> {
-    let mut tree_string = String::new();
-    scan_directory_recursive(dir_path, 0, &mut tree_string, true)?;
-    Ok(tree_string)
+#[cfg(test)]
+pub fn scan_directory_tree_from_path(dir_path: &Path, ignore: &IgnoreRules) -> io::Result<String> {
+    scan_directory(dir_path, &ScanOptions::default(), ignore)
 }
 
-/// Scans the current working directory and generates a string representation of its tree structure.
+/// Scans the part of the project under `root` selected by `options`, as text in `options.format`.
 ///
-/// It excludes predefined directories (like "target", ".git") and files (like ".DS_Store").
+/// Fails when `options.path` is not a directory inside `root`, or is ignored.
+pub fn scan_directory(
+    root: &Path,
+    options: &ScanOptions,
+    ignore: &IgnoreRules,
+) -> io::Result<String> {
+    scan_with(root, options, ignore, &|directory| {
+        list_directory(root, directory, ignore)
+    })
+}
+
+/// Lists the non-ignored entries of a directory, given relative to the project root.
+pub type ListDirectory<'a> = dyn Fn(&Path) -> io::Result<Vec<Listed>> + 'a;
+
+/// Like `scan_directory`, reading directories through `list`, e.g. from a cached index.
+pub fn scan_with(
+    root: &Path,
+    options: &ScanOptions,
+    ignore: &IgnoreRules,
+    list: &ListDirectory,
+) -> io::Result<String> {
+    let tree = scan_entries(root, options, ignore, list)?;
+    if options.format == ScanFormat::Json {
+        let nodes = build_nodes(root, &tree, options);
+        return serde_json::to_string(&nodes).map_err(io::Error::other);
+    }
+
+    let budget_chars = budget_chars(options);
+    let mut output = String::new();
+    let condensed = match options.only {
+        EntryKind::Files => list_files(&tree, budget_chars, &mut output),
+        EntryKind::All | EntryKind::Dirs => {
+            let layout = layout(&tree, budget_chars);
+            output.push_str(&tree.name);
+            output.push('\n');
+            render_children(&tree, &layout, "", &mut output)
+        }
+    };
+    if let Some(note) = note(&tree, condensed, options) {
+        output.push_str(&format!("({})\n", note));
+    }
+    Ok(output)
+}
+
+/// Every non-ignored file under `root` matching `patterns`, relative to `root` and in path
+/// order, reading directories through `list`.
+pub fn find_files(
+    root: &Path,
+    patterns: &PathPatterns,
+    ignore: &IgnoreRules,
+    list: &ListDirectory,
+) -> io::Result<Vec<PathBuf>> {
+    let options = ScanOptions {
+        include: patterns.clone(),
+        only: EntryKind::Files,
+        ..Default::default()
+    };
+    let tree = scan_entries(root, &options, ignore, list)?;
+    let (files, _) = select_files(&tree, None, |_| 0);
+    Ok(files.into_iter().map(|file| file.path.clone()).collect())
+}
+
+fn scan_entries(
+    root: &Path,
+    options: &ScanOptions,
+    ignore: &IgnoreRules,
+    list: &ListDirectory,
+) -> io::Result<Entry> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    if options
+        .path
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(invalid(format!(
+            "{} must be a relative path inside the project",
+            options.path.display()
+        )));
+    }
+    let start = root.join(&options.path);
+    if !start.is_dir() {
+        return Err(invalid(format!(
+            "{} is not a directory",
+            options.path.display()
+        )));
+    }
+    let real_root = root.canonicalize()?;
+    let real_start = start.canonicalize()?;
+    if !real_start.starts_with(&real_root) {
+        return Err(invalid(format!(
+            "{} leads outside the project through a symlink",
+            options.path.display()
+        )));
+    }
+    if start != root && ignore.is_ignored(&start, true) {
+        return Err(invalid(format!(
+            "{} is excluded by the project's ignore rules",
+            options.path.display()
+        )));
+    }
+
+    // Without `.` components, so the path can be looked up in an index
+    let path: PathBuf = options
+        .path
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect();
+    let name = if path.as_os_str().is_empty() {
+        root.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    } else {
+        path.to_string_lossy().into_owned()
+    };
+    let walk = Walk {
+        root,
+        real_root,
+        options,
+        list,
+    };
+    let children = walk.collect(&path, 1, &mut vec![real_start])?;
+    let mut tree = Entry {
+        name,
+        path,
+        is_dir: true,
+        children,
+        unexpanded: false,
+        link: None,
+    };
+    if options.only == EntryKind::Dirs {
+        remove_files(&mut tree);
+    }
+    Ok(tree)
+}
+
+fn budget_chars(options: &ScanOptions) -> Option<usize> {
+    options
+        .budget_tokens
+        .filter(|&tokens| tokens > 0)
+        .map(|tokens| tokens * CHARS_PER_TOKEN)
+}
+
+/// Tells the model how to see what a scan left out, if anything.
+fn note(tree: &Entry, condensed: bool, options: &ScanOptions) -> Option<String> {
+    let mut notes = Vec::new();
+    if has_unexpanded(tree) {
+        notes.push(
+            "Directories ending in /… were not expanded; scan them with `path` to see inside."
+                .to_string(),
+        );
+    }
+    if has_unfollowed_links(tree) {
+        notes.push(format!(
+            "Symlinked directories marked ({}) can be listed with `follow_symlinks`.",
+            NOT_FOLLOWED
+        ));
+    }
+    if condensed {
+        let reason = match budget_chars(options) {
+            Some(_) => format!(
+                "Condensed to fit about {} tokens",
+                options.budget_tokens.unwrap_or_default()
+            ),
+            None => "Large directories were condensed".to_string(),
+        };
+        notes.push(format!(
+            "{}. Scan a subdirectory with `path`, or narrow the listing with `include`, `exclude` or `max_depth`, to see more.",
+            reason
+        ));
+    }
+    (!notes.is_empty()).then(|| notes.join(" "))
+}
+
+/// A directory entry that passed the built-in list and the ignore rules.
+#[derive(Debug, Clone)]
+pub struct Listed {
+    pub name: String,
+    /// A real directory; a symlink to one is described by `link` instead
+    pub is_dir: bool,
+    pub link: Option<Link>,
+}
+
+/// Where a symlink points.
+#[derive(Debug, Clone)]
+pub struct Link {
+    /// As written in the link, possibly relative to the directory holding it
+    pub target: PathBuf,
+    pub kind: LinkKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkKind {
+    Directory,
+    File,
+    Broken,
+}
+
+/// Reads the entries of `directory` (relative to `root`) that aren't ignored, sorted by name.
 ///
-/// # Returns
-/// A `Result` containing the tree string or an `io::Error`.
-pub fn scan_current_working_directory() -> io::Result<String> {
-    let current_dir = std::env::current_dir()?;
-    println!("Scanning directory: {}", current_dir.display());
-    scan_directory_tree_from_path(&current_dir)
+/// Symlinks are reported as links without being followed. Sockets, FIFOs and device files,
+/// and links to them, are left out: there is nothing in them to read.
+pub fn list_directory(
+    root: &Path,
+    directory: &Path,
+    ignore: &IgnoreRules,
+) -> io::Result<Vec<Listed>> {
+    let mut entries = Vec::new();
+    for dir_entry in fs::read_dir(root.join(directory))?.filter_map(Result::ok) {
+        let full_path = dir_entry.path();
+        // The type comes with the listing; only symlinks need a stat to see what they point to
+        let Ok(file_type) = dir_entry.file_type() else {
+            continue;
+        };
+        let link = if file_type.is_symlink() {
+            let kind = match fs::metadata(&full_path) {
+                Ok(metadata) if metadata.is_dir() => LinkKind::Directory,
+                Ok(metadata) if metadata.is_file() => LinkKind::File,
+                Ok(_) => continue,
+                Err(_) => LinkKind::Broken,
+            };
+            Some(Link {
+                target: fs::read_link(&full_path).unwrap_or_default(),
+                kind,
+            })
+        } else if file_type.is_dir() || file_type.is_file() {
+            None
+        } else {
+            continue;
+        };
+        let is_dir = file_type.is_dir();
+        let name = dir_entry.file_name().to_string_lossy().into_owned();
+
+        let looks_like_dir = is_dir
+            || link
+                .as_ref()
+                .is_some_and(|link| link.kind == LinkKind::Directory);
+        let builtin = if looks_like_dir {
+            IGNORED_DIRS
+        } else {
+            IGNORED_FILES
+        };
+        if builtin.contains(&name.as_str()) || ignore.is_ignored_entry(&full_path, looks_like_dir) {
+            continue;
+        }
+        entries.push(Listed { name, is_dir, link });
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name)); // Sort for consistent order
+    Ok(entries)
 }
 
-fn scan_directory_recursive(
-    dir_path: &Path,
-    depth: usize,
-    tree_string: &mut String,
-    is_last: bool,
-) -> io::Result<()> {
-    let file_name = dir_path.file_name().unwrap_or_default().to_string_lossy();
-
-    // Indentation logic
-    for i in 0..depth {
-        if i == depth -1 {
-            tree_string.push_str(if is_last { "└── " } else { "├── " });
+/// What a scan needs while descending the tree.
+struct Walk<'a> {
+    root: &'a Path,
+    /// `root` with symlinks resolved, the boundary links may not lead out of
+    real_root: PathBuf,
+    options: &'a ScanOptions,
+    list: &'a ListDirectory<'a>,
+}
+
+impl Walk<'_> {
+    /// Reads the entries of `directory` that pass the scan's filters, `depth` levels down.
+    /// `real` holds the resolved path of every directory from the start down to `directory`.
+    fn collect(
+        &self,
+        directory: &Path,
+        depth: usize,
+        real: &mut Vec<PathBuf>,
+    ) -> io::Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        for Listed { name, is_dir, link } in (self.list)(directory)? {
+            let path = directory.join(&name);
+            if self.options.exclude.matches(&path) {
+                continue;
+            }
+            let is_dir = link
+                .as_ref()
+                .map_or(is_dir, |link| link.kind == LinkKind::Directory);
+            let mut link = link.map(|link| EntryLink {
+                target: link.target.to_string_lossy().into_owned(),
+                not_followed: (link.kind == LinkKind::Broken).then_some(BROKEN_LINK),
+            });
+
+            if is_dir {
+                let expand = self
+                    .options
+                    .max_depth
+                    .is_none_or(|max_depth| depth < max_depth);
+                let real_path = match &mut link {
+                    _ if !expand => None,
+                    None => real.last().map(|parent| parent.join(&name)),
+                    Some(link) => self
+                        .follow(&path, real)
+                        .map_err(|reason| link.not_followed = Some(reason))
+                        .ok(),
+                };
+                let children = match real_path {
+                    Some(real_path) => {
+                        real.push(real_path);
+                        let children = self.collect(&path, depth + 1, real);
+                        real.pop();
+                        children?
+                    }
+                    None => Vec::new(),
+                };
+                // With `include`, directories only matter for the files they lead to
+                if expand && !self.options.include.is_empty() && children.is_empty() {
+                    continue;
+                }
+                entries.push(Entry {
+                    name,
+                    path,
+                    is_dir,
+                    children,
+                    unexpanded: !expand,
+                    link,
+                });
+            } else if self.options.include.is_empty() || self.options.include.matches(&path) {
+                entries.push(Entry {
+                    name,
+                    path,
+                    is_dir,
+                    children: Vec::new(),
+                    unexpanded: false,
+                    link,
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Resolves a symlinked directory the scan may descend into, or says why it may not:
+    /// following is off, it leads outside the project, or it leads back up the current path.
+    fn follow(&self, path: &Path, real: &[PathBuf]) -> Result<PathBuf, &'static str> {
+        if !self.options.follow_symlinks {
+            return Err(NOT_FOLLOWED);
+        }
+        let target = self
+            .root
+            .join(path)
+            .canonicalize()
+            .map_err(|_| BROKEN_LINK)?;
+        if !target.starts_with(&self.real_root) {
+            Err(OUTSIDE_PROJECT)
+        } else if real.iter().any(|directory| directory.starts_with(&target)) {
+            Err(LINK_LOOP)
         } else {
-            // This part needs improvement to correctly draw parent connectors
-            // For simplicity, just adding spaces now. A more robust solution
-            // would track `is_last` for parent directories.
-            tree_string.push_str("    "); 
-        }
-    }
-    tree_string.push_str(&file_name);
-    tree_string.push('\n');
-
-    if dir_path.is_dir() {
-        let mut entries: Vec<PathBuf> = fs::read_dir(dir_path)?
-            .filter_map(Result::ok)
-            .map(|entry| entry.path())
-            .filter(|path| {
-                let name = path.file_name().unwrap_or_default().to_string_lossy();
-                if path.is_dir() {
-                    !IGNORED_DIRS.contains(&name.as_ref())
-                } else {
-                    !IGNORED_FILES.contains(&name.as_ref())
+            Ok(target)
+        }
+    }
+}
+
+fn remove_files(entry: &mut Entry) {
+    entry.children.retain(|child| child.is_dir);
+    entry.children.iter_mut().for_each(remove_files);
+}
+
+const ASSET_PRIORITY: u8 = 4;
+
+/// Lower is listed first when a directory has to be cut short: directories, then source code,
+/// docs and config, everything else, and finally assets.
+fn priority(entry: &Entry) -> u8 {
+    if entry.is_dir {
+        return if ASSET_DIRECTORIES.contains(&entry.name.to_lowercase().as_str()) {
+            ASSET_PRIORITY
+        } else {
+            0
+        };
+    }
+    let name = entry.name.to_lowercase();
+    let has_extension = |extensions: &[&str]| {
+        extensions.iter().any(|extension| {
+            name.strip_suffix(extension)
+                .is_some_and(|stem| stem.ends_with('.'))
+        })
+    };
+    if has_extension(ASSET_EXTENSIONS) {
+        ASSET_PRIORITY
+    } else if has_extension(SOURCE_EXTENSIONS) {
+        1
+    } else if has_extension(DOC_AND_CONFIG_EXTENSIONS) {
+        2
+    } else {
+        3
+    }
+}
+
+/// Characters one tree line takes at `depth`.
+fn line_cost(entry: &Entry, depth: usize) -> usize {
+    let suffix = if entry.is_dir {
+        COLLAPSED_SUFFIX_CHARS
+    } else {
+        0
+    };
+    let link = entry
+        .link
+        .as_ref()
+        .map_or(0, |link| link.target.chars().count() + 4);
+    depth * 4 + entry.name.chars().count() + 2 + suffix + link
+}
+
+/// Decides which children of each directory to list within `budget_chars`, breadth first, so
+/// the top of the tree is always shown and deep directories are the first to be collapsed.
+/// Directories missing from the result are listed without their contents.
+fn layout(tree: &Entry, budget_chars: Option<usize>) -> HashMap<&Path, Vec<&Entry>> {
+    let mut shown = HashMap::new();
+    let mut remaining = budget_chars
+        .unwrap_or(usize::MAX)
+        .saturating_sub(tree.name.len() + 1);
+    let mut queue = VecDeque::from([(tree, 0)]);
+
+    while let Some((directory, depth)) = queue.pop_front() {
+        let summary_cost = (depth + 1) * 4 + SUMMARY_LINE_CHARS;
+        if !directory.children.is_empty() && remaining < summary_cost {
+            continue;
+        }
+        let mut candidates: Vec<&Entry> = directory.children.iter().collect();
+        candidates.sort_by_key(|entry| priority(entry));
+        let assets = candidates
+            .iter()
+            .filter(|entry| !entry.is_dir && priority(entry) == ASSET_PRIORITY)
+            .count();
+        let mut cap = candidates.len() - assets.saturating_sub(MAX_ASSETS_LISTED);
+        if cap > MAX_ENTRIES_PER_DIRECTORY {
+            cap = ENTRIES_SHOWN_WHEN_COLLAPSED;
+        }
+        let total: usize = candidates
+            .iter()
+            .take(cap)
+            .map(|entry| line_cost(entry, depth + 1))
+            .sum();
+
+        let mut selected = Vec::new();
+        if cap == candidates.len() && total <= remaining {
+            selected = candidates;
+            remaining -= total;
+        } else {
+            remaining -= summary_cost;
+            for entry in candidates.into_iter().take(cap) {
+                let cost = line_cost(entry, depth + 1);
+                if cost > remaining {
+                    break;
                 }
-            })
-            .collect();
+                remaining -= cost;
+                selected.push(entry);
+            }
+        }
+
+        for entry in &selected {
+            if entry.is_dir && !entry.unexpanded {
+                queue.push_back((entry, depth + 1));
+            }
+        }
+        selected.sort_by(|a, b| a.name.cmp(&b.name));
+        shown.insert(directory.path.as_path(), selected);
+    }
+    shown
+}
+
+/// Converts the scanned tree to nodes with metadata, within the same budget as the text tree.
+fn build_nodes(root: &Path, tree: &Entry, options: &ScanOptions) -> ScanNode {
+    let budget_chars = budget_chars(options).map(|chars| chars / JSON_SIZE_FACTOR);
+    let git = GitStatuses::load(root);
+    let mut condensed = false;
+
+    let mut node = match options.only {
+        EntryKind::Files => {
+            let (listed, left_out) = select_files(tree, budget_chars, |file| {
+                file.path.to_string_lossy().len() + 1
+            });
+            let mut node = node_for(root, tree, &git);
+            node.children = listed
+                .into_iter()
+                .map(|file| node_for(root, file, &git))
+                .collect();
+            if !left_out.is_empty() {
+                node.omitted = Some(Tally::of(&left_out).describe());
+                condensed = true;
+            }
+            node
+        }
+        EntryKind::All | EntryKind::Dirs => nest(
+            root,
+            tree,
+            &layout(tree, budget_chars),
+            &git,
+            &mut condensed,
+        ),
+    };
+    node.note = note(tree, condensed, options);
+    node
+}
+
+fn nest(
+    root: &Path,
+    entry: &Entry,
+    layout: &HashMap<&Path, Vec<&Entry>>,
+    git: &GitStatuses,
+    condensed: &mut bool,
+) -> ScanNode {
+    let mut node = node_for(root, entry, git);
+    if !entry.is_dir || entry.unexpanded {
+        return node;
+    }
+    let hidden = match layout.get(entry.path.as_path()) {
+        Some(shown) => {
+            node.children = shown
+                .iter()
+                .map(|child| nest(root, child, layout, git, condensed))
+                .collect();
+            hidden_children(entry, shown)
+        }
+        None => entry.children.iter().collect(),
+    };
+    if !hidden.is_empty() {
+        node.omitted = Some(Tally::of(&hidden).describe());
+        *condensed = true;
+    }
+    node
+}
+
+fn node_for(root: &Path, entry: &Entry, git: &GitStatuses) -> ScanNode {
+    let full_path = root.join(&entry.path);
+    let metadata = fs::metadata(&full_path).ok();
+    let size = metadata
+        .as_ref()
+        .filter(|_| !entry.is_dir)
+        .map(|metadata| metadata.len());
+    ScanNode {
+        path: if entry.path.as_os_str().is_empty() {
+            ".".to_string()
+        } else {
+            entry.path.to_string_lossy().into_owned()
+        },
+        kind: match &entry.link {
+            Some(EntryLink {
+                not_followed: Some(_),
+                ..
+            }) => NodeKind::Symlink,
+            _ if entry.is_dir => NodeKind::Directory,
+            _ => NodeKind::File,
+        },
+        size,
+        lines: size.and_then(|size| file_metadata::count_lines(&full_path, size)),
+        language: if entry.is_dir {
+            None
+        } else {
+            file_metadata::language(&entry.path)
+        },
+        modified: metadata
+            .and_then(|metadata| metadata.modified().ok())
+            .map(DateTime::<Utc>::from),
+        git_status: git.get(&entry.path),
+        target: entry.link.as_ref().map(|link| link.target.clone()),
+        not_followed: entry.link.as_ref().and_then(|link| link.not_followed),
+        unexpanded: entry.unexpanded,
+        children: Vec::new(),
+        omitted: None,
+        note: None,
+    }
+}
+
+fn hidden_children<'a>(directory: &'a Entry, shown: &[&Entry]) -> Vec<&'a Entry> {
+    directory
+        .children
+        .iter()
+        .filter(|child| !shown.iter().any(|entry| std::ptr::eq(*entry, *child)))
+        .collect()
+}
+
+/// Draws the listed children of `directory` below `prefix`. Returns true when anything was left out.
+fn render_children(
+    directory: &Entry,
+    layout: &HashMap<&Path, Vec<&Entry>>,
+    prefix: &str,
+    tree_string: &mut String,
+) -> bool {
+    let Some(shown) = layout.get(directory.path.as_path()) else {
+        return false;
+    };
+    let hidden = hidden_children(directory, shown);
+    let mut condensed = !hidden.is_empty();
+
+    for (index, child) in shown.iter().enumerate() {
+        let is_last = index + 1 == shown.len() && hidden.is_empty();
+        tree_string.push_str(prefix);
+        tree_string.push_str(if is_last { "└── " } else { "├── " });
+        tree_string.push_str(&child.name);
+        if child.is_dir {
+            tree_string.push_str(if child.unexpanded { "/…" } else { "/" });
+        }
+        if let Some(link) = &child.link {
+            tree_string.push_str(" -> ");
+            tree_string.push_str(&link.target);
+            if let Some(reason) = link.not_followed {
+                tree_string.push_str(&format!(" ({})", reason));
+            }
+        }
+        let followed = child
+            .link
+            .as_ref()
+            .is_none_or(|link| link.not_followed.is_none());
+        if child.is_dir
+            && !child.unexpanded
+            && followed
+            && !layout.contains_key(child.path.as_path())
+        {
+            tree_string.push_str(&format!(
+                " ({})",
+                Tally::of(&child.children).describe_counts()
+            ));
+            condensed = true;
+        }
+        tree_string.push('\n');
+
+        if child.is_dir {
+            let child_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+            condensed |= render_children(child, layout, &child_prefix, tree_string);
+        }
+    }
+
+    if !hidden.is_empty() {
+        tree_string.push_str(prefix);
+        tree_string.push_str("└── … and ");
+        tree_string.push_str(&Tally::of(&hidden).describe());
+        tree_string.push('\n');
+    }
+    condensed
+}
+
+/// Lists every file as a path, up to `budget_chars`. Returns true when files were left out.
+fn list_files(tree: &Entry, budget_chars: Option<usize>, output: &mut String) -> bool {
+    let (listed, left_out) = select_files(tree, budget_chars, |file| {
+        file.path.to_string_lossy().len() + 1
+    });
+    for file in listed {
+        output.push_str(&file.path.to_string_lossy());
+        if let Some(link) = &file.link {
+            output.push_str(" -> ");
+            output.push_str(&link.target);
+        }
+        output.push('\n');
+    }
+    if !left_out.is_empty() {
+        output.push_str("… and ");
+        output.push_str(&Tally::of(&left_out).describe());
+        output.push('\n');
+    }
+    !left_out.is_empty()
+}
 
-        entries.sort(); // Sort for consistent order
+/// Every file in `tree`, split into those that fit `budget_chars` (in path order) and the rest.
+/// When not everything fits, source files are picked first.
+fn select_files(
+    tree: &Entry,
+    budget_chars: Option<usize>,
+    cost: impl Fn(&Entry) -> usize,
+) -> (Vec<&Entry>, Vec<&Entry>) {
+    fn files<'a>(entry: &'a Entry, found: &mut Vec<&'a Entry>) {
+        for child in &entry.children {
+            if child.is_dir {
+                files(child, found);
+            } else {
+                found.push(child);
+            }
+        }
+    }
+    let mut found = Vec::new();
+    files(tree, &mut found);
 
-        let mut peekable_entries = entries.into_iter().peekable();
-        while let Some(entry) = peekable_entries.next() {
-            let last = peekable_entries.peek().is_none();
-            scan_directory_recursive(&entry, depth + 1, tree_string, last)?;
+    let mut candidates = found.clone();
+    candidates.sort_by_key(|file| priority(file));
+    let mut remaining = budget_chars
+        .unwrap_or(usize::MAX)
+        .saturating_sub(SUMMARY_LINE_CHARS);
+    let mut fitting = HashSet::new();
+    let mut left_out = Vec::new();
+    for file in candidates {
+        let cost = cost(file);
+        if left_out.is_empty() && cost <= remaining {
+            remaining -= cost;
+            fitting.insert(file.path.as_path());
+        } else {
+            left_out.push(file);
         }
     }
-    Ok(())
+    found.retain(|file| fitting.contains(file.path.as_path()));
+    (found, left_out)
+}
+
+/// File and directory counts of entries that are not listed.
+#[derive(Default)]
+struct Tally {
+    directories: usize,
+    files: usize,
+    /// File counts by extension, like `*.rs`
+    kinds: BTreeMap<String, usize>,
+}
+
+impl Tally {
+    /// Counts `entries` and everything below them.
+    fn of(entries: &[impl std::borrow::Borrow<Entry>]) -> Self {
+        let mut tally = Tally::default();
+        for entry in entries {
+            tally.add(entry.borrow());
+        }
+        tally
+    }
+
+    fn add(&mut self, entry: &Entry) {
+        if entry.is_dir {
+            self.directories += 1;
+            entry.children.iter().for_each(|child| self.add(child));
+        } else {
+            self.files += 1;
+            let kind = match entry.name.rsplit_once('.') {
+                Some((stem, extension)) if !stem.is_empty() => format!("*.{}", extension),
+                _ => "other".to_string(),
+            };
+            *self.kinds.entry(kind).or_default() += 1;
+        }
+    }
+
+    /// `312 files` or `2 directories, 40 files`
+    fn describe_counts(&self) -> String {
+        self.counts("")
+    }
+
+    /// `312 more files (*.rs: 280, *.toml: 32)`, naming the three most common kinds.
+    fn describe(&self) -> String {
+        let mut kinds: Vec<(&String, &usize)> = self.kinds.iter().collect();
+        kinds.sort_by(|a, b| b.1.cmp(a.1));
+        let mut listed: Vec<String> = kinds
+            .iter()
+            .take(3)
+            .map(|(kind, count)| format!("{}: {}", kind, count))
+            .collect();
+        if kinds.len() > 3 {
+            listed.push("…".to_string());
+        }
+        if listed.is_empty() {
+            self.counts("more ")
+        } else {
+            format!("{} ({})", self.counts("more "), listed.join(", "))
+        }
+    }
+
+    fn counts(&self, more: &str) -> String {
+        let directories = format!(
+            "{} {}{}",
+            self.directories,
+            more,
+            if self.directories == 1 {
+                "directory"
+            } else {
+                "directories"
+            }
+        );
+        let files = format!(
+            "{} {}{}",
+            self.files,
+            more,
+            if self.files == 1 { "file" } else { "files" }
+        );
+        match (self.directories, self.files) {
+            (0, _) => files,
+            (_, 0) => directories,
+            _ => format!("{}, {}", directories, files),
+        }
+    }
+}
+
+fn has_unexpanded(entry: &Entry) -> bool {
+    entry.unexpanded || entry.children.iter().any(has_unexpanded)
+}
+
+fn has_unfollowed_links(entry: &Entry) -> bool {
+    entry
+        .link
+        .as_ref()
+        .is_some_and(|link| link.not_followed == Some(NOT_FOLLOWED))
+        || entry.children.iter().any(has_unfollowed_links)
 }
 
 // Basic test function (can be run with `cargo test`)
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs::{File, create_dir_all};
+    use std::fs::{create_dir_all, File};
     use std::io::Write;
     use tempfile::tempdir;
 
@@ -110,16 +1032,22 @@ mod tests {
 
         // Create some structure
         create_dir_all(root_path.join("src")).unwrap();
-        File::create(root_path.join("src/main.rs")).unwrap().write_all(b"fn main() {}").unwrap();
-        File::create(root_path.join("Cargo.toml")).unwrap().write_all(b"[package]").unwrap();
+        File::create(root_path.join("src/main.rs"))
+            .unwrap()
+            .write_all(b"fn main() {}")
+            .unwrap();
+        File::create(root_path.join("Cargo.toml"))
+            .unwrap()
+            .write_all(b"[package]")
+            .unwrap();
         create_dir_all(root_path.join("target")).unwrap(); // Should be ignored
         File::create(root_path.join("target/debug_file")).unwrap();
         File::create(root_path.join(".DS_Store")).unwrap(); // Should be ignored
 
-
-        let expected_prefix = format!("{}\\n├── Cargo.toml\\n└── src\\n    └── main.rs\\n", root_path.file_name().unwrap().to_string_lossy());
-        
-        match scan_directory_tree_from_path(root_path) {
+        match scan_directory_tree_from_path(
+            root_path,
+            &IgnoreRules::new(root_path, &PathPatterns::empty()),
+        ) {
             Ok(tree) => {
                 println!("Generated tree:\\n{}", tree);
                 // Note: The exact string can be a bit tricky with dynamic root names
@@ -129,9 +1057,232 @@ mod tests {
                 assert!(tree.contains("main.rs"));
                 assert!(!tree.contains("target"));
                 assert!(!tree.contains(".DS_Store"));
-                assert!(tree.starts_with(&root_path.file_name().unwrap().to_string_lossy()));
+                assert!(tree.starts_with(&*root_path.file_name().unwrap().to_string_lossy()));
             }
             Err(e) => panic!("Scan failed: {}", e),
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_scan_directory_ignore_rules() {
+        let dir = tempdir().unwrap();
+        let root_path = dir.path();
+        create_dir_all(root_path.join("src")).unwrap();
+        File::create(root_path.join("src/main.rs"))
+            .unwrap()
+            .write_all(b"fn main() {}")
+            .unwrap();
+        File::create(root_path.join("Cargo.toml"))
+            .unwrap()
+            .write_all(b"[package]")
+            .unwrap();
+
+        let ignore = PathPatterns::new(&["*.toml".to_string()]).unwrap();
+        let tree = scan_directory_tree_from_path(root_path, &IgnoreRules::new(root_path, &ignore))
+            .unwrap();
+        assert!(!tree.contains("Cargo.toml"));
+        assert!(tree.contains("main.rs"));
+
+        File::create(root_path.join(".gitignore"))
+            .unwrap()
+            .write_all(b"src/\n")
+            .unwrap();
+        let tree = scan_directory_tree_from_path(
+            root_path,
+            &IgnoreRules::new(root_path, &PathPatterns::empty()),
+        )
+        .unwrap();
+        assert!(!tree.contains("main.rs"));
+        assert!(tree.contains(".gitignore"));
+    }
+
+    #[test]
+    fn test_scan_options() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        create_dir_all(root.join("crates/core/src")).unwrap();
+        create_dir_all(root.join("crates/cli/assets")).unwrap();
+        File::create(root.join("crates/core/src/lib.rs")).unwrap();
+        File::create(root.join("crates/core/Cargo.toml")).unwrap();
+        File::create(root.join("crates/cli/assets/logo.png")).unwrap();
+        File::create(root.join("README.md")).unwrap();
+        let ignore = IgnoreRules::new(root, &PathPatterns::empty());
+        let scan = |input: Value| {
+            scan_directory(
+                root,
+                &ScanOptions::from_tool_input(&input).unwrap(),
+                &ignore,
+            )
+        };
+
+        let tree = scan(serde_json::json!({"path": "crates", "max_depth": 1})).unwrap();
+        assert!(tree.starts_with("crates\n├── cli/…\n└── core/…\n"));
+        assert!(tree.contains("scan them with `path`"));
+
+        let files =
+            scan(serde_json::json!({"include": ["*.rs", "*.toml"], "only": "files"})).unwrap();
+        assert_eq!(files, "crates/core/Cargo.toml\ncrates/core/src/lib.rs\n");
+
+        let dirs =
+            scan(serde_json::json!({"path": "crates", "only": "dirs", "exclude": ["assets"]}))
+                .unwrap();
+        assert!(
+            dirs.contains("core/")
+                && dirs.contains("src/")
+                && !dirs.contains("assets")
+                && !dirs.contains("lib.rs")
+        );
+
+        assert!(scan(serde_json::json!({"path": "../"}))
+            .unwrap_err()
+            .to_string()
+            .contains("inside the project"));
+        assert!(scan(serde_json::json!({"path": "README.md"}))
+            .unwrap_err()
+            .to_string()
+            .contains("not a directory"));
+        assert!(ScanOptions::from_tool_input(&serde_json::json!({"only": "links"})).is_err());
+    }
+
+    #[test]
+    fn test_budgeted_tree() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        create_dir_all(root.join("src/parser")).unwrap();
+        create_dir_all(root.join("assets")).unwrap();
+        File::create(root.join("src/parser/lexer.rs")).unwrap();
+        File::create(root.join("src/main.rs")).unwrap();
+        File::create(root.join("Cargo.toml")).unwrap();
+        for i in 0..5 {
+            File::create(root.join(format!("assets/icon{}.png", i))).unwrap();
+        }
+        let ignore = IgnoreRules::new(root, &PathPatterns::empty());
+
+        // Entries below a directory that isn't last keep the parent's connector
+        let tree = scan_directory(
+            root,
+            &ScanOptions {
+                path: "src".into(),
+                ..ScanOptions::default()
+            },
+            &ignore,
+        )
+        .unwrap();
+        assert_eq!(tree, "src\n├── main.rs\n└── parser/\n    └── lexer.rs\n");
+
+        let tree = scan_directory_tree_from_path(root, &ignore).unwrap();
+        assert!(tree.contains("├── assets/\n│   ├── icon0.png\n│   ├── icon1.png\n│   ├── icon2.png\n│   └── … and 2 more files (*.png: 2)\n"));
+        assert!(tree.contains("└── src/\n    ├── main.rs\n    └── parser/\n        └── lexer.rs\n"));
+
+        for i in 0..60 {
+            File::create(root.join(format!("src/module{}.rs", i))).unwrap();
+        }
+        let options = ScanOptions {
+            budget_tokens: Some(50),
+            ..ScanOptions::default()
+        };
+        let tree = scan_directory(root, &options, &ignore).unwrap();
+        assert!(tree.len() / CHARS_PER_TOKEN < 100);
+        assert!(tree.contains("assets/ (5 files)"));
+        assert!(tree.contains("more files (*.rs: "));
+        assert!(tree.contains("Condensed to fit about 50 tokens"));
+    }
+
+    #[test]
+    fn test_json_output() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        create_dir_all(root.join("src")).unwrap();
+        File::create(root.join("src/main.rs"))
+            .unwrap()
+            .write_all(b"fn main() {\n}\n")
+            .unwrap();
+        File::create(root.join("logo.png"))
+            .unwrap()
+            .write_all(&[0x89, 0, 1, 2])
+            .unwrap();
+        let ignore = IgnoreRules::new(root, &PathPatterns::empty());
+
+        let options = ScanOptions::default();
+        let entries = scan_entries(root, &options, &ignore, &|directory| {
+            list_directory(root, directory, &ignore)
+        })
+        .unwrap();
+        let tree = build_nodes(root, &entries, &options);
+        assert_eq!(tree.path, ".");
+        assert_eq!(tree.children.len(), 2);
+        let logo = &tree.children[0];
+        assert_eq!(
+            (logo.path.as_str(), logo.kind, logo.size, logo.lines),
+            ("logo.png", NodeKind::File, Some(4), None)
+        );
+        let main = &tree.children[1].children[0];
+        assert_eq!(
+            (main.path.as_str(), main.lines, main.language),
+            ("src/main.rs", Some(2), Some("Rust"))
+        );
+        assert!(main.modified.is_some());
+
+        let options = ScanOptions::from_tool_input(
+            &serde_json::json!({"format": "json", "only": "files", "include": ["*.rs"]}),
+        )
+        .unwrap();
+        let json: Value =
+            serde_json::from_str(&scan_directory(root, &options, &ignore).unwrap()).unwrap();
+        assert_eq!(json["children"][0]["path"], "src/main.rs");
+        assert_eq!(json["children"][0]["kind"], "file");
+        assert!(json["children"][0].get("git_status").is_none());
+        assert!(json.get("note").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempdir().unwrap();
+        let outside = tempdir().unwrap();
+        let root = dir.path();
+        create_dir_all(root.join("src/nested")).unwrap();
+        File::create(root.join("src/nested/lib.rs")).unwrap();
+        symlink("..", root.join("src/nested/up")).unwrap();
+        symlink("src/nested", root.join("shortcut")).unwrap();
+        symlink(outside.path(), root.join("elsewhere")).unwrap();
+        symlink("missing.rs", root.join("gone.rs")).unwrap();
+        symlink("src/nested/lib.rs", root.join("lib.rs")).unwrap();
+        std::process::Command::new("mkfifo")
+            .arg(root.join("pipe"))
+            .status()
+            .unwrap();
+        let ignore = IgnoreRules::new(root, &PathPatterns::empty());
+
+        let tree = scan_directory(root, &ScanOptions::default(), &ignore).unwrap();
+        assert!(tree.contains("├── lib.rs -> src/nested/lib.rs\n"));
+        assert!(tree.contains("gone.rs -> missing.rs (broken link)"));
+        assert!(tree.contains("shortcut/ -> src/nested (not followed)"));
+        assert!(tree.contains("up/ -> .. (not followed)"));
+        assert!(!tree.contains("pipe"));
+        assert!(tree.contains("`follow_symlinks`"));
+
+        let options = ScanOptions {
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        let tree = scan_directory(root, &options, &ignore).unwrap();
+        assert!(tree
+            .contains("shortcut/ -> src/nested\n│   ├── lib.rs\n│   └── up/ -> .. (link loop)\n"));
+        assert!(tree.contains("(outside the project)"));
+        // The index lists the same tree, reading followed links from disk
+        let index = crate::workspace_index::WorkspaceIndex::new(root, &PathPatterns::empty());
+        assert_eq!(index.scan(&options).unwrap(), tree);
+
+        let escape = ScanOptions {
+            path: "elsewhere".into(),
+            ..Default::default()
+        };
+        assert!(scan_directory(root, &escape, &ignore)
+            .unwrap_err()
+            .to_string()
+            .contains("outside the project"));
+    }
+}
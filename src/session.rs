@@ -0,0 +1,583 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{Message, ModelConfig};
+
+const APP_DIR_NAME: &str = "simple-coder";
+const MAX_TITLE_CHARS: usize = 60;
+
+/// One line of a session transcript.
+///
+/// A transcript is replayed from the top to rebuild the conversation, so records only ever get appended.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SessionRecord {
+    /// First line of every transcript
+    Start {
+        id: String,
+        created_at: DateTime<Utc>,
+        working_dir: String,
+    },
+    /// Model settings in effect from this point on
+    Settings {
+        model: String,
+        temperature: f32,
+        max_tokens: usize,
+    },
+    /// A message appended to the conversation
+    Message(Message),
+    /// The conversation was replaced wholesale, e.g. by `/clear` or `/load`
+    Reset {
+        messages: Vec<Message>,
+    },
+    Title {
+        title: String,
+    },
+    /// The model called a tool. Kept for exports; not replayed into the conversation.
+    ToolCall {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    /// A tool finished. Replays as the user message that carried the result back to the model.
+    ToolResult {
+        id: String,
+        name: String,
+        success: bool,
+        content: String,
+    },
+    /// A file edit made by a tool, as a unified diff
+    FileDiff {
+        path: String,
+        diff: String,
+    },
+}
+
+/// The model settings a session records and restores.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionSettings {
+    pub model: String,
+    pub temperature: f32,
+    pub max_tokens: usize,
+}
+
+impl SessionSettings {
+    pub fn from_config(config: &ModelConfig) -> Self {
+        Self {
+            model: config.model_name.clone(),
+            temperature: config.temperature,
+            max_tokens: config.max_tokens,
+        }
+    }
+
+    pub fn apply_to(&self, config: &mut ModelConfig) {
+        config.model_name = self.model.clone();
+        config.temperature = self.temperature;
+        config.max_tokens = self.max_tokens;
+    }
+}
+
+/// Conversation state rebuilt from a transcript.
+#[derive(Debug, Default)]
+pub struct RestoredSession {
+    pub messages: Vec<Message>,
+    pub settings: Option<SessionSettings>,
+    pub title: Option<String>,
+}
+
+/// The transcript of the running session, written to `<data dir>/simple-coder/projects/<project>/<id>.jsonl`.
+///
+/// The file is only created once there is something to record, so sessions that never
+/// talk to the model leave nothing behind.
+pub struct Session {
+    pub id: String,
+    path: PathBuf,
+    working_dir: PathBuf,
+    started: bool,
+    title: Option<String>,
+    /// What the transcript currently replays to, used to work out what to append next
+    persisted_messages: Vec<Message>,
+    persisted_settings: Option<SessionSettings>,
+}
+
+impl Session {
+    /// Prepares a new session for the project in `working_dir`. Returns `None` when there is no data directory.
+    pub fn new(working_dir: &Path) -> Option<Self> {
+        let id = Local::now().format("%Y%m%d-%H%M%S-%3f").to_string();
+        let path = project_sessions_dir(working_dir)?.join(format!("{}.jsonl", id));
+        Some(Self {
+            id,
+            path,
+            working_dir: working_dir.to_path_buf(),
+            started: false,
+            title: None,
+            persisted_messages: Vec::new(),
+            persisted_settings: None,
+        })
+    }
+
+    /// Opens an existing session of this project, the most recently updated one when `id` is `None`.
+    /// New records are appended to the same transcript.
+    pub fn resume(
+        working_dir: &Path,
+        id: Option<&str>,
+    ) -> Result<(Self, RestoredSession), Box<dyn std::error::Error>> {
+        let summary = find_session(working_dir, id)?;
+        let restored = replay(&read_records(&summary.path)?);
+        let session = Self {
+            id: summary.id,
+            path: summary.path,
+            working_dir: working_dir.to_path_buf(),
+            started: true,
+            title: restored.title.clone(),
+            persisted_messages: restored.messages.clone(),
+            persisted_settings: restored.settings.clone(),
+        };
+        Ok((session, restored))
+    }
+
+    /// Appends whatever changed since the last sync: new messages, a reset, new settings or the title.
+    pub fn sync(&mut self, messages: &[Message], config: &ModelConfig) -> io::Result<()> {
+        if !self.started && messages.is_empty() {
+            return Ok(());
+        }
+        let records = self.pending_records(messages, config);
+        self.write(records, messages, config)
+    }
+
+    /// Appends a tool call, tool result or diff record, after syncing the messages before it.
+    ///
+    /// A `ToolResult` stands for the last entry of `messages`, which the agent pushed with the same content.
+    pub fn record(
+        &mut self,
+        messages: &[Message],
+        config: &ModelConfig,
+        record: SessionRecord,
+    ) -> io::Result<()> {
+        let earlier = match record {
+            SessionRecord::ToolResult { .. } => &messages[..messages.len().saturating_sub(1)],
+            _ => messages,
+        };
+        let mut records = self.pending_records(earlier, config);
+        records.push(record);
+        self.write(records, messages, config)
+    }
+
+    /// Reads back everything recorded so far.
+    pub fn records(&self) -> io::Result<Vec<SessionRecord>> {
+        if !self.started {
+            return Ok(Vec::new());
+        }
+        read_records(&self.path)
+    }
+
+    fn pending_records(
+        &mut self,
+        messages: &[Message],
+        config: &ModelConfig,
+    ) -> Vec<SessionRecord> {
+        let mut records = Vec::new();
+        if !self.started {
+            records.push(SessionRecord::Start {
+                id: self.id.clone(),
+                created_at: Utc::now(),
+                working_dir: self.working_dir.display().to_string(),
+            });
+        }
+
+        let settings = SessionSettings::from_config(config);
+        if self.persisted_settings.as_ref() != Some(&settings) {
+            records.push(SessionRecord::Settings {
+                model: settings.model.clone(),
+                temperature: settings.temperature,
+                max_tokens: settings.max_tokens,
+            });
+        }
+
+        let persisted = self.persisted_messages.len();
+        if messages.len() >= persisted && messages[..persisted] == self.persisted_messages[..] {
+            records.extend(
+                messages[persisted..]
+                    .iter()
+                    .cloned()
+                    .map(SessionRecord::Message),
+            );
+        } else {
+            records.push(SessionRecord::Reset {
+                messages: messages.to_vec(),
+            });
+        }
+
+        if self.title.is_none() {
+            if let Some(title) = messages
+                .iter()
+                .find(|message| message.role == "user")
+                .map(|message| generate_title(&message.content.text()))
+            {
+                records.push(SessionRecord::Title {
+                    title: title.clone(),
+                });
+                self.title = Some(title);
+            }
+        }
+
+        records
+    }
+
+    fn write(
+        &mut self,
+        records: Vec<SessionRecord>,
+        messages: &[Message],
+        config: &ModelConfig,
+    ) -> io::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        self.append(&records)?;
+        self.started = true;
+        self.persisted_messages = messages.to_vec();
+        self.persisted_settings = Some(SessionSettings::from_config(config));
+        Ok(())
+    }
+
+    fn append(&self, records: &[SessionRecord]) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let mut lines = String::new();
+        for record in records {
+            lines.push_str(&serde_json::to_string(record)?);
+            lines.push('\n');
+        }
+        file.write_all(lines.as_bytes())
+    }
+}
+
+/// Resumes a saved session into the running conversation and makes it the session new records go to.
+/// Returns a one-line description for the user.
+pub fn resume_into(
+    working_dir: &Path,
+    id: Option<&str>,
+    messages: &mut Vec<Message>,
+    config: &mut ModelConfig,
+    current: &mut Option<Session>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let (session, restored) = Session::resume(working_dir, id)?;
+    if let Some(settings) = &restored.settings {
+        settings.apply_to(config);
+    }
+    let description = format!(
+        "Resumed session {} \"{}\" ({} messages, model {})",
+        session.id,
+        restored.title.as_deref().unwrap_or("untitled"),
+        restored.messages.len(),
+        config.model_name
+    );
+    *messages = restored.messages;
+    *current = Some(session);
+    Ok(description)
+}
+
+/// A past session as shown by `--list-sessions` and `/sessions`.
+#[derive(Debug)]
+pub struct SessionSummary {
+    pub id: String,
+    pub title: String,
+    pub message_count: usize,
+    pub updated: DateTime<Local>,
+    path: PathBuf,
+}
+
+/// Lists the sessions recorded for this project, most recently updated first.
+pub fn list_sessions(working_dir: &Path) -> io::Result<Vec<SessionSummary>> {
+    let Some(directory) = project_sessions_dir(working_dir) else {
+        return Ok(Vec::new());
+    };
+    if !directory.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut sessions = Vec::new();
+    for entry in fs::read_dir(&directory)? {
+        let path = entry?.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("jsonl") {
+            continue;
+        }
+        let restored = match read_records(&path) {
+            Ok(records) => replay(&records),
+            Err(e) => {
+                warn!("Skipping unreadable session {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let updated = fs::metadata(&path)?.modified()?.into();
+        sessions.push(SessionSummary {
+            id: path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            title: restored.title.unwrap_or_else(|| "(untitled)".to_string()),
+            message_count: restored.messages.len(),
+            updated,
+            path,
+        });
+    }
+    sessions.sort_by(|a, b| b.updated.cmp(&a.updated).then_with(|| b.id.cmp(&a.id)));
+    Ok(sessions)
+}
+
+/// Finds a saved session of this project, the most recently updated one when `id` is `None`.
+fn find_session(
+    working_dir: &Path,
+    id: Option<&str>,
+) -> Result<SessionSummary, Box<dyn std::error::Error>> {
+    let sessions = list_sessions(working_dir)?;
+    let summary = match id {
+        Some(id) => sessions
+            .into_iter()
+            .find(|session| session.id == id)
+            .ok_or_else(|| {
+                format!(
+                    "No session with id {} for this project. Use /sessions to list them.",
+                    id
+                )
+            })?,
+        None => sessions
+            .into_iter()
+            .next()
+            .ok_or("There are no saved sessions for this project yet.")?,
+    };
+    Ok(summary)
+}
+
+/// Reads the transcript of a saved session (the latest when `id` is `None`), returning its id and records.
+pub fn load_records(
+    working_dir: &Path,
+    id: Option<&str>,
+) -> Result<(String, Vec<SessionRecord>), Box<dyn std::error::Error>> {
+    let summary = find_session(working_dir, id)?;
+    let records = read_records(&summary.path)?;
+    Ok((summary.id, records))
+}
+
+/// Formats session summaries as an aligned table, one per line.
+pub fn format_session_list(sessions: &[SessionSummary]) -> String {
+    if sessions.is_empty() {
+        return "No saved sessions for this project.".to_string();
+    }
+    sessions
+        .iter()
+        .map(|session| {
+            format!(
+                "{}  {}  {:>4} msgs  {}",
+                session.id,
+                session.updated.format("%Y-%m-%d %H:%M"),
+                session.message_count,
+                session.title
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Rebuilds the conversation by applying records in order.
+pub fn replay(records: &[SessionRecord]) -> RestoredSession {
+    let mut restored = RestoredSession::default();
+    for record in records {
+        match record {
+            SessionRecord::Start { .. } => {}
+            SessionRecord::Settings {
+                model,
+                temperature,
+                max_tokens,
+            } => {
+                restored.settings = Some(SessionSettings {
+                    model: model.clone(),
+                    temperature: *temperature,
+                    max_tokens: *max_tokens,
+                });
+            }
+            SessionRecord::Message(message) => restored.messages.push(message.clone()),
+            SessionRecord::Reset { messages } => restored.messages = messages.clone(),
+            SessionRecord::Title { title } => restored.title = Some(title.clone()),
+            SessionRecord::ToolResult {
+                id,
+                success,
+                content,
+                ..
+            } => restored
+                .messages
+                .push(Message::tool_result(id, content, *success)),
+            SessionRecord::ToolCall { .. } | SessionRecord::FileDiff { .. } => {}
+        }
+    }
+    restored
+}
+
+/// Reads a transcript. A truncated last line (e.g. from a crash mid-write) is ignored.
+fn read_records(path: &Path) -> io::Result<Vec<SessionRecord>> {
+    let mut records = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            Err(e) => warn!("Skipping bad line in {}: {}", path.display(), e),
+        }
+    }
+    Ok(records)
+}
+
+/// Builds a short title from the first user message: its first line, cut at a word boundary.
+pub fn generate_title(text: &str) -> String {
+    let first_line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or("");
+    let collapsed = first_line.split_whitespace().collect::<Vec<_>>().join(" ");
+    if collapsed.chars().count() <= MAX_TITLE_CHARS {
+        return collapsed;
+    }
+
+    let cut: String = collapsed.chars().take(MAX_TITLE_CHARS).collect();
+    let cut = match cut.rfind(' ') {
+        Some(space) if space > MAX_TITLE_CHARS / 2 => &cut[..space],
+        _ => &cut,
+    };
+    format!("{}…", cut.trim_end_matches(|c: char| !c.is_alphanumeric()))
+}
+
+/// Root directory for data kept across runs (sessions and the like).
+pub fn data_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|directory| directory.join(APP_DIR_NAME))
+}
+
+/// Sessions are grouped per project, keyed by the project's absolute path.
+fn project_sessions_dir(working_dir: &Path) -> Option<PathBuf> {
+    Some(data_dir()?.join("projects").join(project_key(working_dir)))
+}
+
+/// A readable form of the canonical path plus a hash of it, since the readable part alone
+/// maps both `/a/b-c` and `/a/b/c` to `-a-b-c`.
+fn project_key(working_dir: &Path) -> String {
+    let path = working_dir
+        .canonicalize()
+        .unwrap_or_else(|_| working_dir.to_path_buf());
+    let readable: String = path
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect();
+    // FNV-1a, which unlike `DefaultHasher` stays the same across Rust releases
+    let hash = path
+        .as_os_str()
+        .as_encoded_bytes()
+        .iter()
+        .fold(0xcbf29ce484222325u64, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        });
+    format!("{}-{:08x}", readable, hash as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: &str, content: &str) -> Message {
+        Message {
+            role: role.to_string(),
+            content: content.to_string().into(),
+        }
+    }
+
+    fn config(model: &str) -> ModelConfig {
+        ModelConfig {
+            provider: "anthropic".to_string(),
+            model_name: model.to_string(),
+            api_key: String::new(),
+            temperature: 0.5,
+            max_tokens: 100,
+            api_base_url: None,
+        }
+    }
+
+    #[test]
+    fn test_sync_and_replay() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut session = Session::new(directory.path()).unwrap();
+        session.path = directory.path().join("session.jsonl");
+
+        session.sync(&[], &config("a")).unwrap();
+        assert!(!session.path.exists());
+
+        let mut messages = vec![
+            message("user", "Fix the failing test in src/lib.rs"),
+            message("assistant", "Done."),
+        ];
+        session.sync(&messages, &config("a")).unwrap();
+        messages.push(message("user", "thanks"));
+        session.sync(&messages, &config("b")).unwrap();
+        session.sync(&messages, &config("b")).unwrap();
+
+        let records = read_records(&session.path).unwrap();
+        assert_eq!(records.len(), 7);
+        let restored = replay(&records);
+        assert_eq!(restored.messages, messages);
+        assert_eq!(restored.settings.unwrap().model, "b");
+        assert_eq!(
+            restored.title.as_deref(),
+            Some("Fix the failing test in src/lib.rs")
+        );
+
+        // Replacing the history (e.g. /clear) is recorded as a reset
+        session
+            .sync(&[message("user", "new topic")], &config("b"))
+            .unwrap();
+        let restored = replay(&read_records(&session.path).unwrap());
+        assert_eq!(restored.messages, vec![message("user", "new topic")]);
+        assert_eq!(
+            restored.title.as_deref(),
+            Some("Fix the failing test in src/lib.rs")
+        );
+    }
+
+    #[test]
+    fn test_project_keys_do_not_collide() {
+        let directory = tempfile::tempdir().unwrap();
+        let (dashed, nested) = (directory.path().join("b-c"), directory.path().join("b/c"));
+        fs::create_dir_all(&dashed).unwrap();
+        fs::create_dir_all(&nested).unwrap();
+        assert_ne!(project_key(&dashed), project_key(&nested));
+        assert_eq!(
+            project_key(&dashed),
+            project_key(&directory.path().join("b/../b-c"))
+        );
+    }
+
+    #[test]
+    fn test_generate_title() {
+        assert_eq!(
+            generate_title("\n  add   a README \nwith details"),
+            "add a README"
+        );
+        let long = "please refactor the scanner so that it walks directories in parallel and caches results";
+        let title = generate_title(long);
+        assert!(title.ends_with('…'));
+        assert!(title.chars().count() <= MAX_TITLE_CHARS + 1);
+        assert!(long.starts_with(title.trim_end_matches('…')));
+    }
+}
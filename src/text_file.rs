@@ -0,0 +1,310 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+
+/// A NUL byte in this many leading bytes marks a file as binary
+const BINARY_SNIFF_BYTES: usize = 8000;
+/// Undecodable files with more control characters than this (in percent) are binary, not legacy text
+const MAX_CONTROL_PERCENT: usize = 10;
+
+/// Well-known file signatures, for describing binary files
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "PNG image"),
+    (b"\xff\xd8\xff", "JPEG image"),
+    (b"GIF8", "GIF image"),
+    (b"%PDF", "PDF document"),
+    (b"PK\x03\x04", "ZIP archive"),
+    (b"\x1f\x8b", "gzip archive"),
+    (b"BZh", "bzip2 archive"),
+    (b"\xfd7zXZ\x00", "xz archive"),
+    (b"7z\xbc\xaf\x27\x1c", "7-Zip archive"),
+    (b"\x7fELF", "ELF executable"),
+    (b"MZ", "Windows executable"),
+    (b"\xcf\xfa\xed\xfe", "Mach-O executable"),
+    (b"\xca\xfe\xba\xbe", "Java class or Mach-O universal binary"),
+    (b"\x00asm", "WebAssembly module"),
+    (b"SQLite format 3\x00", "SQLite database"),
+    (b"\x00\x00\x01\x00", "ICO image"),
+    (b"ID3", "MP3 audio"),
+];
+
+/// How a text file's bytes map to its text, so edits can be written back the same way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextEncoding {
+    encoding: &'static Encoding,
+    /// Whether the file starts with a byte order mark
+    bom: bool,
+}
+
+impl TextEncoding {
+    pub const UTF8: Self = Self {
+        encoding: UTF_8,
+        bom: false,
+    };
+
+    /// Encodes `text` for writing, failing rather than substituting characters the encoding lacks.
+    pub fn encode(&self, text: &str) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(text.len() + 3);
+        if self.bom {
+            bytes.extend_from_slice(match self.encoding.name() {
+                "UTF-16LE" => b"\xff\xfe".as_slice(),
+                "UTF-16BE" => b"\xfe\xff".as_slice(),
+                _ => b"\xef\xbb\xbf".as_slice(),
+            });
+        }
+        // encoding_rs only decodes UTF-16; its encoder writes UTF-8 for it
+        if self.encoding == UTF_16LE {
+            bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        } else if self.encoding == UTF_16BE {
+            bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+        } else if self.encoding == UTF_8 {
+            bytes.extend_from_slice(text.as_bytes());
+        } else {
+            let (encoded, _, had_errors) = self.encoding.encode(text);
+            if had_errors {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("the text contains characters that can't be saved as {}, the file's encoding", self),
+                ));
+            }
+            bytes.extend_from_slice(&encoded);
+        }
+        Ok(bytes)
+    }
+}
+
+impl fmt::Display for TextEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.encoding == UTF_8, self.bom) {
+            (true, true) => write!(f, "UTF-8 with BOM"),
+            _ => write!(f, "{}", self.encoding.name()),
+        }
+    }
+}
+
+/// A file decoded to text.
+#[derive(Debug)]
+pub struct TextFile {
+    pub text: String,
+    pub encoding: TextEncoding,
+    /// On disk, in bytes
+    pub size: u64,
+}
+
+/// A file that isn't text, described instead of shown.
+#[derive(Debug)]
+pub struct BinaryFile {
+    pub kind: &'static str,
+    pub size: u64,
+}
+
+impl BinaryFile {
+    pub fn describe(&self, path: &Path) -> String {
+        format!(
+            "{} is a binary file ({}, {} bytes); its contents can't be shown as text",
+            path.display(),
+            self.kind,
+            self.size
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum FileContents {
+    Text(TextFile),
+    Binary(BinaryFile),
+}
+
+/// Reads a regular file of at most `max_bytes`, decoding it as text when it is text.
+///
+/// A byte order mark selects UTF-8 or UTF-16. Without one the file is UTF-8 if it is valid
+/// UTF-8, binary if it contains NUL bytes, and otherwise legacy text read as Windows-1252
+/// (a superset of Latin-1).
+pub fn read(path: &Path, max_bytes: u64) -> io::Result<FileContents> {
+    let metadata = fs::metadata(path)?;
+    if metadata.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::IsADirectory,
+            format!("{} is a directory", path.display()),
+        ));
+    }
+    if !metadata.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is not a regular file (a socket, FIFO or device)",
+                path.display()
+            ),
+        ));
+    }
+    if metadata.len() > max_bytes {
+        return Err(io::Error::new(
+            io::ErrorKind::FileTooLarge,
+            format!(
+                "{} is {} bytes, over the {} byte limit (max_read_bytes)",
+                path.display(),
+                metadata.len(),
+                max_bytes
+            ),
+        ));
+    }
+    Ok(decode(fs::read(path)?))
+}
+
+/// Like `read`, treating binary files as an error.
+pub fn read_text(path: &Path, max_bytes: u64) -> io::Result<TextFile> {
+    match read(path, max_bytes)? {
+        FileContents::Text(file) => Ok(file),
+        FileContents::Binary(binary) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            binary.describe(path),
+        )),
+    }
+}
+
+/// Writes `text` to `path` in `encoding`, so edits keep the file's original encoding and BOM.
+pub fn write(path: &Path, text: &str, encoding: TextEncoding) -> io::Result<()> {
+    fs::write(path, encoding.encode(text)?)
+}
+
+/// Whether `bytes` look binary: a NUL byte among the first `BINARY_SNIFF_BYTES`.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes[..bytes.len().min(BINARY_SNIFF_BYTES)].contains(&0)
+}
+
+fn decode(bytes: Vec<u8>) -> FileContents {
+    let size = bytes.len() as u64;
+    if let Some((encoding, bom_length)) = Encoding::for_bom(&bytes) {
+        if let Some(text) =
+            encoding.decode_without_bom_handling_and_without_replacement(&bytes[bom_length..])
+        {
+            return FileContents::Text(TextFile {
+                text: text.into_owned(),
+                encoding: TextEncoding {
+                    encoding,
+                    bom: true,
+                },
+                size,
+            });
+        }
+    }
+    if is_binary(&bytes) {
+        return FileContents::Binary(BinaryFile {
+            kind: kind(&bytes),
+            size,
+        });
+    }
+    match String::from_utf8(bytes) {
+        Ok(text) => FileContents::Text(TextFile {
+            text,
+            encoding: TextEncoding::UTF8,
+            size,
+        }),
+        Err(e) => {
+            let bytes = e.into_bytes();
+            let sample = &bytes[..bytes.len().min(BINARY_SNIFF_BYTES)];
+            let controls = sample
+                .iter()
+                .filter(|&&byte| byte < 0x20 && !b"\t\n\r\x0c\x1b".contains(&byte))
+                .count();
+            if controls * 100 > sample.len() * MAX_CONTROL_PERCENT {
+                return FileContents::Binary(BinaryFile {
+                    kind: kind(&bytes),
+                    size,
+                });
+            }
+            let (text, _) = WINDOWS_1252.decode_without_bom_handling(&bytes);
+            FileContents::Text(TextFile {
+                text: text.into_owned(),
+                encoding: TextEncoding {
+                    encoding: WINDOWS_1252,
+                    bom: false,
+                },
+                size,
+            })
+        }
+    }
+}
+
+fn kind(bytes: &[u8]) -> &'static str {
+    if bytes.starts_with(b"RIFF") {
+        match bytes.get(8..12) {
+            Some(b"WEBP") => return "WebP image",
+            Some(b"WAVE") => return "WAV audio",
+            _ => {}
+        }
+    }
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| bytes.starts_with(signature))
+        .map_or("binary data", |(_, kind)| kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(bytes: &[u8]) -> TextFile {
+        match decode(bytes.to_vec()) {
+            FileContents::Text(file) => file,
+            FileContents::Binary(binary) => panic!("decoded as {}", binary.kind),
+        }
+    }
+
+    #[test]
+    fn test_decode_and_round_trip() {
+        let cases: &[(&[u8], &str, &str)] = &[
+            (b"caf\xc3\xa9\n", "café\n", "UTF-8"),
+            (b"\xef\xbb\xbfcaf\xc3\xa9\n", "café\n", "UTF-8 with BOM"),
+            (b"\xff\xfec\x00a\x00f\x00\xe9\x00", "café", "UTF-16LE"),
+            (b"\xfe\xff\x00c\x00a\x00f\x00\xe9", "café", "UTF-16BE"),
+            (b"caf\xe9 \x80\n", "café €\n", "windows-1252"),
+        ];
+        for (bytes, expected, encoding) in cases {
+            let file = text(bytes);
+            assert_eq!(
+                (file.text.as_str(), file.encoding.to_string().as_str()),
+                (*expected, *encoding)
+            );
+            assert_eq!(file.encoding.encode(&file.text).unwrap(), *bytes);
+        }
+
+        let latin = text(b"caf\xe9\n");
+        assert!(latin
+            .encoding
+            .encode("日本")
+            .unwrap_err()
+            .to_string()
+            .contains("windows-1252"));
+
+        match decode(b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR".to_vec()) {
+            FileContents::Binary(binary) => {
+                assert_eq!((binary.kind, binary.size), ("PNG image", 16))
+            }
+            FileContents::Text(_) => panic!("PNG decoded as text"),
+        }
+        assert!(matches!(
+            decode(b"\x01\x02\x03\xff\x04\x05".to_vec()),
+            FileContents::Binary(_)
+        ));
+    }
+
+    #[test]
+    fn test_read_limits() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("big.txt");
+        fs::write(&path, "0123456789").unwrap();
+        assert_eq!(read_text(&path, 10).unwrap().size, 10);
+        assert_eq!(
+            read(&path, 9).unwrap_err().kind(),
+            io::ErrorKind::FileTooLarge
+        );
+        assert_eq!(
+            read(directory.path(), 100).unwrap_err().kind(),
+            io::ErrorKind::IsADirectory
+        );
+    }
+}
@@ -1,9 +1,12 @@
+use chrono::{DateTime, Utc};
+use log::debug;
+use serde_json::Value;
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::collections::HashMap;
-use std::sync::Mutex;
-use serde_json::Value;
-use chrono::{DateTime, Utc};
+
+use crate::diff::FileDiff;
+use crate::text_file::{self, FileContents, TextEncoding};
 
 #[derive(Debug)]
 pub struct TextEditorResult {
@@ -11,79 +14,165 @@ pub struct TextEditorResult {
     pub message: String,
     pub file_content: Option<String>,
     pub changes_made: bool,
+    /// Unified diff of the change, for mutating commands that succeeded
+    pub diff: Option<FileDiff>,
 }
 
 #[derive(Debug)]
 pub struct FileBackup {
     pub original_content: String,
+    /// Restored in the encoding the file had
+    pub encoding: TextEncoding,
     pub timestamp: DateTime<Utc>,
     pub file_path: PathBuf,
 }
 
-// Safe backup storage using Mutex
-static BACKUP_STORAGE: Mutex<Option<HashMap<String, FileBackup>>> = Mutex::new(None);
+/// What the editor remembers about one session's edits: a backup of each file's content before
+/// its latest edit, for undo, and its content before the first edit, for `/diff`.
+///
+/// Owned by the agent, so every session starts with a clean history.
+#[derive(Debug, Default)]
+pub struct EditHistory {
+    backups: HashMap<String, FileBackup>,
+    // None for files created this session
+    originals: HashMap<String, Option<String>>,
+}
+
+impl EditHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forgets every backup and original, as when the conversation starts over.
+    pub fn clear(&mut self) {
+        self.backups.clear();
+        self.originals.clear();
+    }
+
+    fn record_original(&mut self, file_path: &str, content: Option<&str>) {
+        self.originals
+            .entry(file_path.to_string())
+            .or_insert_with(|| content.map(str::to_string));
+    }
+
+    /// Returns every file modified this session together with its content before the first edit,
+    /// sorted by path. Files created during the session have `None` as their original content.
+    pub fn changes(&self) -> Vec<(String, Option<String>)> {
+        let mut changes: Vec<(String, Option<String>)> = self
+            .originals
+            .iter()
+            .map(|(path, content)| (path.clone(), content.clone()))
+            .collect();
+        changes.sort_by(|a, b| a.0.cmp(&b.0));
+        changes
+    }
 
-fn get_backup_storage() -> std::sync::MutexGuard<'static, Option<HashMap<String, FileBackup>>> {
-    BACKUP_STORAGE.lock().unwrap()
+    /// The file of the most recent edit that can still be undone, as the tool call named it.
+    pub fn last_edited(&self) -> Option<&str> {
+        self.backups
+            .iter()
+            .max_by_key(|(_, backup)| backup.timestamp)
+            .map(|(file_path, _)| file_path.as_str())
+    }
 }
 
-pub fn handle_text_editor_tool(input_params: &Value, model_version: &str) -> Result<TextEditorResult, Box<dyn std::error::Error>> {
-    let command = input_params.get("command")
+/// Runs one text editor command. Files over `max_read_bytes` are neither viewed nor edited.
+///
+/// `path` is relative to `base_dir` unless absolute. Messages, diffs and `EditHistory::changes` show
+/// it as given.
+pub fn handle_text_editor_tool(
+    input_params: &Value,
+    model_version: &str,
+    max_read_bytes: u64,
+    base_dir: &Path,
+    history: &mut EditHistory,
+) -> Result<TextEditorResult, Box<dyn std::error::Error>> {
+    let command = input_params
+        .get("command")
         .and_then(|v| v.as_str())
         .unwrap_or("");
-    
-    let file_path = input_params.get("path")
+
+    let file_path = input_params
+        .get("path")
         .and_then(|v| v.as_str())
         .unwrap_or("");
-    println!("Command: {}", command);
-    println!("File path: {}", file_path);
-    match command { 
-        "view" => handle_view_command(file_path),
-        "str_replace" => handle_str_replace_command(input_params, file_path),
-        "create" => handle_create_command(input_params, file_path),
-        "insert" => handle_insert_command(input_params, file_path),
-        "undo_edit" => handle_undo_command(file_path, model_version),
+    debug!("Command: {}", command);
+    debug!("File path: {}", file_path);
+    let full_path = &base_dir.join(file_path);
+    match command {
+        "view" => handle_view_command(file_path, full_path, max_read_bytes),
+        "str_replace" => {
+            handle_str_replace_command(input_params, file_path, full_path, max_read_bytes, history)
+        }
+        "create" => handle_create_command(input_params, file_path, full_path, history),
+        "insert" => {
+            handle_insert_command(input_params, file_path, full_path, max_read_bytes, history)
+        }
+        "undo_edit" => handle_undo_command(file_path, model_version, history),
         _ => Ok(TextEditorResult {
             success: false,
             message: format!("Unknown command: {}", command),
             file_content: None,
             changes_made: false,
-        })
+            diff: None,
+        }),
     }
 }
 
-fn handle_view_command(file_path: &str) -> Result<TextEditorResult, Box<dyn std::error::Error>> {
+fn handle_view_command(
+    file_path: &str,
+    full_path: &Path,
+    max_read_bytes: u64,
+) -> Result<TextEditorResult, Box<dyn std::error::Error>> {
     if file_path.is_empty() {
         return Ok(TextEditorResult {
             success: false,
             message: "File path is required for view command".to_string(),
             file_content: None,
             changes_made: false,
+            diff: None,
         });
     }
 
-    match fs::read_to_string(file_path) {
-        Ok(content) => Ok(TextEditorResult {
+    match text_file::read(full_path, max_read_bytes) {
+        Ok(FileContents::Text(file)) => Ok(TextEditorResult {
             success: true,
             message: format!("Successfully read file: {}", file_path),
-            file_content: Some(content),
+            file_content: Some(file.text),
+            changes_made: false,
+            diff: None,
+        }),
+        Ok(FileContents::Binary(binary)) => Ok(TextEditorResult {
+            success: false,
+            message: binary.describe(full_path),
+            file_content: None,
             changes_made: false,
+            diff: None,
         }),
         Err(e) => Ok(TextEditorResult {
             success: false,
             message: format!("Failed to read file {}: {}", file_path, e),
             file_content: None,
             changes_made: false,
-        })
+            diff: None,
+        }),
     }
 }
 
-fn handle_str_replace_command(input_params: &Value, file_path: &str) -> Result<TextEditorResult, Box<dyn std::error::Error>> {
-    let old_str = input_params.get("old_str")
+fn handle_str_replace_command(
+    input_params: &Value,
+    file_path: &str,
+    full_path: &Path,
+    max_read_bytes: u64,
+    history: &mut EditHistory,
+) -> Result<TextEditorResult, Box<dyn std::error::Error>> {
+    let old_str = input_params
+        .get("old_str")
         .and_then(|v| v.as_str())
         .unwrap_or("");
-    
-    let new_str = input_params.get("new_str")
+
+    let new_str = input_params
+        .get("new_str")
         .and_then(|v| v.as_str())
         .unwrap_or("");
 
@@ -93,22 +182,26 @@ fn handle_str_replace_command(input_params: &Value, file_path: &str) -> Result<T
             message: "File path and old_str are required for str_replace command".to_string(),
             file_content: None,
             changes_made: false,
+            diff: None,
         });
     }
 
-    // Read current content
-    let current_content = match fs::read_to_string(file_path) {
-        Ok(content) => content,
-        Err(e) => return Ok(TextEditorResult {
-            success: false,
-            message: format!("Failed to read file {}: {}", file_path, e),
-            file_content: None,
-            changes_made: false,
-        })
+    // Read current content, remembering its encoding so the edit is saved the same way
+    let (current_content, encoding) = match text_file::read_text(full_path, max_read_bytes) {
+        Ok(file) => (file.text, file.encoding),
+        Err(e) => {
+            return Ok(TextEditorResult {
+                success: false,
+                message: format!("Failed to read file {}: {}", file_path, e),
+                file_content: None,
+                changes_made: false,
+                diff: None,
+            })
+        }
     };
 
     // Create backup before modification
-    create_backup(file_path, &current_content)?;
+    create_backup(history, file_path, full_path, &current_content, encoding);
 
     // Perform string replacement
     if !current_content.contains(old_str) {
@@ -117,30 +210,43 @@ fn handle_str_replace_command(input_params: &Value, file_path: &str) -> Result<T
             message: format!("String '{}' not found in file {}", old_str, file_path),
             file_content: Some(current_content),
             changes_made: false,
+            diff: None,
         });
     }
 
     let new_content = current_content.replace(old_str, new_str);
-    
+
     // Write modified content
-    match fs::write(file_path, &new_content) {
+    match text_file::write(full_path, &new_content, encoding) {
         Ok(_) => Ok(TextEditorResult {
             success: true,
             message: format!("Successfully replaced text in {}", file_path),
-            file_content: Some(new_content),
+            file_content: None,
             changes_made: true,
+            diff: Some(FileDiff::new(
+                file_path,
+                Some(&current_content),
+                &new_content,
+            )),
         }),
         Err(e) => Ok(TextEditorResult {
             success: false,
             message: format!("Failed to write to file {}: {}", file_path, e),
             file_content: None,
             changes_made: false,
-        })
+            diff: None,
+        }),
     }
 }
 
-fn handle_create_command(input_params: &Value, file_path: &str) -> Result<TextEditorResult, Box<dyn std::error::Error>> {
-    let file_text = input_params.get("file_text")
+fn handle_create_command(
+    input_params: &Value,
+    file_path: &str,
+    full_path: &Path,
+    history: &mut EditHistory,
+) -> Result<TextEditorResult, Box<dyn std::error::Error>> {
+    let file_text = input_params
+        .get("file_text")
         .and_then(|v| v.as_str())
         .unwrap_or("");
 
@@ -150,49 +256,67 @@ fn handle_create_command(input_params: &Value, file_path: &str) -> Result<TextEd
             message: "File path is required for create command".to_string(),
             file_content: None,
             changes_made: false,
+            diff: None,
         });
     }
 
     // Check if file already exists
-    if Path::new(file_path).exists() {
+    if full_path.exists() {
         return Ok(TextEditorResult {
             success: false,
-            message: format!("File {} already exists. Use str_replace to modify existing files.", file_path),
+            message: format!(
+                "File {} already exists. Use str_replace to modify existing files.",
+                file_path
+            ),
             file_content: None,
             changes_made: false,
+            diff: None,
         });
     }
 
     // Create parent directories if they don't exist
-    if let Some(parent) = Path::new(file_path).parent() {
+    if let Some(parent) = full_path.parent() {
         if !parent.exists() {
             fs::create_dir_all(parent)?;
         }
     }
 
     // Write new file
-    match fs::write(file_path, file_text) {
-        Ok(_) => Ok(TextEditorResult {
-            success: true,
-            message: format!("Successfully created file: {}", file_path),
-            file_content: Some(file_text.to_string()),
-            changes_made: true,
-        }),
+    match fs::write(full_path, file_text) {
+        Ok(_) => {
+            history.record_original(file_path, None);
+            Ok(TextEditorResult {
+                success: true,
+                message: format!("Successfully created file: {}", file_path),
+                file_content: None,
+                changes_made: true,
+                diff: Some(FileDiff::new(file_path, None, file_text)),
+            })
+        }
         Err(e) => Ok(TextEditorResult {
             success: false,
             message: format!("Failed to create file {}: {}", file_path, e),
             file_content: None,
             changes_made: false,
-        })
+            diff: None,
+        }),
     }
 }
 
-fn handle_insert_command(input_params: &Value, file_path: &str) -> Result<TextEditorResult, Box<dyn std::error::Error>> {
-    let insert_line = input_params.get("insert_line")
+fn handle_insert_command(
+    input_params: &Value,
+    file_path: &str,
+    full_path: &Path,
+    max_read_bytes: u64,
+    history: &mut EditHistory,
+) -> Result<TextEditorResult, Box<dyn std::error::Error>> {
+    let insert_line = input_params
+        .get("insert_line")
         .and_then(|v| v.as_u64())
         .unwrap_or(0) as usize;
-    
-    let new_str = input_params.get("new_str")
+
+    let new_str = input_params
+        .get("new_str")
         .and_then(|v| v.as_str())
         .unwrap_or("");
 
@@ -202,64 +326,182 @@ fn handle_insert_command(input_params: &Value, file_path: &str) -> Result<TextEd
             message: "File path is required for insert command".to_string(),
             file_content: None,
             changes_made: false,
+            diff: None,
         });
     }
 
-    // Read current content
-    let current_content = match fs::read_to_string(file_path) {
-        Ok(content) => content,
-        Err(e) => return Ok(TextEditorResult {
-            success: false,
-            message: format!("Failed to read file {}: {}", file_path, e),
-            file_content: None,
-            changes_made: false,
-        })
+    // Read current content, remembering its encoding so the edit is saved the same way
+    let (current_content, encoding) = match text_file::read_text(full_path, max_read_bytes) {
+        Ok(file) => (file.text, file.encoding),
+        Err(e) => {
+            return Ok(TextEditorResult {
+                success: false,
+                message: format!("Failed to read file {}: {}", file_path, e),
+                file_content: None,
+                changes_made: false,
+                diff: None,
+            })
+        }
     };
 
     // Create backup before modification
-    create_backup(file_path, &current_content)?;
+    create_backup(history, file_path, full_path, &current_content, encoding);
 
     let lines: Vec<&str> = current_content.lines().collect();
-    
+
     // Insert at specified line (1-based indexing)
     if insert_line == 0 || insert_line > lines.len() + 1 {
         return Ok(TextEditorResult {
             success: false,
-            message: format!("Invalid line number: {}. File has {} lines.", insert_line, lines.len()),
+            message: format!(
+                "Invalid line number: {}. File has {} lines.",
+                insert_line,
+                lines.len()
+            ),
             file_content: Some(current_content),
             changes_made: false,
+            diff: None,
         });
     }
 
-    // Convert to 0-based indexing
-    let insert_index = insert_line - 1;
-    
-    // Insert the new content
-    let mut new_lines = Vec::new();
-    new_lines.extend_from_slice(&lines[..insert_index]);
-    new_lines.push(new_str);
-    new_lines.extend_from_slice(&lines[insert_index..]);
-    
-    let new_content = new_lines.join("\n");
+    let new_content = insert_at_line(&current_content, insert_line, new_str);
 
     // Write modified content
-    match fs::write(file_path, &new_content) {
+    match text_file::write(full_path, &new_content, encoding) {
         Ok(_) => Ok(TextEditorResult {
             success: true,
-            message: format!("Successfully inserted text at line {} in {}", insert_line, file_path),
-            file_content: Some(new_content),
+            message: format!(
+                "Successfully inserted text at line {} in {}",
+                insert_line, file_path
+            ),
+            file_content: None,
             changes_made: true,
+            diff: Some(FileDiff::new(
+                file_path,
+                Some(&current_content),
+                &new_content,
+            )),
         }),
         Err(e) => Ok(TextEditorResult {
             success: false,
             message: format!("Failed to write to file {}: {}", file_path, e),
             file_content: None,
             changes_made: false,
-        })
+            diff: None,
+        }),
     }
 }
 
-fn handle_undo_command(file_path: &str, model_version: &str) -> Result<TextEditorResult, Box<dyn std::error::Error>> {
+// Inserts `new_str` before the given 1-based line; the caller has validated the line number.
+// The file keeps its line endings (CRLF stays CRLF) and whether it ends with a newline.
+fn insert_at_line(content: &str, insert_line: usize, new_str: &str) -> String {
+    let ending = if content.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut lines: Vec<String> = content.split_inclusive('\n').map(str::to_string).collect();
+    let mut inserted = new_str.replace("\r\n", "\n").replace('\n', ending);
+
+    // Convert to 0-based indexing
+    let insert_index = insert_line - 1;
+    let appended = insert_index == lines.len();
+    match lines.last_mut() {
+        // Appended after a last line without a newline: that line gets one, the new last line doesn't
+        Some(last) if appended && !last.ends_with('\n') => last.push_str(ending),
+        _ => inserted.push_str(ending),
+    }
+    lines.insert(insert_index, inserted);
+    lines.concat()
+}
+
+/// Returns true for commands that write to disk.
+pub fn is_mutating_command(input_params: &Value) -> bool {
+    matches!(
+        input_params.get("command").and_then(|v| v.as_str()),
+        Some("str_replace") | Some("create") | Some("insert") | Some("undo_edit")
+    )
+}
+
+/// Computes the diff a mutating command would produce, without writing anything.
+///
+/// Returns `None` for read-only commands, and for invalid calls, which fail with their own
+/// error message once executed.
+pub fn preview_change(
+    input_params: &Value,
+    max_read_bytes: u64,
+    base_dir: &Path,
+    history: &EditHistory,
+) -> Option<FileDiff> {
+    let param = |name: &str| {
+        input_params
+            .get(name)
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+    };
+    let file_path = param("path");
+    if file_path.is_empty() {
+        return None;
+    }
+    let full_path = &base_dir.join(file_path);
+
+    match param("command") {
+        "str_replace" => {
+            let current_content = text_file::read_text(full_path, max_read_bytes).ok()?.text;
+            let old_str = param("old_str");
+            if old_str.is_empty() || !current_content.contains(old_str) {
+                return None;
+            }
+            let new_content = current_content.replace(old_str, param("new_str"));
+            Some(FileDiff::new(
+                file_path,
+                Some(&current_content),
+                &new_content,
+            ))
+        }
+        "create" => {
+            if full_path.exists() {
+                return None;
+            }
+            Some(FileDiff::new(file_path, None, param("file_text")))
+        }
+        "insert" => {
+            let current_content = text_file::read_text(full_path, max_read_bytes).ok()?.text;
+            let insert_line = input_params
+                .get("insert_line")
+                .and_then(|v| v.as_u64())
+                .unwrap_or(0) as usize;
+            let lines: Vec<&str> = current_content.lines().collect();
+            if insert_line == 0 || insert_line > lines.len() + 1 {
+                return None;
+            }
+            let new_content = insert_at_line(&current_content, insert_line, param("new_str"));
+            Some(FileDiff::new(
+                file_path,
+                Some(&current_content),
+                &new_content,
+            ))
+        }
+        "undo_edit" => {
+            let backup = history.backups.get(file_path)?;
+            let current_content = text_file::read_text(&backup.file_path, u64::MAX)
+                .ok()
+                .map(|file| file.text);
+            Some(FileDiff::new(
+                file_path,
+                current_content.as_deref(),
+                &backup.original_content,
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn handle_undo_command(
+    file_path: &str,
+    model_version: &str,
+    history: &mut EditHistory,
+) -> Result<TextEditorResult, Box<dyn std::error::Error>> {
     // Check if it's a Claude 4 model (claude-sonnet-4 or claude-4)
     if model_version.contains("claude-sonnet-4") || model_version.contains("claude-4") {
         return Ok(TextEditorResult {
@@ -267,6 +509,7 @@ fn handle_undo_command(file_path: &str, model_version: &str) -> Result<TextEdito
             message: "undo_edit command is not supported in Claude 4 models. The text_editor_20250429 version removes this functionality.".to_string(),
             file_content: None,
             changes_made: false,
+            diff: None,
         });
     }
 
@@ -276,64 +519,196 @@ fn handle_undo_command(file_path: &str, model_version: &str) -> Result<TextEdito
             message: "File path is required for undo_edit command".to_string(),
             file_content: None,
             changes_made: false,
+            diff: None,
         });
     }
 
-    let mut backup_storage = get_backup_storage();
-    if backup_storage.is_none() {
-        *backup_storage = Some(HashMap::new());
+    Ok(restore_backup(history, file_path))
+}
+
+/// Reverts the most recent edit made by the text editor tool, whichever file it touched.
+pub fn undo_last_edit(history: &mut EditHistory) -> TextEditorResult {
+    match history.last_edited().map(str::to_string) {
+        Some(file_path) => restore_backup(history, &file_path),
+        None => TextEditorResult {
+            success: false,
+            message: "No edits to undo".to_string(),
+            file_content: None,
+            changes_made: false,
+            diff: None,
+        },
     }
-    let backup_map = backup_storage.as_ref().unwrap();
-    
-    match backup_map.get(file_path) {
+}
+
+fn restore_backup(history: &mut EditHistory, file_path: &str) -> TextEditorResult {
+    match history.backups.get(file_path) {
         Some(backup) => {
             // Clone the data we need before removing from storage
             let restored_content = backup.original_content.clone();
             let backup_timestamp = backup.timestamp;
-            
+            let current_content = text_file::read_text(&backup.file_path, u64::MAX)
+                .ok()
+                .map(|file| file.text);
+
             // Restore from backup
-            match fs::write(file_path, &restored_content) {
+            match text_file::write(&backup.file_path, &restored_content, backup.encoding) {
                 Ok(_) => {
                     // Remove the backup after successful restore
-                    backup_storage.as_mut().unwrap().remove(file_path);
-                    
-                    Ok(TextEditorResult {
+                    history.backups.remove(file_path);
+
+                    TextEditorResult {
                         success: true,
-                        message: format!("Successfully restored {} from backup created at {}", 
-                                       file_path, backup_timestamp.format("%Y-%m-%d %H:%M:%S UTC")),
-                        file_content: Some(restored_content),
+                        message: format!(
+                            "Successfully restored {} from backup created at {}",
+                            file_path,
+                            backup_timestamp.format("%Y-%m-%d %H:%M:%S UTC")
+                        ),
+                        file_content: None,
                         changes_made: true,
-                    })
-                },
-                Err(e) => Ok(TextEditorResult {
+                        diff: Some(FileDiff::new(
+                            file_path,
+                            current_content.as_deref(),
+                            &restored_content,
+                        )),
+                    }
+                }
+                Err(e) => TextEditorResult {
                     success: false,
                     message: format!("Failed to restore file {}: {}", file_path, e),
                     file_content: None,
                     changes_made: false,
-                })
+                    diff: None,
+                },
             }
-        },
-        None => Ok(TextEditorResult {
+        }
+        None => TextEditorResult {
             success: false,
             message: format!("No backup found for file: {}", file_path),
             file_content: None,
             changes_made: false,
-        })
+            diff: None,
+        },
     }
 }
 
-fn create_backup(file_path: &str, content: &str) -> Result<(), Box<dyn std::error::Error>> {
+fn create_backup(
+    history: &mut EditHistory,
+    file_path: &str,
+    full_path: &Path,
+    content: &str,
+    encoding: TextEncoding,
+) {
     let backup = FileBackup {
         original_content: content.to_string(),
+        encoding,
         timestamp: Utc::now(),
-        file_path: PathBuf::from(file_path),
+        file_path: full_path.to_path_buf(),
     };
-    
-    let mut backup_storage = get_backup_storage();
-    if backup_storage.is_none() {
-        *backup_storage = Some(HashMap::new());
+    history.backups.insert(file_path.to_string(), backup);
+    history.record_original(file_path, Some(content));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_insert_keeps_line_endings() {
+        assert_eq!(
+            insert_at_line("a\r\nb\r\n", 2, "x\ny"),
+            "a\r\nx\r\ny\r\nb\r\n"
+        );
+        assert_eq!(insert_at_line("a\nb\n", 3, "c"), "a\nb\nc\n");
+        assert_eq!(insert_at_line("a\nb", 3, "c"), "a\nb\nc");
+        assert_eq!(insert_at_line("a\nb", 1, "c"), "c\na\nb");
+        assert_eq!(insert_at_line("", 1, "c"), "c\n");
+    }
+
+    #[test]
+    fn test_mutating_commands_return_diff_instead_of_content() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        let path_str = path.to_str().unwrap();
+        let mut history = EditHistory::new();
+
+        let created = handle_text_editor_tool(
+            &json!({"command": "create", "path": path_str, "file_text": "one\ntwo\n"}),
+            "claude-sonnet-4",
+            u64::MAX,
+            dir.path(),
+            &mut history,
+        )
+        .unwrap();
+        assert!(created.success);
+        assert!(created.file_content.is_none());
+        assert!(created.diff.unwrap().created);
+
+        let replaced = handle_text_editor_tool(&json!({"command": "str_replace", "path": path_str, "old_str": "two", "new_str": "three"}), "claude-sonnet-4", u64::MAX, dir.path(), &mut history).unwrap();
+        assert!(replaced.file_content.is_none());
+        let diff = replaced.diff.unwrap();
+        assert_eq!((diff.added, diff.removed), (1, 1));
+        assert!(diff.unified.contains("+three"));
+
+        let viewed = handle_text_editor_tool(
+            &json!({"command": "view", "path": path_str}),
+            "claude-sonnet-4",
+            u64::MAX,
+            dir.path(),
+            &mut history,
+        )
+        .unwrap();
+        assert!(viewed.diff.is_none());
+        assert_eq!(viewed.file_content.as_deref(), Some("one\nthree\n"));
     }
-    backup_storage.as_mut().unwrap().insert(file_path.to_string(), backup);
-    
-    Ok(())
-}
\ No newline at end of file
+
+    #[test]
+    fn test_edits_keep_the_file_encoding() {
+        let dir = tempdir().unwrap();
+        let latin = dir.path().join("legacy.txt");
+        fs::write(&latin, b"caf\xe9 = 1\n").unwrap();
+        let path_str = latin.to_str().unwrap();
+        let mut history = EditHistory::new();
+
+        let replaced = handle_text_editor_tool(
+            &json!({"command": "str_replace", "path": path_str, "old_str": "1", "new_str": "2"}),
+            "claude-3-7",
+            u64::MAX,
+            dir.path(),
+            &mut history,
+        )
+        .unwrap();
+        assert!(replaced.success);
+        assert_eq!(fs::read(&latin).unwrap(), b"caf\xe9 = 2\n");
+        assert!(restore_backup(&mut history, path_str).success);
+        assert_eq!(fs::read(&latin).unwrap(), b"caf\xe9 = 1\n");
+
+        let utf16 = dir.path().join("wide.txt");
+        fs::write(&utf16, b"\xff\xfea\x00\n\x00").unwrap();
+        let inserted = handle_text_editor_tool(&json!({"command": "insert", "path": utf16.to_str().unwrap(), "insert_line": 1, "new_str": "b"}), "claude-3-7", u64::MAX, dir.path(), &mut history).unwrap();
+        assert!(inserted.success);
+        assert_eq!(fs::read(&utf16).unwrap(), b"\xff\xfeb\x00\n\x00a\x00\n\x00");
+
+        let binary = dir.path().join("logo.png");
+        fs::write(&binary, b"\x89PNG\r\n\x1a\n\x00").unwrap();
+        let viewed = handle_text_editor_tool(
+            &json!({"command": "view", "path": binary.to_str().unwrap()}),
+            "claude-3-7",
+            u64::MAX,
+            dir.path(),
+            &mut history,
+        )
+        .unwrap();
+        assert!(!viewed.success && viewed.message.contains("PNG image"));
+        let too_big = handle_text_editor_tool(
+            &json!({"command": "view", "path": path_str}),
+            "claude-3-7",
+            4,
+            dir.path(),
+            &mut history,
+        )
+        .unwrap();
+        assert!(too_big.message.contains("max_read_bytes"));
+    }
+}
@@ -0,0 +1,765 @@
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Position, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph};
+use ratatui::Frame;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::sync::oneshot;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::agent::{Agent, AgentEvent, ApprovalFuture, Frontend};
+use crate::commands::CommandOutcome;
+use crate::credentials;
+use crate::diff::{classify_line, DiffLineKind};
+use crate::markdown;
+use crate::permissions::{ApprovalDecision, ApprovalRequest, PermissionMode};
+use crate::prompt::complete_input;
+use crate::usage::TokenUsage;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const PAGE_SCROLL: usize = 10;
+const INITIAL_CONVERSATION_WIDTH: usize = 80;
+
+/// Sent from the UI thread to the agent.
+enum UiMessage {
+    Input(String),
+    /// Cancel the turn in progress
+    Interrupt,
+    Quit,
+}
+
+/// Sent from the agent to the UI thread.
+enum UiEvent {
+    Agent(AgentEvent),
+    /// The agent is blocked until a decision is sent back on the channel
+    Approval(ApprovalRequest, oneshot::Sender<ApprovalDecision>),
+    TurnFinished,
+    Exit,
+}
+
+/// Frontend that forwards agent events to the UI thread.
+struct ChannelFrontend {
+    sender: Sender<UiEvent>,
+}
+
+impl Frontend for ChannelFrontend {
+    fn emit(&mut self, event: AgentEvent) {
+        // The UI thread may already be gone if the user quit mid-turn
+        let _ = self.sender.send(UiEvent::Agent(event));
+    }
+
+    fn request_approval<'a>(&'a mut self, request: &'a ApprovalRequest) -> ApprovalFuture<'a> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let sent = self
+            .sender
+            .send(UiEvent::Approval(request.clone(), reply_sender))
+            .is_ok();
+        Box::pin(async move {
+            let closed = || ApprovalDecision::Reject("the approval prompt was closed".to_string());
+            if !sent {
+                return closed();
+            }
+            reply_receiver.await.unwrap_or_else(|_| closed())
+        })
+    }
+}
+
+/// Runs the agent behind a full-screen terminal UI until the user quits.
+///
+/// The UI draws and reads keys on its own thread while turns run on the async runtime,
+/// so the screen stays responsive during long requests and tool calls.
+pub async fn run(mut agent: Agent) -> Result<(), Box<dyn std::error::Error>> {
+    let (input_sender, mut input_receiver) = unbounded_channel();
+    let (event_sender, event_receiver) = mpsc::channel();
+    let working_dir = agent.working_dir.display().to_string();
+    let ui_thread = std::thread::spawn(move || run_ui(event_receiver, input_sender, working_dir));
+
+    let mut frontend = ChannelFrontend {
+        sender: event_sender,
+    };
+    frontend.emit(agent.status());
+
+    while let Some(message) = input_receiver.recv().await {
+        let line = match message {
+            UiMessage::Input(line) => line,
+            // A late interrupt for a turn that already finished
+            UiMessage::Interrupt => continue,
+            UiMessage::Quit => break,
+        };
+
+        // Keep listening to the UI during the turn so interrupting or quitting doesn't wait for the model.
+        // Resolves to true for an interrupt and false for quit.
+        let stop_requested = async {
+            loop {
+                match input_receiver.recv().await {
+                    Some(UiMessage::Interrupt) => return true,
+                    Some(UiMessage::Quit) | None => return false,
+                    Some(UiMessage::Input(_)) => {}
+                }
+            }
+        };
+        let result = tokio::select! {
+            result = agent.handle_input(&line, &mut frontend) => Some(result),
+            interrupted = stop_requested => if interrupted { None } else { break },
+        };
+
+        match result {
+            None => agent.interrupt(&mut frontend),
+            Some(result) => match result {
+                Ok(CommandOutcome::Exit) => break,
+                Ok(CommandOutcome::Continue) => {}
+                Err(e) => frontend.emit(AgentEvent::Notice(format!(
+                    "Error: {}",
+                    credentials::redact(&e.to_string())
+                ))),
+            },
+        }
+        let _ = frontend.sender.send(UiEvent::TurnFinished);
+    }
+
+    let _ = frontend.sender.send(UiEvent::Exit);
+    match ui_thread.join() {
+        Ok(result) => result?,
+        Err(_) => return Err("terminal UI thread panicked".into()),
+    }
+    Ok(())
+}
+
+fn run_ui(
+    events: Receiver<UiEvent>,
+    input: UnboundedSender<UiMessage>,
+    working_dir: String,
+) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let mut app = App::new(working_dir);
+
+    let result = loop {
+        match drain_events(&events, &mut app) {
+            Ok(true) => {}
+            Ok(false) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+        if let Err(e) = terminal.draw(|frame| app.draw(frame)) {
+            break Err(e);
+        }
+
+        match event::poll(POLL_INTERVAL).and_then(|ready| {
+            if ready {
+                event::read().map(Some)
+            } else {
+                Ok(None)
+            }
+        }) {
+            Ok(Some(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                if let Some(message) = app.handle_key(key) {
+                    let quit = matches!(message, UiMessage::Quit);
+                    let _ = input.send(message);
+                    if quit {
+                        break Ok(());
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(e) => break Err(e),
+        }
+    };
+
+    ratatui::restore();
+    result
+}
+
+/// Applies pending agent events. Returns `Ok(false)` once the agent has finished.
+fn drain_events(events: &Receiver<UiEvent>, app: &mut App) -> io::Result<bool> {
+    loop {
+        match events.try_recv() {
+            Ok(UiEvent::Agent(event)) => app.apply(event),
+            Ok(UiEvent::Approval(request, reply)) => app.ask_approval(request, reply),
+            Ok(UiEvent::TurnFinished) => app.finish_turn(),
+            Ok(UiEvent::Exit) | Err(TryRecvError::Disconnected) => return Ok(false),
+            Err(TryRecvError::Empty) => return Ok(true),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ToolStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+struct ToolCallEntry {
+    id: String,
+    name: String,
+    detail: String,
+    status: ToolStatus,
+}
+
+struct PendingApproval {
+    reply: oneshot::Sender<ApprovalDecision>,
+    /// Set once the user chose to reject and is typing a reason
+    reason: Option<String>,
+}
+
+struct App {
+    /// Logical conversation lines; wrapped to the pane width when drawn
+    conversation: Vec<Line<'static>>,
+    /// Inner width of the conversation pane at the last draw, used to lay out markdown
+    conversation_width: usize,
+    tool_calls: Vec<ToolCallEntry>,
+    input: String,
+    /// How many wrapped lines the conversation is scrolled up from the bottom
+    scroll_back: usize,
+    busy: bool,
+    model: String,
+    usage: TokenUsage,
+    permission_mode: PermissionMode,
+    working_dir: String,
+    pending_approval: Option<PendingApproval>,
+}
+
+impl App {
+    fn new(working_dir: String) -> Self {
+        let mut app = Self {
+            conversation: Vec::new(),
+            conversation_width: INITIAL_CONVERSATION_WIDTH,
+            tool_calls: Vec::new(),
+            input: String::new(),
+            scroll_back: 0,
+            busy: false,
+            model: String::new(),
+            usage: TokenUsage::default(),
+            permission_mode: PermissionMode::default(),
+            working_dir,
+            pending_approval: None,
+        };
+        app.push_text(
+            "Type a message and press Enter. /help lists commands, Esc quits.",
+            Style::default().fg(Color::DarkGray),
+        );
+        app
+    }
+
+    fn push_text(&mut self, text: &str, style: Style) {
+        for line in text.lines() {
+            self.conversation
+                .push(Line::styled(line.to_string(), style));
+        }
+    }
+
+    fn push_blank(&mut self) {
+        self.conversation.push(Line::default());
+    }
+
+    fn apply(&mut self, event: AgentEvent) {
+        match event {
+            AgentEvent::AssistantText(text) => {
+                self.push_blank();
+                for line in markdown::render(&text, self.conversation_width) {
+                    self.conversation.push(to_tui_line(line));
+                }
+            }
+            AgentEvent::ToolCall { id, name, detail } => {
+                self.push_text(
+                    &format!("⚙ {} {}", name, detail),
+                    Style::default().fg(Color::DarkGray),
+                );
+                self.tool_calls.push(ToolCallEntry {
+                    id,
+                    name,
+                    detail,
+                    status: ToolStatus::Running,
+                });
+            }
+            AgentEvent::ToolResult { id, success, .. } => {
+                if let Some(entry) = self
+                    .tool_calls
+                    .iter_mut()
+                    .rev()
+                    .find(|entry| entry.id == id)
+                {
+                    entry.status = if success {
+                        ToolStatus::Succeeded
+                    } else {
+                        ToolStatus::Failed
+                    };
+                }
+            }
+            AgentEvent::FileDiff { path, diff } => {
+                self.push_text(
+                    &format!("── {} ──", path),
+                    Style::default().add_modifier(Modifier::BOLD),
+                );
+                for line in diff.lines() {
+                    self.conversation
+                        .push(Line::styled(line.to_string(), diff_line_style(line)));
+                }
+            }
+            AgentEvent::Notice(text) => self.push_text(&text, Style::default().fg(Color::Yellow)),
+            AgentEvent::Status {
+                model,
+                usage,
+                permission_mode,
+            } => {
+                self.model = model;
+                self.usage = usage;
+                self.permission_mode = permission_mode;
+            }
+        }
+    }
+
+    fn ask_approval(&mut self, request: ApprovalRequest, reply: oneshot::Sender<ApprovalDecision>) {
+        self.push_blank();
+        self.push_text(
+            &format!("⚠ {} wants to run: {}", request.tool_name, request.summary),
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        );
+        if let Some(diff) = &request.diff {
+            for line in diff.lines() {
+                self.conversation
+                    .push(Line::styled(line.to_string(), diff_line_style(line)));
+            }
+        }
+        self.scroll_back = 0;
+        self.pending_approval = Some(PendingApproval {
+            reply,
+            reason: None,
+        });
+    }
+
+    /// Keys while an approval is pending: y approves, a approves for the session, n asks for a reason.
+    fn handle_approval_key(&mut self, key: KeyEvent) {
+        let Some(pending) = self.pending_approval.as_mut() else {
+            return;
+        };
+        let decision = match (&mut pending.reason, key.code) {
+            (None, KeyCode::Char('y')) => Some(ApprovalDecision::Approve),
+            (None, KeyCode::Char('a')) => Some(ApprovalDecision::ApproveForSession),
+            (None, KeyCode::Char('n')) => {
+                pending.reason = Some(String::new());
+                None
+            }
+            (_, KeyCode::Esc) => Some(ApprovalDecision::Reject(String::new())),
+            (Some(reason), KeyCode::Enter) => {
+                Some(ApprovalDecision::Reject(reason.trim().to_string()))
+            }
+            (Some(reason), KeyCode::Backspace) => {
+                reason.pop();
+                None
+            }
+            (Some(reason), KeyCode::Char(c)) => {
+                reason.push(c);
+                None
+            }
+            _ => None,
+        };
+
+        if let Some(decision) = decision {
+            let text = match &decision {
+                ApprovalDecision::Approve => "✓ approved".to_string(),
+                ApprovalDecision::ApproveForSession => {
+                    "✓ approved for the rest of the session".to_string()
+                }
+                ApprovalDecision::Reject(reason) if reason.is_empty() => "✗ rejected".to_string(),
+                ApprovalDecision::Reject(reason) => format!("✗ rejected: {}", reason),
+            };
+            self.push_text(&text, Style::default().fg(Color::Yellow));
+            if let Some(pending) = self.pending_approval.take() {
+                let _ = pending.reply.send(decision);
+            }
+        }
+    }
+
+    fn finish_turn(&mut self) {
+        self.busy = false;
+        // Tools still marked running were abandoned by an interrupt
+        for entry in &mut self.tool_calls {
+            if entry.status == ToolStatus::Running {
+                entry.status = ToolStatus::Failed;
+            }
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Option<UiMessage> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        if self.pending_approval.is_some() && !ctrl {
+            self.handle_approval_key(key);
+            return None;
+        }
+        match key.code {
+            KeyCode::Esc => return Some(UiMessage::Quit),
+            // Ctrl-C cancels a running turn; pressed again once idle, it quits
+            KeyCode::Char('c') if ctrl && self.busy => {
+                if let Some(pending) = self.pending_approval.take() {
+                    let _ = pending.reply.send(ApprovalDecision::Reject(
+                        "interrupted by the user".to_string(),
+                    ));
+                }
+                return Some(UiMessage::Interrupt);
+            }
+            KeyCode::Char('c') | KeyCode::Char('d') if ctrl => return Some(UiMessage::Quit),
+            KeyCode::Enter => return self.submit(),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Tab => {
+                let (start, candidates) = complete_input(&self.input, Path::new(&self.working_dir));
+                if let Some(completion) = common_prefix(
+                    candidates
+                        .iter()
+                        .map(|candidate| candidate.replacement.as_str()),
+                ) {
+                    self.input.truncate(start);
+                    self.input.push_str(&completion);
+                }
+            }
+            KeyCode::Up => self.scroll_back += 1,
+            KeyCode::Down => self.scroll_back = self.scroll_back.saturating_sub(1),
+            KeyCode::PageUp => self.scroll_back += PAGE_SCROLL,
+            KeyCode::PageDown => self.scroll_back = self.scroll_back.saturating_sub(PAGE_SCROLL),
+            KeyCode::End => self.scroll_back = 0,
+            KeyCode::Char(c) if !ctrl => self.input.push(c),
+            _ => {}
+        }
+        None
+    }
+
+    fn submit(&mut self) -> Option<UiMessage> {
+        let line = self.input.trim().to_string();
+        if self.busy || line.is_empty() {
+            return None;
+        }
+        self.input.clear();
+        if line == "exit" {
+            return Some(UiMessage::Quit);
+        }
+        self.push_blank();
+        self.push_text(
+            &format!("you › {}", line),
+            Style::default()
+                .fg(Color::Cyan)
+                .add_modifier(Modifier::BOLD),
+        );
+        self.scroll_back = 0;
+        self.busy = true;
+        Some(UiMessage::Input(line))
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main_area, input_area, status_area] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [conversation_area, tools_area] =
+            Layout::horizontal([Constraint::Percentage(70), Constraint::Percentage(30)])
+                .areas(main_area);
+
+        self.draw_conversation(frame, conversation_area);
+        self.draw_tool_calls(frame, tools_area);
+        self.draw_input(frame, input_area);
+        self.draw_status(frame, status_area);
+    }
+
+    fn draw_conversation(&mut self, frame: &mut Frame, area: Rect) {
+        let width = area.width.saturating_sub(2) as usize;
+        let height = area.height.saturating_sub(2) as usize;
+        self.conversation_width = width.max(1);
+        let wrapped: Vec<Line> = self
+            .conversation
+            .iter()
+            .flat_map(|line| wrap(line, width))
+            .collect();
+
+        self.scroll_back = self.scroll_back.min(wrapped.len().saturating_sub(height));
+        let end = wrapped.len() - self.scroll_back;
+        let start = end.saturating_sub(height);
+        let title = if self.scroll_back > 0 {
+            format!(" Conversation (↑{}, End to follow) ", self.scroll_back)
+        } else {
+            " Conversation ".to_string()
+        };
+        let paragraph = Paragraph::new(wrapped[start..end].to_vec())
+            .block(Block::default().borders(Borders::ALL).title(title));
+        frame.render_widget(paragraph, area);
+    }
+
+    fn draw_tool_calls(&self, frame: &mut Frame, area: Rect) {
+        let height = area.height.saturating_sub(2) as usize;
+        let skip = self.tool_calls.len().saturating_sub(height);
+        let items: Vec<ListItem> = self.tool_calls[skip..]
+            .iter()
+            .map(|entry| {
+                let (symbol, color) = match entry.status {
+                    ToolStatus::Running => ("…", Color::Yellow),
+                    ToolStatus::Succeeded => ("✓", Color::Green),
+                    ToolStatus::Failed => ("✗", Color::Red),
+                };
+                ListItem::new(Line::styled(
+                    format!("{} {} {}", symbol, entry.name, entry.detail),
+                    Style::default().fg(color),
+                ))
+            })
+            .collect();
+        frame.render_widget(
+            List::new(items).block(Block::default().borders(Borders::ALL).title(" Tool calls ")),
+            area,
+        );
+    }
+
+    fn draw_input(&self, frame: &mut Frame, area: Rect) {
+        let (title, text) = match &self.pending_approval {
+            Some(PendingApproval {
+                reason: Some(reason),
+                ..
+            }) => (" Reason for rejecting (Enter to send) ", reason.as_str()),
+            Some(_) => (" Apply this change? y = yes, a = always, n = no ", ""),
+            None if self.busy => (
+                " Waiting for the model… (Ctrl-C to interrupt) ",
+                self.input.as_str(),
+            ),
+            None => (
+                " Message (Enter to send, Esc to quit) ",
+                self.input.as_str(),
+            ),
+        };
+        let visible_width = area.width.saturating_sub(3) as usize;
+        // The end of the input that fits, measured in columns like the conversation pane
+        let mut visible_columns = 0;
+        let start = text
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| {
+                visible_columns += c.width().unwrap_or(0);
+                visible_columns <= visible_width
+            })
+            .last()
+            .map_or(text.len(), |(index, _)| index);
+        let visible = &text[start..];
+        let cursor_x = area.x + 1 + visible.width() as u16;
+
+        frame.render_widget(
+            Paragraph::new(visible).block(Block::default().borders(Borders::ALL).title(title)),
+            area,
+        );
+        frame.set_cursor_position(Position::new(cursor_x, area.y + 1));
+    }
+
+    fn draw_status(&self, frame: &mut Frame, area: Rect) {
+        let status = format!(
+            " {} │ tokens in {} / out {} (${:.4}) │ edits: {} │ {}{}",
+            self.model,
+            self.usage.input_tokens,
+            self.usage.output_tokens,
+            self.usage.estimated_cost(&self.model),
+            self.permission_mode,
+            self.working_dir,
+            if self.busy { " │ working…" } else { "" },
+        );
+        frame.render_widget(
+            Paragraph::new(status).style(Style::default().bg(Color::Blue).fg(Color::White)),
+            area,
+        );
+    }
+}
+
+fn diff_line_style(line: &str) -> Style {
+    match classify_line(line) {
+        DiffLineKind::FileHeader => Style::default().add_modifier(Modifier::BOLD),
+        DiffLineKind::HunkHeader => Style::default().fg(Color::Cyan),
+        DiffLineKind::Added => Style::default().fg(Color::Green),
+        DiffLineKind::Removed => Style::default().fg(Color::Red),
+        DiffLineKind::Context => Style::default().fg(Color::DarkGray),
+    }
+}
+
+fn to_tui_line(line: markdown::StyledLine) -> Line<'static> {
+    let spans: Vec<Span> = line
+        .into_iter()
+        .map(|span| {
+            let mut style = Style::default();
+            if let Some((r, g, b)) = span.style.fg {
+                style = style.fg(Color::Rgb(r, g, b));
+            }
+            if span.style.bold {
+                style = style.add_modifier(Modifier::BOLD);
+            }
+            if span.style.italic {
+                style = style.add_modifier(Modifier::ITALIC);
+            }
+            if span.style.underline {
+                style = style.add_modifier(Modifier::UNDERLINED);
+            }
+            if span.style.dim {
+                style = style.add_modifier(Modifier::DIM);
+            }
+            Span::styled(span.text, style)
+        })
+        .collect();
+    Line::from(spans)
+}
+
+/// Longest common prefix of the completion candidates, or `None` when there are none.
+fn common_prefix<'a>(mut candidates: impl Iterator<Item = &'a str>) -> Option<String> {
+    let mut prefix = candidates.next()?.to_string();
+    for candidate in candidates {
+        let common = prefix
+            .char_indices()
+            .zip(candidate.chars())
+            .take_while(|((_, a), b)| a == b)
+            .count();
+        let end = prefix
+            .char_indices()
+            .nth(common)
+            .map_or(prefix.len(), |(index, _)| index);
+        prefix.truncate(end);
+    }
+    Some(prefix)
+}
+
+/// Splits a line into chunks of at most `width` characters, keeping span styles.
+fn wrap(line: &Line<'static>, width: usize) -> Vec<Line<'static>> {
+    if width == 0 || line.width() <= width {
+        return vec![line.clone()];
+    }
+    let mut lines = Vec::new();
+    let mut current: Vec<Span<'static>> = Vec::new();
+    let mut current_width = 0;
+    for span in &line.spans {
+        let mut chunk = String::new();
+        for c in span.content.chars() {
+            // Wide characters (CJK, emoji) take two columns and move to the next line whole
+            let char_width = c.width().unwrap_or(0);
+            if current_width > 0 && current_width + char_width > width {
+                if !chunk.is_empty() {
+                    current.push(Span::styled(std::mem::take(&mut chunk), span.style));
+                }
+                lines.push(Line::from(std::mem::take(&mut current)));
+                current_width = 0;
+            }
+            chunk.push(c);
+            current_width += char_width;
+        }
+        if !chunk.is_empty() {
+            current.push(Span::styled(chunk, span.style));
+        }
+    }
+    if !current.is_empty() {
+        lines.push(Line::from(current));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        let line = Line::from(vec![
+            Span::raw("abc"),
+            Span::styled("def", Style::default().fg(Color::Red)),
+        ]);
+        let wrapped = wrap(&line, 4);
+        assert_eq!(wrapped.len(), 2);
+        assert_eq!(wrapped[0].to_string(), "abcd");
+        assert_eq!(wrapped[1].to_string(), "ef");
+        assert_eq!(wrapped[1].spans[0].style.fg, Some(Color::Red));
+        assert_eq!(wrap(&Line::default(), 4).len(), 1);
+        let wide = wrap(&Line::from("日本語テキスト"), 5);
+        assert_eq!(
+            wide.iter().map(|line| line.to_string()).collect::<Vec<_>>(),
+            ["日本", "語テ", "キス", "ト"]
+        );
+        assert!(wide.iter().all(|line| line.width() <= 5));
+    }
+
+    #[test]
+    fn test_tool_result_updates_status() {
+        let mut app = App::new(String::new());
+        app.apply(AgentEvent::ToolCall {
+            id: "1".to_string(),
+            name: "read_file".to_string(),
+            detail: "a.rs".to_string(),
+        });
+        app.apply(AgentEvent::ToolResult {
+            id: "1".to_string(),
+            name: "read_file".to_string(),
+            success: false,
+            content: String::new(),
+        });
+        assert!(app.tool_calls[0].status == ToolStatus::Failed);
+    }
+
+    #[test]
+    fn test_ctrl_c_interrupts_then_quits() {
+        let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        let mut app = App::new(String::new());
+        app.input = "hello".to_string();
+        assert!(matches!(app.submit(), Some(UiMessage::Input(_))));
+        app.apply(AgentEvent::ToolCall {
+            id: "t1".to_string(),
+            name: "scan_directory".to_string(),
+            detail: String::new(),
+        });
+
+        assert!(matches!(app.handle_key(ctrl_c), Some(UiMessage::Interrupt)));
+        app.finish_turn();
+        assert!(app.tool_calls[0].status == ToolStatus::Failed);
+        assert!(matches!(app.handle_key(ctrl_c), Some(UiMessage::Quit)));
+    }
+
+    #[test]
+    fn test_common_prefix() {
+        assert_eq!(
+            common_prefix(["src/main.rs ", "src/mod.rs "].into_iter()),
+            Some("src/m".to_string())
+        );
+        assert_eq!(
+            common_prefix(["/help "].into_iter()),
+            Some("/help ".to_string())
+        );
+        assert_eq!(common_prefix(std::iter::empty()), None);
+    }
+
+    #[test]
+    fn test_approval_keys() {
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        let request = ApprovalRequest {
+            tool_name: "edit".to_string(),
+            summary: "create a.rs".to_string(),
+            diff: None,
+        };
+        let mut app = App::new(String::new());
+
+        let (reply, mut decisions) = oneshot::channel();
+        app.ask_approval(request.clone(), reply);
+        assert!(app.handle_key(key(KeyCode::Char('y'))).is_none());
+        assert_eq!(decisions.try_recv().unwrap(), ApprovalDecision::Approve);
+
+        let (reply, mut decisions) = oneshot::channel();
+        app.ask_approval(request, reply);
+        for code in [
+            KeyCode::Char('n'),
+            KeyCode::Char('n'),
+            KeyCode::Char('o'),
+            KeyCode::Enter,
+        ] {
+            app.handle_key(key(code));
+        }
+        assert_eq!(
+            decisions.try_recv().unwrap(),
+            ApprovalDecision::Reject("no".to_string())
+        );
+        assert!(app.pending_approval.is_none());
+    }
+}
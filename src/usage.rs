@@ -0,0 +1,40 @@
+use serde_json::Value;
+
+/// Running token totals for the current session, as reported by the API `usage` field.
+#[derive(Debug, Default, Clone)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub requests: u64,
+}
+
+impl TokenUsage {
+    /// Adds the `usage` block of a Messages API response to the totals.
+    pub fn record(&mut self, response_json: &Value) {
+        let usage = &response_json["usage"];
+        if usage.is_null() {
+            return;
+        }
+        self.input_tokens += usage["input_tokens"].as_u64().unwrap_or(0);
+        self.output_tokens += usage["output_tokens"].as_u64().unwrap_or(0);
+        self.requests += 1;
+    }
+
+    /// Estimated cost in USD for the given model, based on public per-million-token pricing.
+    pub fn estimated_cost(&self, model_name: &str) -> f64 {
+        let (input_price, output_price) = price_per_million_tokens(model_name);
+        (self.input_tokens as f64 * input_price + self.output_tokens as f64 * output_price)
+            / 1_000_000.0
+    }
+}
+
+fn price_per_million_tokens(model_name: &str) -> (f64, f64) {
+    if model_name.contains("opus") {
+        (15.0, 75.0)
+    } else if model_name.contains("haiku") {
+        (0.8, 4.0)
+    } else {
+        // Sonnet pricing, also used for unknown models
+        (3.0, 15.0)
+    }
+}
@@ -0,0 +1,191 @@
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use log::warn;
+
+use crate::path_patterns;
+
+/// The directories the file tools may use: the project root, plus any `allowed_directories`.
+///
+/// Paths are checked by where they really lead, so `..` components, absolute paths and
+/// symlinks pointing out of the project are all caught. Paths that don't exist yet (a file
+/// about to be created) are resolved through their nearest existing parent.
+pub struct Workspace {
+    root: PathBuf,
+    /// Canonical forms of the root and the extra directories, symlinks resolved
+    allowed: Vec<PathBuf>,
+}
+
+impl Workspace {
+    /// `extra` directories are relative to `root` unless absolute. Ones that don't exist are skipped.
+    pub fn new(root: &Path, extra: &[String]) -> Self {
+        let mut allowed = vec![root.canonicalize().unwrap_or_else(|_| root.to_path_buf())];
+        for directory in extra {
+            match root.join(directory).canonicalize() {
+                Ok(directory) => allowed.push(directory),
+                Err(e) => warn!("Skipping {} in allowed_directories: {}", directory, e),
+            }
+        }
+        Self {
+            root: root.to_path_buf(),
+            allowed,
+        }
+    }
+
+    /// The real location of `path` (relative to the root unless absolute), or an error when it
+    /// lies outside the workspace.
+    pub fn resolve(&self, path: &Path) -> io::Result<PathBuf> {
+        let joined = self.root.join(path);
+        // A broken link still "exists" here, so it is canonicalized (and refused) rather than written through
+        let existing = joined
+            .ancestors()
+            .find(|ancestor| ancestor.symlink_metadata().is_ok())
+            .unwrap_or(&joined);
+        let mut real = existing.canonicalize().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "{} is a broken symlink, so where it leads can't be checked",
+                    path.display()
+                ),
+            )
+        })?;
+        for component in joined
+            .strip_prefix(existing)
+            .unwrap_or(Path::new(""))
+            .components()
+        {
+            match component {
+                Component::ParentDir => {
+                    real.pop();
+                }
+                Component::Normal(name) => real.push(name),
+                _ => {}
+            }
+        }
+
+        if self
+            .allowed
+            .iter()
+            .any(|directory| real.starts_with(directory))
+        {
+            return Ok(real);
+        }
+        let how = if path_patterns::normalize(&joined)
+            .starts_with(path_patterns::normalize(&self.root))
+        {
+            "leads outside the project through a symlink"
+        } else {
+            "is outside the project"
+        };
+        let mut message = format!(
+            "{} {} ({}). The tools can only use files in the project",
+            path.display(),
+            how,
+            self.root.display()
+        );
+        if self.allowed.len() > 1 {
+            let extra: Vec<String> = self.allowed[1..]
+                .iter()
+                .map(|directory| directory.display().to_string())
+                .collect();
+            message.push_str(&format!(" and in {}", extra.join(", ")));
+        }
+        message.push_str(
+            "; the user can add directories to allowed_directories in their global configuration.",
+        );
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, message))
+    }
+
+    /// `real`, as returned by `resolve`, relative to the project root. Paths in the other allowed
+    /// directories stay absolute.
+    pub fn relative(&self, real: &Path) -> PathBuf {
+        path_patterns::relative_to(real, &self.allowed[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_resolve() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path().join("project");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(directory.path().join("shared")).unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+
+        let workspace = Workspace::new(&root, &[]);
+        let real_root = root.canonicalize().unwrap();
+        assert_eq!(
+            workspace.resolve(Path::new("src/main.rs")).unwrap(),
+            real_root.join("src/main.rs")
+        );
+        assert_eq!(
+            workspace.resolve(&root.join("src/./main.rs")).unwrap(),
+            real_root.join("src/main.rs")
+        );
+        // Files to be created resolve through their nearest existing directory
+        assert_eq!(
+            workspace.resolve(Path::new("new/dir/../file.rs")).unwrap(),
+            real_root.join("new/file.rs")
+        );
+        assert_eq!(
+            workspace.relative(&workspace.resolve(Path::new("src/../src/main.rs")).unwrap()),
+            Path::new("src/main.rs")
+        );
+
+        for escape in [
+            "../shared/notes.md",
+            "src/../../shared",
+            "/etc/passwd",
+            "new/../../x",
+        ] {
+            let error = workspace.resolve(Path::new(escape)).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+            assert!(
+                error.to_string().contains("is outside the project"),
+                "{}",
+                error
+            );
+        }
+
+        let widened = Workspace::new(&root, &["../shared".to_string(), "missing".to_string()]);
+        assert!(widened.resolve(Path::new("../shared/notes.md")).is_ok());
+        assert!(widened
+            .resolve(Path::new("/etc/passwd"))
+            .unwrap_err()
+            .to_string()
+            .contains("shared"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_escapes() {
+        use std::os::unix::fs::symlink;
+
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path().join("project");
+        let outside = directory.path().join("outside");
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        symlink(&outside, root.join("elsewhere")).unwrap();
+        symlink(outside.join("gone"), root.join("dangling")).unwrap();
+        symlink(&root, root.join("self")).unwrap();
+
+        let workspace = Workspace::new(&root, &[]);
+        assert!(workspace
+            .resolve(Path::new("elsewhere/new.rs"))
+            .unwrap_err()
+            .to_string()
+            .contains("through a symlink"));
+        assert!(workspace
+            .resolve(Path::new("dangling"))
+            .unwrap_err()
+            .to_string()
+            .contains("broken symlink"));
+        assert!(workspace.resolve(Path::new("self/file.rs")).is_ok());
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use log::{debug, warn};
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use rayon::prelude::*;
+
+use crate::ignore_rules::IgnoreRules;
+use crate::path_patterns::PathPatterns;
+use crate::scan_directory::{self, ListDirectory, Listed, ScanOptions};
+
+/// Changing one of these can change what is ignored anywhere below it
+const IGNORE_FILE_NAMES: &[&str] = &[".gitignore", ".simplecoderignore", "exclude"];
+
+/// A directory in the index, with its non-ignored entries sorted by name.
+#[derive(Debug, Default)]
+struct IndexNode {
+    entries: Vec<(Listed, Option<IndexNode>)>,
+}
+
+/// What the watcher has seen since the index was last brought up to date.
+#[derive(Default)]
+struct Changes {
+    /// Directories whose listing may have changed, relative to the root
+    directories: HashSet<PathBuf>,
+    /// Set when an ignore file changed or events were lost
+    rebuild: bool,
+}
+
+struct IndexState {
+    tree: Option<IndexNode>,
+    ignore: Arc<IgnoreRules>,
+    watcher: Option<RecommendedWatcher>,
+}
+
+/// An in-memory listing of the project, shared by every `scan_directory` call.
+///
+/// The first scan walks the project in parallel and starts a file system watcher; later scans
+/// re-read only the directories the watcher reported as changed. When the watcher can't be
+/// started (e.g. inotify limits), every scan walks the project again.
+pub struct WorkspaceIndex {
+    root: PathBuf,
+    patterns: PathPatterns,
+    state: Mutex<IndexState>,
+    changes: Arc<Mutex<Changes>>,
+}
+
+impl WorkspaceIndex {
+    pub fn new(root: &Path, patterns: &PathPatterns) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            patterns: patterns.clone(),
+            state: Mutex::new(IndexState {
+                tree: None,
+                ignore: Arc::new(IgnoreRules::new(root, patterns)),
+                watcher: None,
+            }),
+            changes: Arc::new(Mutex::new(Changes::default())),
+        }
+    }
+
+    /// The `ignore_patterns` the index was built with; a different set needs a new index.
+    pub fn patterns(&self) -> &PathPatterns {
+        &self.patterns
+    }
+
+    /// Scans like `scan_directory::scan_directory`, from the index.
+    pub fn scan(&self, options: &ScanOptions) -> io::Result<String> {
+        self.with_listing(|ignore, list| {
+            scan_directory::scan_with(&self.root, options, ignore, list)
+        })
+    }
+
+    /// Finds files like `scan_directory::find_files`, from the index.
+    pub fn find_files(&self, patterns: &PathPatterns) -> io::Result<Vec<PathBuf>> {
+        self.with_listing(|ignore, list| {
+            scan_directory::find_files(&self.root, patterns, ignore, list)
+        })
+    }
+
+    /// Brings the index up to date and runs `task` with a directory lister reading from it.
+    fn with_listing<T>(
+        &self,
+        task: impl FnOnce(&IgnoreRules, &ListDirectory) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.refresh(&mut state)?;
+        let tree = state.tree.as_ref().expect("refresh builds the tree");
+        let ignore = Arc::clone(&state.ignore);
+        task(&ignore, &|directory| match find(tree, directory) {
+            Some(node) => Ok(node
+                .entries
+                .iter()
+                .map(|(listed, _)| listed.clone())
+                .collect()),
+            // Only reachable through a followed symlink, which the index doesn't descend into
+            None => scan_directory::list_directory(&self.root, directory, &ignore),
+        })
+    }
+
+    /// Marks the directory containing `path` as changed, for edits made by the agent itself,
+    /// which the next scan may otherwise see before the watcher reports them.
+    pub fn invalidate(&self, path: &Path) {
+        let mut changes = self
+            .changes
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        record_change(&mut changes, &self.root, path);
+    }
+
+    /// Builds the tree, or applies the changes reported since the last scan.
+    fn refresh(&self, state: &mut IndexState) -> io::Result<()> {
+        let changes = std::mem::take(
+            &mut *self
+                .changes
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        );
+        if state.watcher.is_none() {
+            state.tree = None;
+            state.watcher = self.start_watcher();
+        }
+        if changes.rebuild {
+            state.ignore = Arc::new(IgnoreRules::new(&self.root, &self.patterns));
+            state.tree = None;
+        }
+
+        match &mut state.tree {
+            None => {
+                debug!("Indexing {}", self.root.display());
+                state.tree = Some(walk(&self.root, Path::new(""), &state.ignore)?);
+            }
+            Some(tree) => {
+                // Parents first, so a re-read parent's new subdirectories are walked only once
+                let mut directories: Vec<PathBuf> = changes.directories.into_iter().collect();
+                directories.sort_by_key(|directory| directory.components().count());
+                for directory in directories {
+                    refresh_directory(tree, &self.root, &directory, &state.ignore)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn start_watcher(&self) -> Option<RecommendedWatcher> {
+        let changes = Arc::clone(&self.changes);
+        let root = self.root.clone();
+        let handler = move |result: notify::Result<Event>| {
+            let mut changes = changes
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            match result {
+                Ok(event) if event.need_rescan() => changes.rebuild = true,
+                // Content changes don't change the listing
+                Ok(Event {
+                    kind:
+                        EventKind::Access(_)
+                        | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Metadata(_)),
+                    ..
+                }) => {}
+                Ok(event) => event
+                    .paths
+                    .iter()
+                    .for_each(|path| record_change(&mut changes, &root, path)),
+                Err(_) => changes.rebuild = true,
+            }
+        };
+
+        // Links may lead outside the project or back up the tree; scans don't follow them by default either
+        let config = notify::Config::default().with_follow_symlinks(false);
+        let mut watcher = match RecommendedWatcher::new(handler, config) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                warn!(
+                    "Could not watch {} for changes, scans will re-read it every time: {}",
+                    self.root.display(),
+                    e
+                );
+                return None;
+            }
+        };
+        if let Err(e) = watcher.watch(&self.root, RecursiveMode::Recursive) {
+            warn!(
+                "Could not watch {} for changes, scans will re-read it every time: {}",
+                self.root.display(),
+                e
+            );
+            return None;
+        }
+        Some(watcher)
+    }
+}
+
+/// Records that the directory containing `path` (an absolute path) changed.
+fn record_change(changes: &mut Changes, root: &Path, path: &Path) {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return;
+    };
+    if relative.file_name().is_some_and(|name| {
+        IGNORE_FILE_NAMES
+            .iter()
+            .any(|ignore_file| name == *ignore_file)
+    }) {
+        changes.rebuild = true;
+    }
+    changes
+        .directories
+        .insert(relative.parent().unwrap_or(Path::new("")).to_path_buf());
+}
+
+/// Lists `directory` and all directories below it, reading sibling directories in parallel.
+fn walk(root: &Path, directory: &Path, ignore: &IgnoreRules) -> io::Result<IndexNode> {
+    let listed = scan_directory::list_directory(root, directory, ignore)?;
+    let entries = listed
+        .into_par_iter()
+        .map(|listed| {
+            // A directory that disappears mid-walk is indexed as empty; the watcher will report it
+            let node = listed
+                .is_dir
+                .then(|| walk(root, &directory.join(&listed.name), ignore).unwrap_or_default());
+            (listed, node)
+        })
+        .collect();
+    Ok(IndexNode { entries })
+}
+
+fn find<'a>(tree: &'a IndexNode, directory: &Path) -> Option<&'a IndexNode> {
+    let mut node = tree;
+    for component in directory.components() {
+        let Component::Normal(name) = component else {
+            continue;
+        };
+        node = node
+            .entries
+            .iter()
+            .find(|(listed, _)| listed.name.as_str() == name)?
+            .1
+            .as_ref()?;
+    }
+    Some(node)
+}
+
+fn find_mut<'a>(tree: &'a mut IndexNode, directory: &Path) -> Option<&'a mut IndexNode> {
+    let mut node = tree;
+    for component in directory.components() {
+        let Component::Normal(name) = component else {
+            continue;
+        };
+        node = node
+            .entries
+            .iter_mut()
+            .find(|(listed, _)| listed.name.as_str() == name)?
+            .1
+            .as_mut()?;
+    }
+    Some(node)
+}
+
+/// Re-reads one directory, keeping the subtrees of directories that are still there and
+/// walking new ones. A directory missing from the index refreshes its nearest indexed parent.
+fn refresh_directory(
+    tree: &mut IndexNode,
+    root: &Path,
+    directory: &Path,
+    ignore: &IgnoreRules,
+) -> io::Result<()> {
+    let mut directory = directory.to_path_buf();
+    while find(tree, &directory).is_none() {
+        if !directory.pop() {
+            return Ok(());
+        }
+    }
+    let node = find_mut(tree, &directory).expect("found above");
+
+    let listed = match scan_directory::list_directory(root, &directory, ignore) {
+        Ok(listed) => listed,
+        // Removed; its parent's refresh drops it
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let mut previous = std::mem::take(&mut node.entries);
+    node.entries = listed
+        .into_iter()
+        .map(|listed| {
+            let kept = previous
+                .iter()
+                .position(|(old, _)| old.name == listed.name && old.is_dir == listed.is_dir)
+                .map(|index| previous.swap_remove(index).1);
+            let subtree = match kept {
+                Some(subtree) => subtree,
+                None => listed
+                    .is_dir
+                    .then(|| walk(root, &directory.join(&listed.name), ignore).unwrap_or_default()),
+            };
+            (listed, subtree)
+        })
+        .collect();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{Duration, Instant};
+
+    fn options() -> ScanOptions {
+        ScanOptions::default()
+    }
+
+    #[test]
+    fn test_index_matches_scan_and_refreshes() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path();
+        fs::create_dir_all(root.join("src/nested")).unwrap();
+        fs::write(root.join("src/main.rs"), "").unwrap();
+        fs::write(root.join("src/nested/mod.rs"), "").unwrap();
+        fs::write(root.join("notes.log"), "").unwrap();
+        fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+
+        let index = WorkspaceIndex::new(root, &PathPatterns::empty());
+        let ignore = IgnoreRules::new(root, &PathPatterns::empty());
+        assert_eq!(
+            index.scan(&options()).unwrap(),
+            scan_directory::scan_directory(root, &options(), &ignore).unwrap()
+        );
+
+        // Changes made by the agent are picked up without waiting for the watcher
+        fs::create_dir_all(root.join("src/parser")).unwrap();
+        fs::write(root.join("src/parser/lexer.rs"), "").unwrap();
+        fs::remove_file(root.join("src/main.rs")).unwrap();
+        index.invalidate(&root.join("src/parser"));
+        index.invalidate(&root.join("src/main.rs"));
+        let tree = index.scan(&options()).unwrap();
+        assert!(tree.contains("lexer.rs") && !tree.contains("main.rs"));
+
+        // Editing an ignore file rebuilds with the new rules
+        fs::write(root.join(".gitignore"), "").unwrap();
+        index.invalidate(&root.join(".gitignore"));
+        assert!(index.scan(&options()).unwrap().contains("notes.log"));
+
+        let subtree = ScanOptions {
+            path: "src/nested".into(),
+            ..options()
+        };
+        assert_eq!(index.scan(&subtree).unwrap(), "src/nested\n└── mod.rs\n");
+    }
+
+    /// Compares a fresh sequential walk (what every scan did before the index) with building
+    /// the index and with scanning a warm index. Run with
+    /// `cargo test --release bench_scan -- --ignored --nocapture`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_scan() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path();
+        for package in 0..40 {
+            for module in 0..25 {
+                let path = root.join(format!("packages/p{}/src/m{}", package, module));
+                fs::create_dir_all(&path).unwrap();
+                for file in 0..20 {
+                    fs::write(path.join(format!("f{}.rs", file)), "").unwrap();
+                }
+            }
+        }
+        let patterns = PathPatterns::empty();
+        let time = |label: &str, runs: u32, scan: &dyn Fn() -> String| {
+            let started = Instant::now();
+            for _ in 0..runs {
+                assert!(!scan().is_empty());
+            }
+            let average = started.elapsed() / runs;
+            println!("{:<32} {:>10.2?}", label, average);
+            average
+        };
+
+        println!("20,000 files in 1,000 directories, average per scan:");
+        let sequential = time("sequential walk (no index)", 5, &|| {
+            scan_directory::scan_directory(root, &options(), &IgnoreRules::new(root, &patterns))
+                .unwrap()
+        });
+        time("index: first scan (parallel)", 5, &|| {
+            WorkspaceIndex::new(root, &patterns)
+                .scan(&options())
+                .unwrap()
+        });
+        let index = WorkspaceIndex::new(root, &patterns);
+        index.scan(&options()).unwrap();
+        let warm = time("index: later scans", 20, &|| {
+            index.scan(&options()).unwrap()
+        });
+        index.invalidate(&root.join("packages/p3/src/m7/f1.rs"));
+        time("index: after one change", 1, &|| {
+            index.scan(&options()).unwrap()
+        });
+        assert!(warm < sequential + Duration::from_millis(5));
+    }
+}